@@ -177,6 +177,9 @@ impl GltfAssetLoader for SpaceAssetServer {
                         normal: [normals[shift], normals[shift + 1], normals[shift + 2]],
                         tangent: [tangent[shift], tangent[shift + 1], tangent[shift + 2]],
                         uv: [uv[uv_shift], uv[uv_shift + 1]],
+                        ao: 1.0,
+                        //models get baked light per instance, see Location::light
+                        light: [0.0; 3]
                     });
                 }
 
@@ -195,7 +198,9 @@ impl GltfAssetLoader for SpaceAssetServer {
                 let model = GMesh {
                     vertex : vert_buffer,
                     index : index_buf,
-                    index_count : indices.len() as u32
+                    index_count : indices.len() as u32,
+                    bounds : GMesh::bounds_of(&verts),
+                    pick : None
                 };
 
                 let normal = self.load_gltf_normal_texture(&base, p.material().normal_texture());
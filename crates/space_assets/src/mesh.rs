@@ -7,7 +7,7 @@ use bevy::reflect::TypeUuid;
 use nalgebra::*;
 use wgpu::util::DeviceExt;
 use wgpu::{BufferUsages, VertexFormat};
-use space_core::RenderBase;
+use space_core::{Aabb, Ray, RayHit, RenderBase};
 use space_core::bevy::prelude::{Component, Bundle};
 
 #[repr(C)]
@@ -16,7 +16,11 @@ pub struct GVertex {
     pub pos : [f32; 3],
     pub normal : [f32; 3],
     pub tangent : [f32; 3],
-    pub uv : [f32; 2]
+    pub uv : [f32; 2],
+    //baked ambient occlusion, 1.0 for fully open vertex
+    pub ao : f32,
+    //baked voxel light, added to ambient light
+    pub light : [f32; 3]
 }
 
 impl GVertex {
@@ -46,10 +50,20 @@ impl GVertex {
                     shader_location: 3,
                     format: wgpu::VertexFormat::Float32x2,
                 },
+                wgpu::VertexAttribute {
+                    offset: 4 * 3 * 3 + 4 * 2,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Float32,
+                },
+                wgpu::VertexAttribute {
+                    offset: 4 * 3 * 3 + 4 * 2 + 4,
+                    shader_location: 13,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
             ],
         },
             wgpu::VertexBufferLayout {
-                array_stride: 16 * 4 * 2,
+                array_stride: std::mem::size_of::<LocationInstant>() as wgpu::BufferAddress,
                 step_mode: wgpu::VertexStepMode::Instance,
                 attributes: &[
                     wgpu::VertexAttribute {
@@ -92,6 +106,11 @@ impl GVertex {
                         offset : 4 * 4 * 7,
                         shader_location: 11
                     },
+                    wgpu::VertexAttribute {
+                        format: wgpu::VertexFormat::Float32x3,
+                        offset : 4 * 4 * 8,
+                        shader_location: 14
+                    },
                 ]
             }
         ]
@@ -103,7 +122,61 @@ impl GVertex {
 pub struct GMesh {
     pub vertex : wgpu::Buffer,
     pub index : wgpu::Buffer,
-    pub index_count : u32
+    pub index_count : u32,
+    //local space bounds, used for culling
+    pub bounds : Aabb,
+    //cpu copy of triangles, only kept for meshes that are picked by rays
+    pub pick : Option<PickMesh>
+}
+
+//local space triangles, see Ray::intersect_mesh
+pub struct PickMesh {
+    pub positions : Vec<Point3<f32>>,
+    pub indices : Vec<u32>
+}
+
+impl GMesh {
+    pub fn bounds_of(vertices : &[GVertex]) -> Aabb {
+        Aabb::from_points(vertices.iter().map(|v| Point3::from(v.pos)))
+            .unwrap_or(Aabb::new(Point3::origin(), Point3::origin()))
+    }
+
+    pub fn from_vertices(device : &wgpu::Device, vertices : &[GVertex], indices : &[u32]) -> GMesh {
+        let vertex = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("generated vertex buffer"),
+            contents: bytemuck::cast_slice(vertices),
+            usage: wgpu::BufferUsages::VERTEX
+        });
+
+        let index = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("generated index buffer"),
+            contents: bytemuck::cast_slice(indices),
+            usage: wgpu::BufferUsages::INDEX
+        });
+
+        GMesh {
+            vertex,
+            index,
+            index_count : indices.len() as u32,
+            bounds : GMesh::bounds_of(vertices),
+            pick : None
+        }
+    }
+
+    pub fn with_pick(mut self, vertices : &[GVertex], indices : &[u32]) -> GMesh {
+        self.pick = Some(PickMesh {
+            positions : vertices.iter().map(|v| Point3::from(v.pos)).collect(),
+            indices : indices.to_vec()
+        });
+        self
+    }
+
+    //ray in mesh local space, none for meshes created without pick data
+    pub fn intersect(&self, ray : &Ray) -> Option<RayHit> {
+        let pick = self.pick.as_ref()?;
+        ray.intersect_aabb(&self.bounds)?;
+        ray.intersect_mesh(&pick.positions, &pick.indices)
+    }
 }
 
 
@@ -112,22 +185,35 @@ pub struct SubLocation {
     pub pos : Vector3<f32>,
     pub rotation : Vector3<f32>,
     pub scale : Vector3<f32>,
+    pub light : Vector3<f32>
+}
+
+fn model_matrix(pos : &Vector3<f32>, rotation : &Vector3<f32>, scale : &Vector3<f32>) -> Matrix4<f32> {
+    let tr : Matrix4<f32> = Matrix::new_translation(pos);
+    let scale : Matrix4<f32> = Matrix::new_nonuniform_scaling(scale);
+
+    let rot = Rotation::from_euler_angles(rotation.x, rotation.y, rotation.z);
+    let rot_mat : Matrix4<f32> = rot.into();
+
+    tr * rot_mat * scale
 }
 
 impl SubLocation {
-    pub fn get_raw(&self) -> LocationInstant {
-        let tr : Matrix4<f32> = Matrix::new_translation(&self.pos);
-        let scale : Matrix4<f32> = Matrix::new_nonuniform_scaling(&self.scale);
+    pub fn model_matrix(&self) -> Matrix4<f32> {
+        model_matrix(&self.pos, &self.rotation, &self.scale)
+    }
 
+    pub fn get_raw(&self) -> LocationInstant {
         let rot = Rotation::from_euler_angles(self.rotation.x, self.rotation.y, self.rotation.z);
         let rot_mat : Matrix4<f32> = rot.into();
 
-        let res = tr * rot_mat * scale;
+        let res = self.model_matrix();
         let normal = rot_mat * Matrix4::identity();
 
         let inst = LocationInstant {
             model : res.into(),
-            normal : normal.into()
+            normal : normal.into(),
+            light : self.light.push(0.0).into()
         };
         inst
     }
@@ -143,13 +229,22 @@ impl LocationInstancing {
     pub fn recreate_buffer(&mut self) {
 
     }
+
+    //world bounds of every instance of mesh with given local bounds
+    pub fn bounds(&self, local : &Aabb) -> Option<Aabb> {
+        self.locs.iter()
+            .map(|loc| local.transformed(&loc.model_matrix()))
+            .reduce(|a, b| a.union(&b))
+    }
 }
 
 #[repr(C)]
 #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable, Default)]
 pub struct LocationInstant {
     model : [[f32; 4]; 4],
-    normal : [[f32; 4]; 4]
+    normal : [[f32; 4]; 4],
+    //w is padding
+    light : [f32; 4]
 }
 
 #[derive(Component)]
@@ -157,6 +252,8 @@ pub struct Location {
     pub pos : Vector3<f32>,
     pub rotation : Vector3<f32>,
     pub scale : Vector3<f32>,
+    //baked light around instance, added to vertex light of its mesh
+    pub light : Vector3<f32>,
     pub buffer : Arc<wgpu::Buffer>
 }
 
@@ -168,9 +265,10 @@ impl Location {
             pos : self.pos.clone(),
             rotation : self.rotation.clone(),
             scale : self.scale.clone(),
+            light : self.light,
             buffer : Arc::new(device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: None,
-                contents: &[0u8; 16 * 4 * 2],
+                contents: &[0u8; std::mem::size_of::<LocationInstant>()],
                 usage: BufferUsages::MAP_WRITE | BufferUsages::VERTEX
             }))
         }
@@ -181,27 +279,30 @@ impl Location {
             pos : [0.0, 0.0, 0.0].into(),
             rotation : [0.0, 0.0, 0.0].into(),
             scale : [1.0, 1.0, 1.0].into(),
+            light : Vector3::zeros(),
             buffer : Arc::new(device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: None,
-                contents: &[0u8; 16 * 4 * 2],
+                contents: &[0u8; std::mem::size_of::<LocationInstant>()],
                 usage: BufferUsages::MAP_WRITE | BufferUsages::VERTEX
             }))
         }
     }
 
-    pub fn get_bytes(&self) -> Vec<u8> {
-        let tr : Matrix4<f32> = Matrix::new_translation(&self.pos);
-        let scale : Matrix4<f32> = Matrix::new_nonuniform_scaling(&self.scale);
+    pub fn model_matrix(&self) -> Matrix4<f32> {
+        model_matrix(&self.pos, &self.rotation, &self.scale)
+    }
 
+    pub fn get_bytes(&self) -> Vec<u8> {
         let rot = Rotation::from_euler_angles(self.rotation.x, self.rotation.y, self.rotation.z);
         let rot_mat : Matrix4<f32> = rot.into();
 
-        let res = tr * rot_mat * scale;
+        let res = self.model_matrix();
         let normal = rot_mat * Matrix4::identity();
 
         let inst = LocationInstant {
             model : res.into(),
-            normal : normal.into()
+            normal : normal.into(),
+            light : self.light.push(0.0).into()
         };
 
         bytemuck::cast_slice(&[inst]).iter().map(|b| *b).collect()
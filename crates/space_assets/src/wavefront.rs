@@ -30,8 +30,11 @@ pub fn wgpu_load_gray_obj(
                 normal: [mesh.normals[shift], mesh.normals[shift + 1],mesh.normals[shift + 2]],
                 tangent: [1.0, 0.0, 0.0],
                 uv: [mesh.texcoords[uv_shift], mesh.texcoords[uv_shift + 1]],
+                ao: 1.0,
+                light: [0.0; 3]
             }
         }).collect();
+        let bounds = GMesh::bounds_of(&vertex);
 
         let vertex = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("obj vertex"),
@@ -47,7 +50,7 @@ pub fn wgpu_load_gray_obj(
 
 
         scene.push(
-            meshs.add(GMesh { vertex, index, index_count: mesh.indices.len() as u32 })
+            meshs.add(GMesh { vertex, index, index_count: mesh.indices.len() as u32, bounds, pick : None })
         );
     }
 
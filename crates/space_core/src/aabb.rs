@@ -0,0 +1,87 @@
+use crate::{Pos3, Vec3};
+
+//axis aligned box in world units
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min : Pos3,
+    pub max : Pos3
+}
+
+impl Aabb {
+    pub fn new(min : Pos3, max : Pos3) -> Self {
+        Self {
+            min,
+            max
+        }
+    }
+
+    pub fn from_center(center : &Pos3, half_size : &Vec3) -> Self {
+        Self {
+            min : center - half_size,
+            max : center + half_size
+        }
+    }
+
+    pub fn center(&self) -> Pos3 {
+        nalgebra::center(&self.min, &self.max)
+    }
+
+    pub fn half_size(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    pub fn translated(&self, offset : &Vec3) -> Aabb {
+        Aabb {
+            min : self.min + offset,
+            max : self.max + offset
+        }
+    }
+
+    pub fn union(&self, other : &Aabb) -> Aabb {
+        Aabb {
+            min : self.min.inf(&other.min),
+            max : self.max.sup(&other.max)
+        }
+    }
+
+    //touching boxes do not intersect
+    pub fn intersects(&self, other : &Aabb) -> bool {
+        (0..3).all(|i| self.min[i] < other.max[i] && other.min[i] < self.max[i])
+    }
+
+    pub fn contains(&self, pos : &Pos3) -> bool {
+        (0..3).all(|i| pos[i] >= self.min[i] && pos[i] <= self.max[i])
+    }
+}
+
+impl Aabb {
+    pub fn from_points<I>(points : I) -> Option<Aabb>
+        where I : IntoIterator<Item = Pos3> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        Some(points.fold(Aabb::new(first, first), |aabb, p| Aabb {
+            min : aabb.min.inf(&p),
+            max : aabb.max.sup(&p)
+        }))
+    }
+
+    pub fn corners(&self) -> [Pos3; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Pos3::new(a.x, a.y, a.z), Pos3::new(b.x, a.y, a.z),
+            Pos3::new(a.x, b.y, a.z), Pos3::new(b.x, b.y, a.z),
+            Pos3::new(a.x, a.y, b.z), Pos3::new(b.x, a.y, b.z),
+            Pos3::new(a.x, b.y, b.z), Pos3::new(b.x, b.y, b.z)
+        ]
+    }
+
+    //box around transformed corners, a bit larger than the rotated box itself
+    pub fn transformed(&self, mat : &nalgebra::Matrix4<f32>) -> Aabb {
+        Aabb::from_points(self.corners().iter().map(|p| mat.transform_point(p))).unwrap_or(*self)
+    }
+
+    pub fn intersects_sphere(&self, center : &Pos3, radius : f32) -> bool {
+        let closest = center.sup(&self.min).inf(&self.max);
+        (closest - center).norm_squared() <= radius * radius
+    }
+}
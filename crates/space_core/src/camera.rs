@@ -9,14 +9,30 @@ pub struct CameraUniform {
     pub pos : nalgebra::Vector3<f32>
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Projection {
+    Perspective,
+    //parallel rays, size of view is set by ortho_height
+    Orthographic
+}
+
 #[derive(Clone, Resource)]
 pub struct Camera {
     pub pos : nalgebra::Point3<f32>,
     pub frw : nalgebra::Vector3<f32>,
-    pub up : nalgebra::Vector3<f32>
+    pub up : nalgebra::Vector3<f32>,
+    //vertical field of view in radians
+    pub fov : f32,
+    pub near : f32,
+    pub far : f32,
+    //width / height of screen, updated on window resize
+    pub aspect : f32,
+    pub projection : Projection,
+    //world units visible vertically in orthographic mode
+    pub ortho_height : f32
 }
 
-#[derive(Default)]
+#[derive(Default, Clone, Copy, Debug)]
 pub struct Ray {
     pub pos : nalgebra::Point3<f32>,
     pub dir : nalgebra::Vector3<f32>
@@ -47,67 +63,95 @@ impl Camera {
         screen_pos : nalgebra::Point2<f32>,
         screen_size : nalgebra::Point2<f32>
     ) -> Ray {
-        let mut res = Ray::default();
-        let uniform_screen_pos = nalgebra::Vector2::<f32>::new(
-            screen_pos.x / screen_size.x * 2.0 - 1.0,
-            screen_pos.y / screen_size.y * 2.0 - 1.0);
+        let ndc_x = screen_pos.x / screen_size.x * 2.0 - 1.0;
+        let ndc_y = 1.0 - screen_pos.y / screen_size.y * 2.0;
 
+        //same matrices as rendering, so picking matches what is drawn
         let uniform = self.build_uniform();
+        let inv = (uniform.proj * uniform.view).try_inverse().unwrap_or_else(na::Matrix4::identity);
+        let near = inv.transform_point(&na::Point3::new(ndc_x, ndc_y, -1.0));
+        let far = inv.transform_point(&na::Point3::new(ndc_x, ndc_y, 1.0));
+
+        Ray {
+            pos : near,
+            dir : (far - near).try_normalize(f32::EPSILON).unwrap_or(self.frw)
+        }
+    }
+
+    pub fn resize(&mut self, width : u32, height : u32) {
+        //minimized window has zero height
+        if width > 0 && height > 0 {
+            self.aspect = width as f32 / height as f32;
+        }
+    }
 
-        let up = self.pos + self.up;
-        let right = self.pos + self.get_right();
-        let frw = self.pos + self.frw;
-
-        let filter_mask = nalgebra::Matrix4::<f32>::new(
-            1.0, 0.0, 0.0, 0.0,
-            0.0, 1.0, 0.0, 0.0,
-            0.0, 0.0, 0.0, 0.0,
-            0.0, 0.0, 0.0, 1.0);
-
-        let w_z = 1.0f32;
-        let P = uniform.proj.clone_owned();
-        let s = uniform_screen_pos.clone_owned();
-        let w_x = (P.m12*P.m23 + P.m12*P.m24 - P.m12*P.m43*s.y - P.m12*P.m44*s.y - P.m13*P.m22 + P.m13*P.m42*s.y - P.m14*P.m22 + P.m14*P.m42*s.y + P.m22*P.m43*s.x + P.m22*P.m44*s.x - P.m23*P.m42*s.x - P.m24*P.m42*s.x)
-            /(P.m11*P.m22 - P.m11*P.m42*s.y - P.m12*P.m21 + P.m12*P.m41*s.y + P.m21*P.m42*s.x - P.m22*P.m41*s.x);
-        let w_y = (-P.m11*P.m23 - P.m11*P.m24 + P.m11*P.m43*s.y + P.m11*P.m44*s.y + P.m13*P.m21 - P.m13*P.m41*s.y + P.m14*P.m21 - P.m14*P.m41*s.y - P.m21*P.m43*s.x - P.m21*P.m44*s.x + P.m23*P.m41*s.x + P.m24*P.m41*s.x)
-            /(P.m11*P.m22 - P.m11*P.m42*s.y - P.m12*P.m21 + P.m12*P.m41*s.y + P.m21*P.m42*s.x - P.m22*P.m41*s.x);
-
-        // println!("{:?}", w_x);
-
-        let w = -w_x * self.get_right() + w_y * self.up + w_z * self.frw;
-
-        // println!("{:?} {:?}", w, mat * w);
-        //
-        // let screen_up = uniform.proj * uniform.view * nalgebra::Vector4::<f32>::new(up.x, up.y, up.z, 1.0);
-        // let screen_right = uniform.proj * uniform.view * nalgebra::Vector4::<f32>::new(right.x, right.y, right.z, 1.0);
-        // let screen_frw = uniform.proj * uniform.view * nalgebra::Vector4::<f32>::new(frw.x, frw.y, frw.z, 1.0);
-        //
-        // // println!("{:?} {:?} {:?}", screen_right, screen_up, screen_frw);
-        //
-        //
-        //
-        // let k_x = uniform_screen_pos.x / screen_right.x * screen_frw.w;
-        // let k_y = -uniform_screen_pos.y / screen_up.y * screen_frw.w;
-
-        // let mut dir = k_x * self.get_right() + k_y * self.up + self.frw;
-        let mut dir = nalgebra::Vector3::<f32>::new(w.x, w.y, w.z);
-        dir = dir.normalize();
-
-        res.dir = dir;
-        res.pos = self.pos;
-
-        res
+    pub fn toggle_projection(&mut self) {
+        self.projection = match self.projection {
+            Projection::Perspective => Projection::Orthographic,
+            Projection::Orthographic => Projection::Perspective
+        };
+    }
+
+    //factor above 1 zooms in, only orthographic view has zoom
+    pub fn zoom(&mut self, factor : f32) {
+        self.ortho_height = (self.ortho_height / factor).clamp(1.0, self.far);
+    }
+
+    pub fn build_projection(&self) -> nalgebra::Matrix4<f32> {
+        match self.projection {
+            Projection::Perspective => nalgebra::Matrix4::<f32>::new_perspective(
+                self.aspect,
+                self.fov,
+                self.near,
+                self.far),
+            Projection::Orthographic => {
+                let half_h = self.ortho_height / 2.0;
+                let half_w = half_h * self.aspect;
+                nalgebra::Matrix4::<f32>::new_orthographic(
+                    -half_w,
+                    half_w,
+                    -half_h,
+                    half_h,
+                    self.near,
+                    self.far)
+            }
+        }
     }
 }
 
 
 
+impl Camera {
+    //pixel position of world point, None if it is behind camera
+    pub fn world_to_screen(
+        &self,
+        pos : &nalgebra::Point3<f32>,
+        screen_size : nalgebra::Point2<f32>
+    ) -> Option<nalgebra::Point2<f32>> {
+        //orthographic w is always 1, so check side of camera directly
+        if (pos - self.pos).dot(&self.frw) <= 0.0 {
+            return None;
+        }
+        let uniform = self.build_uniform();
+        let clip = uniform.proj * uniform.view * pos.to_homogeneous();
+        Some(nalgebra::Point2::new(
+            (clip.x / clip.w + 1.0) / 2.0 * screen_size.x,
+            (1.0 - clip.y / clip.w) / 2.0 * screen_size.y))
+    }
+}
+
 impl Default for Camera {
     fn default() -> Self {
         Self {
             pos : [-3.0, 9.0, 0.0].into(),
             frw : [1.0, 0.0, 0.0].into(),
-            up : [0.0, 1.0, 0.0].into()
+            up : [0.0, 1.0, 0.0].into(),
+            fov : std::f32::consts::FRAC_PI_2,
+            near : 0.05,
+            far : 2000.0,
+            aspect : 1.0,
+            projection : Projection::Perspective,
+            ortho_height : 20.0
         }
     }
 }
@@ -120,15 +164,71 @@ impl Camera {
             &self.pos,
             &target,
             &self.up);
-        let proj = nalgebra::Matrix4::<f32>::new_perspective(
-            1.0,
-            3.14 / 2.0,
-            0.01,
-            10000.0);
+        let proj = self.build_projection();
         CameraUniform {
             view,
             proj,
             pos : na::Vector3::new(self.pos.x, self.pos.y, self.pos.z)
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picking_matches_projection() {
+        let mut camera = Camera::default();
+        camera.resize(1600, 900);
+        let size = na::Point2::new(1600.0, 900.0);
+
+        let center = camera.screen_pos_to_ray(na::Point2::new(800.0, 450.0), size);
+        assert!((center.dir - camera.frw).norm() < 1e-4);
+
+        for point in [na::Point3::new(10.0, 12.0, 3.0), na::Point3::new(4.0, 6.0, -5.0)] {
+            let screen = camera.world_to_screen(&point, size).unwrap();
+            let ray = camera.screen_pos_to_ray(screen, size);
+            let to_point = point - ray.pos;
+            let dist = (to_point - ray.dir * to_point.dot(&ray.dir)).norm();
+            assert!(dist < 1e-3, "ray misses {:?} by {}", point, dist);
+        }
+    }
+
+    #[test]
+    fn aspect_keeps_pixels_square() {
+        let mut camera = Camera::default();
+        camera.resize(2000, 1000);
+        let size = na::Point2::new(2000.0, 1000.0);
+
+        //same angle right and up lands same pixel distance from center
+        let right = camera.pos + camera.frw + camera.get_right() * 0.2;
+        let up = camera.pos + camera.frw + camera.up * 0.2;
+        let right = camera.world_to_screen(&right, size).unwrap();
+        let up = camera.world_to_screen(&up, size).unwrap();
+        assert!(((right.x - 1000.0) - (500.0 - up.y)).abs() < 1e-2);
+    }
+
+    #[test]
+    fn ortho_rays_are_parallel() {
+        let mut camera = Camera::default();
+        camera.resize(1600, 900);
+        camera.toggle_projection();
+        let size = na::Point2::new(1600.0, 900.0);
+
+        let corner = camera.screen_pos_to_ray(na::Point2::new(0.0, 0.0), size);
+        let center = camera.screen_pos_to_ray(na::Point2::new(800.0, 450.0), size);
+        assert!((corner.dir - camera.frw).norm() < 1e-4);
+        assert!((center.dir - camera.frw).norm() < 1e-4);
+
+        //corner ray starts half a view away from center one
+        let offset = corner.pos - center.pos;
+        assert!((offset.dot(&camera.up) - 10.0).abs() < 1e-3);
+        assert!((offset.dot(&camera.get_right()) + 10.0 * 16.0 / 9.0).abs() < 1e-3);
+
+        let point = na::Point3::new(8.0, 3.0, 2.0);
+        let screen = camera.world_to_screen(&point, size).unwrap();
+        let ray = camera.screen_pos_to_ray(screen, size);
+        let to_point = point - ray.pos;
+        assert!((to_point - ray.dir * to_point.dot(&ray.dir)).norm() < 1e-3);
+    }
+}
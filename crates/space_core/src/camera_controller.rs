@@ -0,0 +1,347 @@
+use nalgebra as na;
+use crate::ecs::*;
+use crate::{Camera, Projection};
+
+//pitch limit keeps camera from flipping over the pole
+const MAX_PITCH : f32 = 1.55;
+
+//frame input for controllers, filled by game from keyboard and mouse
+#[derive(Clone, Debug, Default)]
+pub struct CameraInput {
+    //x right, y up, z forward, each in -1..1
+    pub movement : na::Vector3<f32>,
+    //mouse movement in pixels while look button is held
+    pub look : na::Vector2<f32>,
+    //wheel steps, positive zooms in
+    pub zoom : f32,
+    //x right, y forward, each in -1..1, e.g. from cursor at screen edge
+    pub pan : na::Vector2<f32>,
+    pub fast : bool
+}
+
+//part of the way to goal covered in dt, same result for any frame rate
+fn smooth_factor(sharpness : f32, dt : f32) -> f32 {
+    if sharpness <= 0.0 {
+        1.0
+    } else {
+        1.0 - (-sharpness * dt).exp()
+    }
+}
+
+fn dir_from_angles(yaw : f32, pitch : f32) -> na::Vector3<f32> {
+    na::Vector3::new(pitch.cos() * yaw.cos(), pitch.sin(), pitch.cos() * yaw.sin())
+}
+
+fn angles_from_dir(dir : &na::Vector3<f32>) -> (f32, f32) {
+    let dir = dir.normalize();
+    (dir.z.atan2(dir.x), dir.y.clamp(-1.0, 1.0).asin().clamp(-MAX_PITCH, MAX_PITCH))
+}
+
+fn look_along(camera : &mut Camera, frw : na::Vector3<f32>) {
+    let right = frw.cross(&na::Vector3::y()).try_normalize(1e-6).unwrap_or(na::Vector3::x());
+    camera.frw = frw.normalize();
+    camera.up = right.cross(&camera.frw).normalize();
+}
+
+//ortho view does not get closer with distance, so zoom resizes it too
+fn zoom_view(camera : &mut Camera, old_distance : f32, new_distance : f32) {
+    if camera.projection == Projection::Orthographic && new_distance > 0.0 {
+        camera.zoom(old_distance / new_distance);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct OrbitController {
+    pub target : na::Point3<f32>,
+    pub distance : f32,
+    pub yaw : f32,
+    pub pitch : f32,
+    //radians per pixel
+    pub rotate_speed : f32,
+    //part of distance per wheel step
+    pub zoom_speed : f32,
+    //part of distance per second
+    pub pan_speed : f32,
+    pub min_distance : f32,
+    pub max_distance : f32,
+    //higher is snappier, 0 disables smoothing
+    pub smoothing : f32,
+    goal_target : na::Point3<f32>,
+    goal_distance : f32,
+    goal_yaw : f32,
+    goal_pitch : f32
+}
+
+impl OrbitController {
+    //orbits point distance ahead of camera
+    pub fn from_camera(camera : &Camera, distance : f32) -> Self {
+        let target = camera.pos + camera.frw.normalize() * distance;
+        //angles of camera as seen from target
+        let (yaw, pitch) = angles_from_dir(&-camera.frw);
+        Self {
+            target,
+            distance,
+            yaw,
+            pitch,
+            rotate_speed : 0.005,
+            zoom_speed : 0.1,
+            pan_speed : 1.0,
+            min_distance : 1.0,
+            max_distance : 500.0,
+            smoothing : 12.0,
+            goal_target : target,
+            goal_distance : distance,
+            goal_yaw : yaw,
+            goal_pitch : pitch
+        }
+    }
+
+    pub fn update(&mut self, camera : &mut Camera, input : &CameraInput, dt : f32) {
+        self.goal_yaw += input.look.x * self.rotate_speed;
+        self.goal_pitch = (self.goal_pitch + input.look.y * self.rotate_speed).clamp(-MAX_PITCH, MAX_PITCH);
+        self.goal_distance = (self.goal_distance * (1.0 - self.zoom_speed).powf(input.zoom))
+            .clamp(self.min_distance, self.max_distance);
+
+        let speed = self.pan_speed * self.goal_distance * if input.fast { 3.0 } else { 1.0 };
+        let right = camera.get_right();
+        let movement = right * input.movement.x + camera.up * input.movement.y + camera.frw * input.movement.z;
+        self.goal_target += movement * speed * dt;
+
+        let k = smooth_factor(self.smoothing, dt);
+        let old_distance = self.distance;
+        self.yaw += (self.goal_yaw - self.yaw) * k;
+        self.pitch += (self.goal_pitch - self.pitch) * k;
+        self.distance += (self.goal_distance - self.distance) * k;
+        self.target += (self.goal_target - self.target) * k;
+
+        let offset = dir_from_angles(self.yaw, self.pitch) * self.distance;
+        camera.pos = self.target + offset;
+        look_along(camera, -offset);
+        zoom_view(camera, old_distance, self.distance);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FreeFlyController {
+    pub yaw : f32,
+    pub pitch : f32,
+    //units per second
+    pub move_speed : f32,
+    pub fast_multiplier : f32,
+    //radians per pixel
+    pub look_speed : f32,
+    pub smoothing : f32,
+    velocity : na::Vector3<f32>,
+    goal_yaw : f32,
+    goal_pitch : f32
+}
+
+impl FreeFlyController {
+    pub fn from_camera(camera : &Camera) -> Self {
+        let (yaw, pitch) = angles_from_dir(&camera.frw);
+        Self {
+            yaw,
+            pitch,
+            move_speed : 8.0,
+            fast_multiplier : 4.0,
+            look_speed : 0.003,
+            smoothing : 12.0,
+            velocity : na::Vector3::zeros(),
+            goal_yaw : yaw,
+            goal_pitch : pitch
+        }
+    }
+
+    pub fn update(&mut self, camera : &mut Camera, input : &CameraInput, dt : f32) {
+        self.goal_yaw += input.look.x * self.look_speed;
+        self.goal_pitch = (self.goal_pitch - input.look.y * self.look_speed).clamp(-MAX_PITCH, MAX_PITCH);
+
+        let k = smooth_factor(self.smoothing, dt);
+        self.yaw += (self.goal_yaw - self.yaw) * k;
+        self.pitch += (self.goal_pitch - self.pitch) * k;
+        look_along(camera, dir_from_angles(self.yaw, self.pitch));
+
+        let speed = self.move_speed * if input.fast { self.fast_multiplier } else { 1.0 };
+        let goal_velocity = (camera.get_right() * input.movement.x
+            + na::Vector3::y() * input.movement.y
+            + camera.frw * input.movement.z) * speed;
+        self.velocity += (goal_velocity - self.velocity) * k;
+        camera.pos += self.velocity * dt;
+
+        //wheel moves camera forward unless view is orthographic
+        match camera.projection {
+            Projection::Perspective => camera.pos += camera.frw * input.zoom * speed * 0.25,
+            Projection::Orthographic => camera.zoom(1.1f32.powf(input.zoom))
+        }
+    }
+}
+
+//top down view over ground plane, pans with keys and screen edges
+#[derive(Clone, Debug)]
+pub struct RtsController {
+    //point on ground under view center
+    pub target : na::Point3<f32>,
+    pub distance : f32,
+    pub yaw : f32,
+    //angle down from horizon
+    pub pitch : f32,
+    //part of distance per second
+    pub pan_speed : f32,
+    pub zoom_speed : f32,
+    //radians per pixel of look drag
+    pub rotate_speed : f32,
+    pub min_distance : f32,
+    pub max_distance : f32,
+    pub smoothing : f32,
+    //cursor this close to screen edge in pixels pans view
+    pub edge_margin : f32,
+    goal_target : na::Point3<f32>,
+    goal_distance : f32,
+    goal_yaw : f32
+}
+
+impl RtsController {
+    //looks at the point where camera ray hits plane at ground height
+    pub fn from_camera(camera : &Camera, ground : f32) -> Self {
+        let (yaw, pitch) = angles_from_dir(&camera.frw);
+        let pitch = (-pitch).clamp(0.2, MAX_PITCH);
+        let height = (camera.pos.y - ground).abs().max(1.0);
+        let distance = height / pitch.sin();
+        let target = camera.pos + dir_from_angles(yaw, -pitch) * distance;
+        let target = na::Point3::new(target.x, ground, target.z);
+        Self {
+            target,
+            distance,
+            yaw,
+            pitch,
+            pan_speed : 1.0,
+            zoom_speed : 0.1,
+            rotate_speed : 0.005,
+            min_distance : 2.0,
+            max_distance : 300.0,
+            smoothing : 10.0,
+            edge_margin : 8.0,
+            goal_target : target,
+            goal_distance : distance,
+            goal_yaw : yaw
+        }
+    }
+
+    pub fn update(&mut self, camera : &mut Camera, input : &CameraInput, dt : f32) {
+        self.goal_yaw += input.look.x * self.rotate_speed;
+        self.goal_distance = (self.goal_distance * (1.0 - self.zoom_speed).powf(input.zoom))
+            .clamp(self.min_distance, self.max_distance);
+
+        //pan along ground, forward is view direction flattened
+        let frw = na::Vector3::new(self.yaw.cos(), 0.0, self.yaw.sin());
+        let right = frw.cross(&na::Vector3::y());
+        let pan_x = (input.movement.x + input.pan.x).clamp(-1.0, 1.0);
+        let pan_z = (input.movement.z + input.pan.y).clamp(-1.0, 1.0);
+        let speed = self.pan_speed * self.goal_distance * if input.fast { 3.0 } else { 1.0 };
+        self.goal_target += (right * pan_x + frw * pan_z) * speed * dt;
+
+        let k = smooth_factor(self.smoothing, dt);
+        let old_distance = self.distance;
+        self.yaw += (self.goal_yaw - self.yaw) * k;
+        self.distance += (self.goal_distance - self.distance) * k;
+        self.target += (self.goal_target - self.target) * k;
+
+        let view = dir_from_angles(self.yaw, -self.pitch);
+        camera.pos = self.target - view * self.distance;
+        look_along(camera, view);
+        zoom_view(camera, old_distance, self.distance);
+    }
+}
+
+//camera behaviour of scene, scenes without it move camera themselves
+#[derive(Clone, Debug, Resource)]
+pub enum CameraController {
+    Orbit(OrbitController),
+    FreeFly(FreeFlyController),
+    Rts(RtsController)
+}
+
+impl CameraController {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CameraController::Orbit(_) => "Orbit",
+            CameraController::FreeFly(_) => "Free fly",
+            CameraController::Rts(_) => "RTS"
+        }
+    }
+
+    pub fn update(&mut self, camera : &mut Camera, input : &CameraInput, dt : f32) {
+        match self {
+            CameraController::Orbit(c) => c.update(camera, input, dt),
+            CameraController::FreeFly(c) => c.update(camera, input, dt),
+            CameraController::Rts(c) => c.update(camera, input, dt)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(controller : &mut CameraController, camera : &mut Camera, input : &CameraInput, fps : usize) {
+        let dt = 1.0 / fps as f32;
+        for _ in 0..fps {
+            controller.update(camera, input, dt);
+        }
+    }
+
+    #[test]
+    fn movement_does_not_depend_on_frame_rate() {
+        let input = CameraInput {
+            movement : na::Vector3::new(1.0, 0.0, 1.0),
+            ..Default::default()
+        };
+        let start = Camera::default();
+        for make in [
+            |c : &Camera| CameraController::FreeFly(FreeFlyController::from_camera(c)),
+            |c : &Camera| CameraController::Rts(RtsController::from_camera(c, 0.0)),
+            |c : &Camera| CameraController::Orbit(OrbitController::from_camera(c, 10.0))] {
+
+            let mut slow_camera = start.clone();
+            let mut fast_camera = start.clone();
+            run(&mut make(&start), &mut slow_camera, &input, 30);
+            run(&mut make(&start), &mut fast_camera, &input, 240);
+            let moved = (fast_camera.pos - start.pos).norm();
+            assert!(moved > 1.0);
+            assert!((slow_camera.pos - fast_camera.pos).norm() < moved * 0.05);
+        }
+    }
+
+    #[test]
+    fn controllers_start_from_camera_view() {
+        let mut camera = Camera::default();
+        look_along(&mut camera, na::Vector3::new(1.0, -1.0, 0.5));
+        let start = camera.clone();
+        let idle = CameraInput::default();
+
+        for mut controller in [
+            CameraController::FreeFly(FreeFlyController::from_camera(&camera)),
+            CameraController::Rts(RtsController::from_camera(&camera, 0.0)),
+            CameraController::Orbit(OrbitController::from_camera(&camera, 10.0))] {
+
+            let mut camera = start.clone();
+            controller.update(&mut camera, &idle, 1.0 / 60.0);
+            assert!((camera.pos - start.pos).norm() < 1e-3, "{} moved camera", controller.name());
+            assert!((camera.frw - start.frw).norm() < 1e-3, "{} turned camera", controller.name());
+        }
+    }
+
+    #[test]
+    fn smoothing_reaches_goal() {
+        let mut camera = Camera::default();
+        let mut orbit = OrbitController::from_camera(&camera, 10.0);
+        orbit.update(&mut camera, &CameraInput { zoom : 5.0, ..Default::default() }, 1.0 / 60.0);
+        //half way after first frame, goal is kept
+        assert!(orbit.distance > orbit.goal_distance);
+        for _ in 0..120 {
+            orbit.update(&mut camera, &CameraInput::default(), 1.0 / 60.0);
+        }
+        assert!((orbit.distance - 10.0 * 0.9f32.powf(5.0)).abs() < 1e-3);
+        assert!(((camera.pos - orbit.target).norm() - orbit.distance).abs() < 1e-3);
+    }
+}
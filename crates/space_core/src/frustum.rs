@@ -0,0 +1,120 @@
+use crate::{Aabb, CameraUniform, Pos3, Vec3};
+
+//points with normal.dot(p) + d >= 0 are in front of plane
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Plane {
+    pub normal : Vec3,
+    pub d : f32
+}
+
+impl Plane {
+    fn from_row(row : nalgebra::RowVector4<f32>) -> Self {
+        let normal = Vec3::new(row.x, row.y, row.z);
+        let len = normal.norm().max(f32::EPSILON);
+        Self {
+            normal : normal / len,
+            d : row.w / len
+        }
+    }
+
+    pub fn distance(&self, pos : &Pos3) -> f32 {
+        self.normal.dot(&pos.coords) + self.d
+    }
+}
+
+//view volume of camera, tests are conservative: things near corners may pass
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    //left, right, bottom, top, near, far
+    pub planes : [Plane; 6]
+}
+
+impl Frustum {
+    //proj * view with clip depth from -w to w, as built by nalgebra
+    pub fn from_matrix(mat : &nalgebra::Matrix4<f32>) -> Self {
+        let r = |i : usize| mat.row(i).clone_owned();
+        Self {
+            planes : [
+                Plane::from_row(r(3) + r(0)),
+                Plane::from_row(r(3) - r(0)),
+                Plane::from_row(r(3) + r(1)),
+                Plane::from_row(r(3) - r(1)),
+                Plane::from_row(r(3) + r(2)),
+                Plane::from_row(r(3) - r(2))
+            ]
+        }
+    }
+
+    pub fn from_uniform(uniform : &CameraUniform) -> Self {
+        Frustum::from_matrix(&(uniform.proj * uniform.view))
+    }
+
+    pub fn contains_point(&self, pos : &Pos3) -> bool {
+        self.planes.iter().all(|p| p.distance(pos) >= 0.0)
+    }
+
+    pub fn intersects_sphere(&self, center : &Pos3, radius : f32) -> bool {
+        self.planes.iter().all(|p| p.distance(center) >= -radius)
+    }
+
+    pub fn intersects_aabb(&self, aabb : &Aabb) -> bool {
+        self.planes.iter().all(|p| {
+            //corner furthest along plane normal
+            let corner = Pos3::new(
+                if p.normal.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+                if p.normal.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+                if p.normal.z >= 0.0 { aabb.max.z } else { aabb.min.z });
+            p.distance(&corner) >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Camera;
+
+    //default camera at (-3, 9, 0) looks along +x
+    fn frustum() -> Frustum {
+        let camera = Camera {
+            far : 100.0,
+            ..Camera::default()
+        };
+        Frustum::from_uniform(&camera.build_uniform())
+    }
+
+    #[test]
+    fn boxes_around_camera() {
+        let frustum = frustum();
+        let unit = Vec3::repeat(0.5);
+        assert!(frustum.intersects_aabb(&Aabb::from_center(&Pos3::new(5.0, 9.0, 0.0), &unit)));
+        //behind camera and beyond far plane
+        assert!(!frustum.intersects_aabb(&Aabb::from_center(&Pos3::new(-6.0, 9.0, 0.0), &unit)));
+        assert!(!frustum.intersects_aabb(&Aabb::from_center(&Pos3::new(120.0, 9.0, 0.0), &unit)));
+        //90 degree view, 10 units ahead it is 10 units wide each way
+        assert!(!frustum.intersects_aabb(&Aabb::from_center(&Pos3::new(7.0, 9.0, 12.0), &unit)));
+        assert!(frustum.intersects_aabb(&Aabb::from_center(&Pos3::new(7.0, 9.0, 10.4), &unit)));
+        //huge box around camera is visible though all its corners are outside
+        assert!(frustum.intersects_aabb(&Aabb::from_center(&Pos3::new(-3.0, 9.0, 0.0), &Vec3::repeat(500.0))));
+    }
+
+    #[test]
+    fn spheres_and_points() {
+        let frustum = frustum();
+        assert!(frustum.contains_point(&Pos3::new(10.0, 9.0, 0.0)));
+        assert!(!frustum.contains_point(&Pos3::new(10.0, 30.0, 0.0)));
+        assert!(frustum.intersects_sphere(&Pos3::new(-4.0, 9.0, 0.0), 2.0));
+        assert!(!frustum.intersects_sphere(&Pos3::new(-6.0, 9.0, 0.0), 2.0));
+    }
+
+    #[test]
+    fn transformed_bounds() {
+        let aabb = Aabb::new(Pos3::new(0.0, 0.0, 0.0), Pos3::new(2.0, 1.0, 1.0));
+        let rot = nalgebra::Matrix4::new_rotation(Vec3::new(0.0, std::f32::consts::FRAC_PI_2, 0.0));
+        let moved = aabb.transformed(&(nalgebra::Matrix4::new_translation(&Vec3::new(10.0, 0.0, 0.0)) * rot));
+        assert!((moved.min - Pos3::new(10.0, 0.0, -2.0)).norm() < 1e-5);
+        assert!((moved.max - Pos3::new(11.0, 1.0, 0.0)).norm() < 1e-5);
+        assert!(moved.intersects_sphere(&Pos3::new(12.0, 0.5, -1.0), 1.01));
+        assert!(!moved.intersects_sphere(&Pos3::new(12.0, 0.5, -1.0), 0.99));
+    }
+}
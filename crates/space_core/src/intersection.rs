@@ -0,0 +1,253 @@
+use crate::{Aabb, Pos3, Ray, Vec3};
+
+//distance is in ray dir lengths, world units for normalized dir
+//shapes report normal pointing out of them, planes and triangles toward ray origin
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayHit {
+    pub distance : f32,
+    pub point : Pos3,
+    pub normal : Vec3
+}
+
+//box rotated around its center
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Obb {
+    pub center : Pos3,
+    pub half_size : Vec3,
+    pub rotation : nalgebra::UnitQuaternion<f32>
+}
+
+impl Obb {
+    pub fn new(center : Pos3, half_size : Vec3, rotation : nalgebra::UnitQuaternion<f32>) -> Self {
+        Self {
+            center,
+            half_size,
+            rotation
+        }
+    }
+}
+
+fn face_toward(normal : Vec3, dir : &Vec3) -> Vec3 {
+    if normal.dot(dir) > 0.0 {
+        -normal
+    } else {
+        normal
+    }
+}
+
+impl Ray {
+    pub fn at(&self, distance : f32) -> Pos3 {
+        self.pos + self.dir * distance
+    }
+
+    fn hit(&self, distance : f32, normal : Vec3) -> RayHit {
+        RayHit {
+            distance,
+            point : self.at(distance),
+            normal
+        }
+    }
+
+    pub fn intersect_plane(&self, point : &Pos3, normal : &Vec3) -> Option<RayHit> {
+        let denom = normal.dot(&self.dir);
+        if denom.abs() < f32::EPSILON {
+            return None;
+        }
+        let t = normal.dot(&(point - self.pos)) / denom;
+        if t < 0.0 {
+            return None;
+        }
+        Some(self.hit(t, face_toward(normal.normalize(), &self.dir)))
+    }
+
+    //ray starting inside box hits it where it leaves
+    pub fn intersect_aabb(&self, aabb : &Aabb) -> Option<RayHit> {
+        let mut t_near = f32::NEG_INFINITY;
+        let mut t_far = f32::INFINITY;
+        let mut near_axis = 0;
+        let mut far_axis = 0;
+        for i in 0..3 {
+            if self.dir[i].abs() < f32::EPSILON {
+                if self.pos[i] < aabb.min[i] || self.pos[i] > aabb.max[i] {
+                    return None;
+                }
+                continue;
+            }
+            let t1 = (aabb.min[i] - self.pos[i]) / self.dir[i];
+            let t2 = (aabb.max[i] - self.pos[i]) / self.dir[i];
+            let (t1, t2) = (t1.min(t2), t1.max(t2));
+            if t1 > t_near {
+                t_near = t1;
+                near_axis = i;
+            }
+            if t2 < t_far {
+                t_far = t2;
+                far_axis = i;
+            }
+        }
+        if t_near > t_far || t_far < 0.0 {
+            return None;
+        }
+
+        let mut normal = Vec3::zeros();
+        if t_near >= 0.0 {
+            normal[near_axis] = -self.dir[near_axis].signum();
+            Some(self.hit(t_near, normal))
+        } else {
+            normal[far_axis] = self.dir[far_axis].signum();
+            Some(self.hit(t_far, normal))
+        }
+    }
+
+    pub fn intersect_obb(&self, obb : &Obb) -> Option<RayHit> {
+        let inv = obb.rotation.inverse();
+        let local = Ray {
+            pos : Pos3::from(inv * (self.pos - obb.center)),
+            dir : inv * self.dir
+        };
+        let hit = local.intersect_aabb(&Aabb::from_center(&Pos3::origin(), &obb.half_size))?;
+        Some(self.hit(hit.distance, obb.rotation * hit.normal))
+    }
+
+    //ray starting inside sphere hits it where it leaves
+    pub fn intersect_sphere(&self, center : &Pos3, radius : f32) -> Option<RayHit> {
+        let to_origin = self.pos - center;
+        let a = self.dir.norm_squared();
+        let half_b = to_origin.dot(&self.dir);
+        let c = to_origin.norm_squared() - radius * radius;
+        let discriminant = half_b * half_b - a * c;
+        if a < f32::EPSILON || discriminant < 0.0 {
+            return None;
+        }
+        let sqrt = discriminant.sqrt();
+        let near = (-half_b - sqrt) / a;
+        let far = (-half_b + sqrt) / a;
+        let t = if near >= 0.0 { near } else { far };
+        if t < 0.0 {
+            return None;
+        }
+        let point = self.at(t);
+        Some(RayHit {
+            distance : t,
+            point,
+            normal : (point - center) / radius
+        })
+    }
+
+    //both sides of triangle are hit
+    pub fn intersect_triangle(&self, a : &Pos3, b : &Pos3, c : &Pos3) -> Option<RayHit> {
+        let edge1 = b - a;
+        let edge2 = c - a;
+        let p = self.dir.cross(&edge2);
+        let det = edge1.dot(&p);
+        if det.abs() < f32::EPSILON {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let s = self.pos - a;
+        let u = s.dot(&p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(&edge1);
+        let v = self.dir.dot(&q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = edge2.dot(&q) * inv_det;
+        if t < 0.0 {
+            return None;
+        }
+        let normal = edge1.cross(&edge2).try_normalize(f32::EPSILON)?;
+        Some(self.hit(t, face_toward(normal, &self.dir)))
+    }
+
+    //nearest hit of indexed triangle list, e.g. GMesh pick data
+    //triangles with indices out of positions are skipped
+    pub fn intersect_mesh(&self, positions : &[Pos3], indices : &[u32]) -> Option<RayHit> {
+        indices.chunks_exact(3)
+            .filter_map(|tri| self.intersect_triangle(
+                positions.get(tri[0] as usize)?,
+                positions.get(tri[1] as usize)?,
+                positions.get(tri[2] as usize)?))
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ray(pos : [f32; 3], dir : [f32; 3]) -> Ray {
+        Ray {
+            pos : pos.into(),
+            dir : Vec3::from(dir).normalize()
+        }
+    }
+
+    fn assert_hit(hit : Option<RayHit>, distance : f32, normal : [f32; 3]) {
+        let hit = hit.expect("ray should hit");
+        assert!((hit.distance - distance).abs() < 1e-4, "distance {} instead of {}", hit.distance, distance);
+        assert!((hit.normal - Vec3::from(normal)).norm() < 1e-4, "normal {:?} instead of {:?}", hit.normal, normal);
+    }
+
+    #[test]
+    fn plane_and_triangle() {
+        let down = ray([1.0, 5.0, 1.0], [0.0, -1.0, 0.0]);
+        //tilted plane through origin, normal faces ray even if given backwards
+        let normal = Vec3::new(1.0, 1.0, 0.0).normalize();
+        let hit = down.intersect_plane(&Pos3::origin(), &-normal).unwrap();
+        assert!((hit.point - Pos3::new(1.0, -1.0, 1.0)).norm() < 1e-4);
+        assert!((hit.normal - normal).norm() < 1e-4);
+        assert!(down.intersect_plane(&Pos3::new(0.0, 6.0, 0.0), &Vec3::y()).is_none());
+        assert!(down.intersect_plane(&Pos3::origin(), &Vec3::x()).is_none());
+
+        let (a, b, c) = (Pos3::new(0.0, 0.0, 0.0), Pos3::new(4.0, 0.0, 0.0), Pos3::new(0.0, 0.0, 4.0));
+        assert_hit(down.intersect_triangle(&a, &b, &c), 5.0, [0.0, 1.0, 0.0]);
+        assert_hit(ray([1.0, -2.0, 1.0], [0.0, 1.0, 0.0]).intersect_triangle(&a, &b, &c), 2.0, [0.0, -1.0, 0.0]);
+        assert!(ray([3.0, 5.0, 3.0], [0.0, -1.0, 0.0]).intersect_triangle(&a, &b, &c).is_none());
+    }
+
+    #[test]
+    fn boxes() {
+        let aabb = Aabb::new(Pos3::new(0.0, 0.0, 0.0), Pos3::new(2.0, 2.0, 2.0));
+        assert_hit(ray([-3.0, 1.0, 1.0], [1.0, 0.0, 0.0]).intersect_aabb(&aabb), 3.0, [-1.0, 0.0, 0.0]);
+        assert_hit(ray([1.0, 1.0, 1.0], [0.0, 0.0, 1.0]).intersect_aabb(&aabb), 1.0, [0.0, 0.0, 1.0]);
+        assert!(ray([-3.0, 3.0, 1.0], [1.0, 0.0, 0.0]).intersect_aabb(&aabb).is_none());
+        assert!(ray([3.0, 1.0, 1.0], [1.0, 0.0, 0.0]).intersect_aabb(&aabb).is_none());
+
+        //unit cube turned 45 degrees around y, corner faces the ray
+        let rotation = nalgebra::UnitQuaternion::from_axis_angle(&Vec3::y_axis(), std::f32::consts::FRAC_PI_4);
+        let obb = Obb::new(Pos3::new(5.0, 0.0, 0.0), Vec3::repeat(1.0), rotation);
+        let hit = ray([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]).intersect_obb(&obb).unwrap();
+        assert!((hit.distance - (5.0 - 2.0f32.sqrt())).abs() < 1e-4);
+        let side = ray([0.0, 0.0, -0.5], [1.0, 0.0, 0.0]).intersect_obb(&obb).unwrap();
+        assert!((side.normal - rotation * -Vec3::z()).norm() < 1e-4 || (side.normal - rotation * -Vec3::x()).norm() < 1e-4);
+        assert!(ray([0.0, 0.0, 1.5], [1.0, 0.0, 0.0]).intersect_obb(&obb).is_none());
+    }
+
+    #[test]
+    fn spheres() {
+        let center = Pos3::new(0.0, 0.0, 10.0);
+        assert_hit(ray([0.0, 0.0, 0.0], [0.0, 0.0, 1.0]).intersect_sphere(&center, 2.0), 8.0, [0.0, 0.0, -1.0]);
+        assert_hit(ray([0.0, 0.0, 10.0], [0.0, 1.0, 0.0]).intersect_sphere(&center, 2.0), 2.0, [0.0, 1.0, 0.0]);
+        assert!(ray([0.0, 3.0, 0.0], [0.0, 0.0, 1.0]).intersect_sphere(&center, 2.0).is_none());
+        assert!(ray([0.0, 0.0, 13.0], [0.0, 0.0, 1.0]).intersect_sphere(&center, 2.0).is_none());
+    }
+
+    #[test]
+    fn nearest_mesh_triangle() {
+        //two parallel quads along x
+        let positions : Vec<Pos3> = [0.0, 3.0].iter()
+            .flat_map(|x| [[*x, -1.0, -1.0], [*x, 1.0, -1.0], [*x, 1.0, 1.0], [*x, -1.0, 1.0]])
+            .map(Pos3::from)
+            .collect();
+        let indices = [4, 5, 6, 4, 6, 7, 0, 1, 2, 0, 2, 3];
+        assert_hit(ray([-2.0, 0.5, 0.2], [1.0, 0.0, 0.0]).intersect_mesh(&positions, &indices), 2.0, [-1.0, 0.0, 0.0]);
+        assert_hit(ray([5.0, 0.5, 0.2], [-1.0, 0.0, 0.0]).intersect_mesh(&positions, &indices), 2.0, [1.0, 0.0, 0.0]);
+        assert!(ray([-2.0, 2.0, 0.0], [1.0, 0.0, 0.0]).intersect_mesh(&positions, &indices).is_none());
+        //broken index does not panic, remaining triangles still hit
+        let broken = [4, 5, 60, 0, 1, 2, 0, 2, 3];
+        assert_hit(ray([-2.0, 0.5, 0.2], [1.0, 0.0, 0.0]).intersect_mesh(&positions, &broken), 2.0, [-1.0, 0.0, 0.0]);
+    }
+}
@@ -1,9 +1,17 @@
 
 mod task_server;
 mod camera;
+mod camera_controller;
+mod aabb;
+mod frustum;
+mod intersection;
 
 pub use task_server::*;
 pub use camera::*;
+pub use camera_controller::*;
+pub use aabb::*;
+pub use frustum::*;
+pub use intersection::*;
 
 pub use bevy;
 pub use ron;
@@ -156,6 +156,9 @@ impl Game {
 
     fn resize_event(&mut self, new_size : PhysicalSize<u32>) {
         self.scene.app.insert_resource(ScreenSize {size : new_size.clone(), format : self.api.config.format.clone()});
+        if let Some(mut camera) = self.scene.app.world.get_resource_mut::<Camera>() {
+            camera.resize(new_size.width, new_size.height);
+        }
         let mut plugins = self.plugins.take().unwrap();
         for plugin in &mut plugins.render_plugin {
             plugin.window_resize(self, new_size);
@@ -219,6 +222,7 @@ impl Game {
         self.scene.app.world.get_resource_mut::<SpaceAssetServer>().unwrap().sync_tick();
 
         self.scene.app.update();
+        self.scene.app.world.get_resource_mut::<InputSystem>().unwrap().end_frame();
 
         let mut plugins = self.plugins.take().unwrap();
         for plugin in &mut plugins.render_plugin {
@@ -298,6 +302,10 @@ impl Game {
                             self.scene.app.world.get_resource_mut::<InputSystem>()
                                 .unwrap().process_cursor_move(position.clone());
                         }
+                        WindowEvent::MouseWheel { delta, .. } => {
+                            self.scene.app.world.get_resource_mut::<InputSystem>()
+                                .unwrap().process_scroll(delta);
+                        }
                         _ => {}
                     }
                 }
@@ -403,7 +411,8 @@ impl Default for Game {
         let task_server = Arc::new(TaskServer::new());
         let assets = SpaceAssetServer::new(&render_base, &task_server);
 
-        let camera = Camera::default();
+        let mut camera = Camera::default();
+        camera.resize(api.size.width, api.size.height);
         let camera_uniform = camera.build_uniform();
 
         let mut camera_cpu_buffer = UniformBuffer::new(vec![0u8;100]);
@@ -421,7 +430,7 @@ impl Default for Game {
         };
 
         scene.app.insert_resource(SpaceAssetServer::new(&render_base, &task_server));
-        scene.app.insert_resource(Camera::default());
+        scene.app.insert_resource(camera);
 
         // scene.app.insert_resource(
         //     GpuProfiler::new(
@@ -434,6 +443,7 @@ impl Default for Game {
         scene.app.insert_non_send_resource(window);
 
         scene.app.insert_resource(RenderApi {base : render_base.clone()});
+        scene.app.insert_resource(TaskServerApi {server : task_server.clone()});
         scene.app.insert_resource(ScreenSize {size : api.size.clone(), format : api.config.format});
 
         scene.app.insert_resource(CameraBuffer {buffer : camera_buffer});
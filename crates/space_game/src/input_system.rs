@@ -1,14 +1,17 @@
 use std::collections::HashMap;
 use bevy::prelude::Resource;
 use winit::dpi::PhysicalPosition;
-use winit::event::{ElementState, KeyboardInput, MouseButton};
+use winit::event::{ElementState, KeyboardInput, MouseButton, MouseScrollDelta};
 pub use winit::event::VirtualKeyCode as KeyCode;
 
 #[derive(Default, Resource)]
 pub struct InputSystem {
     key_state : HashMap<winit::event::VirtualKeyCode, bool>,
     mouse_buttons : HashMap<winit::event::MouseButton, bool>,
-    pos : nalgebra::Point2<f32>
+    pos : nalgebra::Point2<f32>,
+    //cursor movement and wheel steps since last frame
+    mouse_delta : nalgebra::Vector2<f32>,
+    scroll : f32
 }
 
 impl InputSystem {
@@ -25,8 +28,23 @@ impl InputSystem {
     }
 
     pub fn process_cursor_move(&mut self, pos : PhysicalPosition<f64>) {
-        self.pos.x = pos.x as f32;
-        self.pos.y = pos.y as f32;
+        let new_pos = nalgebra::Point2::new(pos.x as f32, pos.y as f32);
+        self.mouse_delta += new_pos - self.pos;
+        self.pos = new_pos;
+    }
+
+    pub fn process_scroll(&mut self, delta : &MouseScrollDelta) {
+        self.scroll += match delta {
+            MouseScrollDelta::LineDelta(_, y) => *y,
+            //touchpads report pixels, roughly a line per 50
+            MouseScrollDelta::PixelDelta(pos) => pos.y as f32 / 50.0
+        };
+    }
+
+    //called after every frame, deltas are per frame
+    pub fn end_frame(&mut self) {
+        self.mouse_delta = nalgebra::Vector2::zeros();
+        self.scroll = 0.0;
     }
 
     pub fn get_key_state(&self, key : KeyCode) -> bool {
@@ -49,4 +67,12 @@ impl InputSystem {
         self.pos.clone()
     }
 
+    pub fn get_mouse_delta(&self) -> nalgebra::Vector2<f32> {
+        self.mouse_delta
+    }
+
+    pub fn get_scroll(&self) -> f32 {
+        self.scroll
+    }
+
 }
\ No newline at end of file
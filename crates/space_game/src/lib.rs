@@ -17,7 +17,7 @@ pub use game::*;
 pub use input_system::*;
 pub use gui::*;
 use space_assets::Location;
-use space_core::{ecs::StageLabel, RenderBase};
+use space_core::{ecs::StageLabel, RenderBase, TaskServer};
 use space_core::asset::*;
 use space_core::serde::*;
 use space_core::ecs::Resource;
@@ -154,6 +154,19 @@ impl Deref for RenderApi {
     }
 }
 
+#[derive(Resource)]
+pub struct TaskServerApi {
+    pub server : Arc<TaskServer>
+}
+
+impl Deref for TaskServerApi {
+    type Target = Arc<TaskServer>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.server
+    }
+}
+
 #[derive(PartialEq)]
 pub enum PluginName {
     Text(String),
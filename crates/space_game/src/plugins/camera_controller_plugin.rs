@@ -0,0 +1,79 @@
+use bevy::prelude::CoreStage;
+use bevy::time::Time;
+use space_core::ecs::*;
+use space_core::{Camera, CameraController, CameraInput};
+use winit::event::MouseButton;
+use crate::*;
+
+fn axis(input : &InputSystem, negative : KeyCode, positive : KeyCode) -> f32 {
+    input.get_key_state(positive) as i32 as f32 - input.get_key_state(negative) as i32 as f32
+}
+
+fn edge_pan(pos : nalgebra::Point2<f32>, size : nalgebra::Point2<f32>, margin : f32) -> nalgebra::Vector2<f32> {
+    let inside = pos.x >= 0.0 && pos.y >= 0.0 && pos.x <= size.x && pos.y <= size.y;
+    if !inside || margin <= 0.0 {
+        return nalgebra::Vector2::zeros();
+    }
+    let side = |p : f32, max : f32| {
+        if p < margin {
+            -1.0
+        } else if p > max - margin {
+            1.0
+        } else {
+            0.0
+        }
+    };
+    //screen y goes down, pan y is forward
+    nalgebra::Vector2::new(side(pos.x, size.x), -side(pos.y, size.y))
+}
+
+//WASD moves, space and control go up and down, shift speeds up
+//middle mouse button looks around, wheel zooms
+//right button is taken by block removal in build scene
+fn camera_controller_system(
+    controller : Option<ResMut<CameraController>>,
+    mut camera : ResMut<Camera>,
+    input : Res<InputSystem>,
+    time : Res<Time>,
+    ctx : Res<EguiContext>,
+    screen_size : Res<ScreenSize>) {
+
+    let Some(mut controller) = controller else {
+        return;
+    };
+
+    let mut frame = CameraInput::default();
+    if !ctx.wants_keyboard_input() {
+        frame.movement = nalgebra::Vector3::new(
+            axis(&input, KeyCode::A, KeyCode::D),
+            axis(&input, KeyCode::LControl, KeyCode::Space),
+            axis(&input, KeyCode::S, KeyCode::W));
+        frame.fast = input.get_key_state(KeyCode::LShift);
+    }
+    if !ctx.wants_pointer_input() {
+        if input.get_mouse_button_state(&MouseButton::Middle) {
+            frame.look = input.get_mouse_delta();
+        }
+        frame.zoom = input.get_scroll();
+        if let CameraController::Rts(rts) = controller.as_ref() {
+            let size = nalgebra::Point2::new(screen_size.size.width as f32, screen_size.size.height as f32);
+            frame.pan = edge_pan(input.get_mouse_pos(), size, rts.edge_margin);
+        }
+    }
+
+    controller.update(&mut camera, &frame, time.delta_seconds());
+}
+
+pub struct CameraControllerSystem {
+
+}
+
+impl SchedulePlugin for CameraControllerSystem {
+    fn get_name(&self) -> PluginName {
+        PluginName::Text("CameraController".into())
+    }
+
+    fn add_system(&self, app : &mut space_core::app::App) {
+        app.add_system_to_stage(CoreStage::Update, camera_controller_system);
+    }
+}
@@ -1,5 +1,7 @@
 mod location_update_plugin;
 mod fps_counter;
+mod camera_controller_plugin;
 
 pub use location_update_plugin::*;
-pub use fps_counter::*;
\ No newline at end of file
+pub use fps_counter::*;
+pub use camera_controller_plugin::*;
\ No newline at end of file
@@ -0,0 +1,80 @@
+use space_assets::{GMesh, Location};
+use space_core::{Aabb, Frustum};
+use space_core::app::App;
+use space_core::ecs::*;
+use space_game::*;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PassStats {
+    pub drawn : u32,
+    pub culled : u32
+}
+
+impl PassStats {
+    pub fn count(&mut self, visible : bool) {
+        if visible {
+            self.drawn += 1;
+        } else {
+            self.culled += 1;
+        }
+    }
+}
+
+//frame counters are reset by their passes
+#[derive(Resource)]
+pub struct FrustumCulling {
+    pub enabled : bool,
+    pub gbuffer : PassStats,
+    //all faces of all shadowed lights
+    pub shadow : PassStats
+}
+
+impl Default for FrustumCulling {
+    fn default() -> Self {
+        Self {
+            enabled : true,
+            gbuffer : PassStats::default(),
+            shadow : PassStats::default()
+        }
+    }
+}
+
+impl FrustumCulling {
+    pub fn is_visible(&self, frustum : &Frustum, bounds : &Aabb) -> bool {
+        !self.enabled || frustum.intersects_aabb(bounds)
+    }
+}
+
+pub fn world_bounds(mesh : &GMesh, loc : &Location) -> Aabb {
+    mesh.bounds.transformed(&loc.model_matrix())
+}
+
+fn culling_stats_ui(
+    ctx : Res<EguiContext>,
+    mut culling : ResMut<FrustumCulling>) {
+
+    egui::TopBottomPanel::bottom("Culling").show(&ctx, |ui| {
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut culling.enabled, "Frustum culling");
+            let gbuffer = culling.gbuffer;
+            let shadow = culling.shadow;
+            ui.label(format!("G-buffer: {} drawn, {} culled", gbuffer.drawn, gbuffer.culled));
+            ui.label(format!("Shadows: {} drawn, {} culled", shadow.drawn, shadow.culled));
+        });
+    });
+}
+
+pub struct CullingStatsSystem {
+
+}
+
+impl SchedulePlugin for CullingStatsSystem {
+    fn get_name(&self) -> PluginName {
+        PluginName::Text("Culling stats".into())
+    }
+
+    fn add_system(&self, app : &mut App) {
+        app.insert_resource(FrustumCulling::default());
+        app.add_system_to_stage(GlobalStageStep::Gui, culling_stats_ui);
+    }
+}
@@ -2,6 +2,7 @@ pub mod pipelines;
 pub mod light;
 pub mod hdri;
 pub mod ui;
+pub mod culling;
 
 use bevy::prelude::Component;
 use nalgebra as na;
@@ -17,6 +18,7 @@ pub fn add_game_render_plugins(game : &mut Game) {
     // let mut state = pollster::block_on(crate::pipelines::State::new(game));
     game.add_schedule_plugin(StateSystem{});
     game.add_schedule_plugin(space_game::plugins::LocUpdateSystem {});
+    game.add_schedule_plugin(crate::culling::CullingStatsSystem {});
     game.add_schedule_plugin(crate::pipelines::GBufferPlugin {});
     game.add_schedule_plugin(crate::pipelines::point_light_plugin::PointLightPlugin {});
     game.add_schedule_plugin(crate::pipelines::FastDepthPlugin {});
@@ -1,11 +1,15 @@
 
 use encase::*;
+use bevy::prelude::Resource;
 use crate::pipelines::TextureTransformUniform;
 
 #[derive(ShaderType, Default)]
 pub struct AmbientLightUniform {
     pub color : nalgebra::Vector3<f32>,
-    pub cam_pos : nalgebra::Vector3<f32>
+    pub cam_pos : nalgebra::Vector3<f32>,
+    pub use_ssao : f32,
+    pub use_vertex_ao : f32,
+    pub baked_light : f32
 }
 
 impl TextureTransformUniform for AmbientLightUniform {
@@ -20,3 +24,34 @@ pub struct AmbientLight {
     pub color : nalgebra::Vector3<f32>,
 }
 
+//which occlusion terms darken ambient light
+#[derive(Resource)]
+pub struct AmbientOcclusionSettings {
+    pub ssao : bool,
+    pub vertex_ao : bool
+}
+
+impl Default for AmbientOcclusionSettings {
+    fn default() -> Self {
+        Self {
+            ssao : true,
+            vertex_ao : true
+        }
+    }
+}
+
+//voxel light baked into vertices, see space_voxel light
+#[derive(Resource)]
+pub struct BakedLightSettings {
+    pub enabled : bool,
+    pub intensity : f32
+}
+
+impl Default for BakedLightSettings {
+    fn default() -> Self {
+        Self {
+            enabled : true,
+            intensity : 1.0
+        }
+    }
+}
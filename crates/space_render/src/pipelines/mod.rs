@@ -27,7 +27,7 @@ pub use wgpu_texture_present::*;
 pub use wgpu_light_shadow::*;
 pub use wgpu_textures_transform::*;
 
-use crate::light::{AmbientLightUniform, PointLight};
+use crate::light::{AmbientLightUniform, AmbientOcclusionSettings, BakedLightSettings, PointLight};
 use crate::pipelines::wgpu_ssao::SSAOFrame;
 
 use self::wgpu_sreen_diffuse::DepthTexture;
@@ -290,7 +290,7 @@ impl State {
             render : render.clone(),
             format : wgpu::TextureFormat::Rgba32Float,
             size : extent,
-            input_count : 5,
+            input_count : 6,
             output_count : 1,
             uniform : Some(Arc::new(AmbientLightUniform::default())),
             shader : include_str!("../../../../shaders/wgsl/ambient_light.wgsl").into(),
@@ -350,6 +350,8 @@ pub struct StateSystem {}
 fn state_update(
     mut state : ResMut<State>,
     mut camera : Res<Camera>,
+    ao_settings : Res<AmbientOcclusionSettings>,
+    baked_light : Res<BakedLightSettings>,
     mut lights : Query<&mut PointLight>
 ) {
     for mut light in &mut lights {
@@ -360,7 +362,10 @@ fn state_update(
 
     let ambient_uniform = AmbientLightUniform {
         color: state.ambient_light.color.into(),
-        cam_pos: camera.pos.coords.clone()
+        cam_pos: camera.pos.coords.clone(),
+        use_ssao: if ao_settings.ssao { 1.0 } else { 0.0 },
+        use_vertex_ao: if ao_settings.vertex_ao { 1.0 } else { 0.0 },
+        baked_light: if baked_light.enabled { baked_light.intensity } else { 0.0 }
     };
     state.ambient_light_pipeline.update(Some(&ambient_uniform));
 
@@ -376,7 +381,7 @@ fn state_render(
     ssao : Res<SSAOFiltered>
 ) {
         state.ambient_light_pipeline.draw(&mut encoder,
-                                         &[&gbuffer.diffuse, &gbuffer.normal, &gbuffer.position, &gbuffer.mr, &ssao.tex]
+                                         &[&gbuffer.diffuse, &gbuffer.normal, &gbuffer.position, &gbuffer.mr, &ssao.tex, &gbuffer.light]
                                          , &[&dir_light.tex]);
         
 
@@ -421,6 +426,8 @@ impl space_game::SchedulePlugin for StateSystem {
         app.add_system_to_stage(GlobalStageStep::PreRender, state_update);
         app.add_system_to_stage(GlobalStageStep::Render, state_render);
         app.insert_resource(state);
+        app.insert_resource(AmbientOcclusionSettings::default());
+        app.insert_resource(BakedLightSettings::default());
     }
 }
 
@@ -430,7 +437,10 @@ impl space_game::RenderPlugin for State {
 
         let ambient_uniform = AmbientLightUniform {
             color: self.ambient_light.color.into(),
-            cam_pos: game.scene.app.world.get_resource::<Camera>().unwrap().pos.coords.clone()
+            cam_pos: game.scene.app.world.get_resource::<Camera>().unwrap().pos.coords.clone(),
+            use_ssao: 1.0,
+            use_vertex_ao: 1.0,
+            baked_light: 1.0
         };
         self.ambient_light_pipeline.update(Some(&ambient_uniform));
     }
@@ -453,7 +463,7 @@ impl space_game::RenderPlugin for State {
         // game.scene.resources.get_mut::<GpuProfiler>().unwrap().begin_scope("Ambient", encoder, &self.render.device);
         // self.light_pipeline.draw(&self.render.device, encoder, &game.scene.world, &self.light_buffer, &gbuffer);
         self.ambient_light_pipeline.draw(&mut encoder,
-                                         &[&gbuffer.diffuse, &gbuffer.normal, &gbuffer.position, &gbuffer.mr, &game.scene.app.world.get_resource::<SSAOFiltered>().unwrap().tex]
+                                         &[&gbuffer.diffuse, &gbuffer.normal, &gbuffer.position, &gbuffer.mr, &game.scene.app.world.get_resource::<SSAOFiltered>().unwrap().tex, &gbuffer.light]
                                          , &[&game.scene.app.world.get_resource::<DirLightTexture>().unwrap().tex]);
         // game.scene.resources.get_mut::<GpuProfiler>().unwrap().end_scope(encoder);
 
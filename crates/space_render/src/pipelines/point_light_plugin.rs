@@ -9,6 +9,7 @@ use crate::pipelines::PointLightShadowPipeline;
 use space_assets::Material;
 use space_assets::Location;
 use crate::light::PointLight;
+use crate::culling::FrustumCulling;
 
 use space_core::ecs::*;
 
@@ -22,10 +23,11 @@ fn point_light_shadow(
     mesh_query : Query<(&Handle<GMesh>, &Material, &Location)>,
     light_query : Query<(&mut PointLight)>,
     mut encoder : ResMut<RenderCommands>,
-    mut meshes : ResMut<Assets<GMesh>>
+    mut meshes : ResMut<Assets<GMesh>>,
+    mut culling : ResMut<FrustumCulling>
 ) {
     // profiler.begin_scope("Point light shadow", encoder, &shadow_fill.render.device);
-    shadow_fill.draw(encoder.as_mut(), mesh_query, light_query, meshes);
+    shadow_fill.draw(encoder.as_mut(), mesh_query, light_query, meshes, culling.as_mut());
     // profiler.end_scope(encoder);
 }
 
@@ -4,7 +4,7 @@ use std::process::id;
 use bevy::prelude::{Handle, Assets, info};
 use wgpu::{Extent3d, Texture, TextureFormat, util::DeviceExt};
 use space_assets::*;
-use space_core::{RenderBase, app::App};
+use space_core::{Camera, Frustum, RenderBase, app::App};
 
 use space_game::*;
 use space_game::PluginName::Text;
@@ -12,6 +12,7 @@ use space_game::PluginName::Text;
 use space_core::ecs::*;
 
 use crate::AutoInstancing;
+use crate::culling::{world_bounds, FrustumCulling};
 
 use space_assets::mesh::*;
 
@@ -65,6 +66,8 @@ pub struct GFramebuffer {
     pub normal : TextureBundle,
     pub position : TextureBundle,
     pub mr : TextureBundle,
+    //baked voxel light
+    pub light : TextureBundle,
     pub depth : TextureBundle,
 }
 
@@ -107,6 +110,7 @@ impl GFramebuffer {
         let normal = TextureBundle::new(device, &noraml_desc, wgpu::FilterMode::Nearest);
         let position = TextureBundle::new(device, &pos_desc, wgpu::FilterMode::Nearest);
         let mr = TextureBundle::new(device, &color_desc, wgpu::FilterMode::Nearest);
+        let light = TextureBundle::new(device, &color_desc, wgpu::FilterMode::Nearest);
 
         let depth = TextureBundle::new(device, &wgpu::TextureDescriptor {
             label: Some("gbuffer depth"),
@@ -123,7 +127,8 @@ impl GFramebuffer {
             normal,
             position,
             depth,
-            mr
+            mr,
+            light
         }
     }
 
@@ -181,6 +186,19 @@ impl GFramebuffer {
                     }),
                     store: true,
                 },
+            }),
+            Some(wgpu::RenderPassColorAttachment {
+                view: &self.light.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color {
+                        r: 0.0,
+                        g: 0.0,
+                        b: 0.0,
+                        a: 0.0,
+                    }),
+                    store: true,
+                },
             }),],
             depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                 view : &self.depth.view,
@@ -226,10 +244,13 @@ fn gbuffer_filling(
     mut assets : ResMut<SpaceAssetServer>,
     mut encoder : ResMut<RenderCommands>,
     mut materials : ResMut<Assets<Material>>,
-    mut meshes : ResMut<Assets<GMesh>>) {
+    mut meshes : ResMut<Assets<GMesh>>,
+    camera : Res<Camera>,
+    mut culling : ResMut<FrustumCulling>) {
 
     // profiler.begin_scope("GBuffer fill", encoder, &fill.render.device);
-    fill.draw(query, query_instanced, gbuffer, assets, encoder, materials, meshes);
+    let frustum = Frustum::from_uniform(&camera.build_uniform());
+    fill.draw(query, query_instanced, gbuffer, assets, encoder, materials, meshes, &frustum, culling.as_mut());
     // profiler.end_scope(encoder);
 }
 
@@ -393,6 +414,11 @@ impl GBufferFill {
                     blend : None,
                     write_mask : wgpu::ColorWrites::ALL
                 }),
+                Some(wgpu::ColorTargetState {
+                    format : wgpu::TextureFormat::Rgba8Unorm,
+                    blend : None,
+                    write_mask : wgpu::ColorWrites::ALL
+                }),
                 Some(wgpu::ColorTargetState {
                     format : wgpu::TextureFormat::Rgba8Unorm,
                     blend : None,
@@ -442,8 +468,11 @@ impl GBufferFill {
                 mut assets : ResMut<SpaceAssetServer>,
                 mut encoder_phantom : ResMut<RenderCommands>,
                 mut materials : ResMut<Assets<Material>>,
-                mut meshes : ResMut<Assets<GMesh>>) {
+                mut meshes : ResMut<Assets<GMesh>>,
+                frustum : &Frustum,
+                culling : &mut FrustumCulling) {
 
+        culling.gbuffer = Default::default();
         let mut encoder = self.render.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
         {
             let mut render_pass = gbuffer.spawn_renderpass(&mut encoder);
@@ -455,6 +484,11 @@ impl GBufferFill {
             for (mesh_ptr, mut material_ptr, loc) in &mut query {
                 let mut material = materials.get(&material_ptr).unwrap();
                 let mut mesh = meshes.get(mesh_ptr).unwrap();
+                let visible = culling.is_visible(frustum, &world_bounds(mesh, loc));
+                culling.gbuffer.count(visible);
+                if !visible {
+                    continue;
+                }
 
                 render_pass.set_bind_group(1, material.gbuffer_bind.as_ref().unwrap(), &[]);
                 render_pass.set_vertex_buffer(0, mesh.vertex.slice(..));
@@ -466,6 +500,11 @@ impl GBufferFill {
             for (mesh_ptr, mut material_ptr, loc) in &mut query_instanced {
                 let mut material = materials.get(&material_ptr).unwrap();
                 let mut mesh = meshes.get(mesh_ptr).unwrap();
+                let visible = loc.bounds(&mesh.bounds).is_some_and(|bounds| culling.is_visible(frustum, &bounds));
+                culling.gbuffer.count(visible);
+                if !visible {
+                    continue;
+                }
 
                 render_pass.set_bind_group(1, material.gbuffer_bind.as_ref().unwrap(), &[]);
                 render_pass.set_vertex_buffer(0, mesh.vertex.slice(..));
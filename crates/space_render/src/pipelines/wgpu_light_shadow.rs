@@ -4,7 +4,8 @@ use bevy::prelude::{Handle, Assets};
 use wgpu::{Extent3d, TextureDimension};
 use crate::light::{PointLight, PointLightShadow};
 use space_shaders::*;
-use space_core::RenderBase;
+use space_core::{Frustum, RenderBase};
+use crate::culling::{world_bounds, FrustumCulling};
 use space_assets::*;
 use space_core::ecs::*;
 
@@ -99,7 +100,10 @@ impl PointLightShadowPipeline {
         encoder : &'a mut wgpu::CommandEncoder,
         mut mesh_query : Query<(&Handle<GMesh>, &Material, &Location)>,
         mut light_query : Query<(&mut PointLight)>,
-        mut meshes : ResMut<Assets<GMesh>>) {
+        mut meshes : ResMut<Assets<GMesh>>,
+        culling : &mut FrustumCulling) {
+
+        culling.shadow = Default::default();
 
         for mut light in light_query.iter_mut() {
             if let Some(shadow) = light.shadow.as_mut() {
@@ -130,7 +134,7 @@ impl PointLightShadowPipeline {
             if let Some(shadow) = light.shadow.as_ref() {
                 for camera_idx in 0..6 {
                     // profiler.begin_scope("Shadow pass", encoder, &self.render.device);
-                    self.shadow_draw(shadow, camera_idx, &mut mesh_query, encoder, &mut meshes);
+                    self.shadow_draw(shadow, camera_idx, &mut mesh_query, encoder, &mut meshes, culling);
                     // profiler.end_scope(encoder);
                 }
             }
@@ -143,7 +147,21 @@ impl PointLightShadowPipeline {
                    idx : usize,
                    query : &mut Query<(&Handle<GMesh>, &Material, &Location)>,
                    encoder : &mut wgpu::CommandEncoder,
-                   meshes : &mut ResMut<Assets<GMesh>>) {
+                   meshes : &mut ResMut<Assets<GMesh>>,
+                   culling : &mut FrustumCulling) {
+
+        //cube face sees its part of space up to shadow distance
+        //view is built like in light_camera_shadow.wgsl
+        let camera = &shadow.cameras_unforms[idx];
+        let pos = nalgebra::Point3::from(camera.pos);
+        let right = camera.frw.cross(&camera.up);
+        let rotation = nalgebra::Matrix4::new(
+            right.x, right.y, right.z, 0.0,
+            camera.up.x, camera.up.y, camera.up.z, 0.0,
+            camera.frw.x, camera.frw.y, camera.frw.z, 0.0,
+            0.0, 0.0, 0.0, 1.0);
+        let view = rotation * nalgebra::Matrix4::new_translation(&-camera.pos);
+        let frustum = Frustum::from_matrix(&(camera.proj * view));
 
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Point light renderpass"),
@@ -162,6 +180,12 @@ impl PointLightShadowPipeline {
 
         for (mesh_ptr, material, loc) in query.iter() {
             let mesh = meshes.get(mesh_ptr).unwrap();
+            let bounds = world_bounds(mesh, loc);
+            let visible = culling.is_visible(&frustum, &bounds) && (!culling.enabled || bounds.intersects_sphere(&pos, camera.far));
+            culling.shadow.count(visible);
+            if !visible {
+                continue;
+            }
 
             render_pass.set_vertex_buffer(0, mesh.vertex.slice(..));
             render_pass.set_vertex_buffer(1, loc.buffer.slice(..));
@@ -0,0 +1,73 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use space_voxel::palette_chunk::PaletteChunk;
+use space_voxel::solid_voxel_map::{Pos3i, Vec3i, VoxelChunk};
+
+const SIZE : i32 = 16;
+
+fn filled_chunk(fill_percent : u64, kinds : u64) -> VoxelChunk<u16> {
+    let mut chunk = VoxelChunk::new(Pos3i::new(0, 0, 0), Vec3i::new(SIZE, SIZE, SIZE));
+    let mut rng = StdRng::seed_from_u64(1);
+    for val in chunk.data.iter_mut() {
+        if rng.gen_range(0..100) < fill_percent {
+            *val = rng.gen_range(0..kinds) as u16 + 1;
+        }
+    }
+    chunk
+}
+
+fn coords() -> Vec<(i32, i32, i32)> {
+    let mut rng = StdRng::seed_from_u64(2);
+    (0..4096).map(|_| (rng.gen_range(0..SIZE), rng.gen_range(0..SIZE), rng.gen_range(0..SIZE))).collect()
+}
+
+fn storage_bench(c : &mut Criterion) {
+    let scenes = [("empty", 0, 1), ("sparse", 2, 4), ("station", 30, 16), ("noisy", 100, 200)];
+    let coords = coords();
+
+    for (name, fill, kinds) in scenes {
+        let dense = filled_chunk(fill, kinds);
+        let packed = PaletteChunk::from_chunk(&dense);
+
+        println!("{}: dense {} bytes, palette {} bytes ({} entries)",
+            name, dense.memory_usage(), packed.memory_usage(), packed.palette_len());
+
+        c.bench_function(&format!("dense get {}", name), |b| b.iter(|| {
+            let mut sum = 0u32;
+            for (x, y, z) in &coords {
+                sum += *dense.get(*x, *y, *z) as u32;
+            }
+            black_box(sum)
+        }));
+
+        c.bench_function(&format!("palette get {}", name), |b| b.iter(|| {
+            let mut sum = 0u32;
+            for (x, y, z) in &coords {
+                sum += *packed.get(*x, *y, *z) as u32;
+            }
+            black_box(sum)
+        }));
+
+        c.bench_function(&format!("dense set {}", name), |b| {
+            let mut chunk = filled_chunk(fill, kinds);
+            b.iter(|| {
+                for (i, (x, y, z)) in coords.iter().enumerate() {
+                    *chunk.get_mut(*x, *y, *z) = (i % 8) as u16;
+                }
+            })
+        });
+
+        c.bench_function(&format!("palette set {}", name), |b| {
+            let mut chunk = PaletteChunk::from_chunk(&dense);
+            b.iter(|| {
+                for (i, (x, y, z)) in coords.iter().enumerate() {
+                    chunk.set(*x, *y, *z, (i % 8) as u16);
+                }
+            })
+        });
+    }
+}
+
+criterion_group!(benches, storage_bench);
+criterion_main!(benches);
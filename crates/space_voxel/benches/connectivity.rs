@@ -0,0 +1,64 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use space_voxel::connectivity::{flood_fill, Connectivity, VoxelComponents};
+use space_voxel::solid_voxel_map::{Pos3i, Vec3i, VoxelMap};
+
+const SIZE : i32 = 64;
+
+fn empty(v : &u8) -> bool {
+    *v == 0
+}
+
+fn same(a : &u8, b : &u8) -> bool {
+    a == b
+}
+
+//grid of 8x8x8 rooms with a door in some of the walls
+fn station() -> VoxelMap<u8> {
+    let mut map = VoxelMap::new(1.0, [16, 16, 16].into());
+    map.allocate_box(&Pos3i::new(0, 0, 0), &Vec3i::repeat(SIZE));
+    let mut rng = StdRng::seed_from_u64(1);
+    for i in (0..SIZE).step_by(8) {
+        map.fill_box(&Pos3i::new(i, 0, 0), &Vec3i::new(1, SIZE, SIZE), &1);
+        map.fill_box(&Pos3i::new(0, i, 0), &Vec3i::new(SIZE, 1, SIZE), &1);
+        map.fill_box(&Pos3i::new(0, 0, i), &Vec3i::new(SIZE, SIZE, 1), &1);
+    }
+    for _ in 0..200 {
+        let door = Pos3i::new(
+            rng.gen_range(0..SIZE) / 8 * 8,
+            rng.gen_range(0..SIZE) / 8 * 8 + 4,
+            rng.gen_range(0..SIZE) / 8 * 8 + 4);
+        map.set_voxel(&door, 0);
+    }
+    map
+}
+
+fn connectivity_bench(c : &mut Criterion) {
+    let map = station();
+
+    for (name, connectivity) in [("6", Connectivity::Faces), ("26", Connectivity::All)] {
+        c.bench_function(&format!("flood fill room {}", name), |b| b.iter(|| {
+            black_box(flood_fill(&map, &Pos3i::new(4, 4, 4), connectivity, same).len())
+        }));
+
+        c.bench_function(&format!("label components {}", name), |b| b.iter(|| {
+            black_box(VoxelComponents::build(&map, connectivity, empty, same).component_count())
+        }));
+
+        //toggle one door, every toggle merges or splits two rooms
+        c.bench_function(&format!("incremental door {}", name), |b| {
+            let mut map = station();
+            let mut components = VoxelComponents::build(&map, connectivity, empty, same);
+            let door = Pos3i::new(8, 4, 4);
+            b.iter(|| {
+                let val = 1 - *map.get_voxel(&door).unwrap();
+                map.set_voxel(&door, val);
+                black_box(components.update(&map, &door, empty, same))
+            })
+        });
+    }
+}
+
+criterion_group!(benches, connectivity_bench);
+criterion_main!(benches);
@@ -0,0 +1,113 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use space_voxel::octree::VoxelOctree;
+use space_voxel::solid_voxel_map::{Pos3i, Ray, Vec3, Vec3i, VoxelMap};
+
+//half size of sector in cells
+const SECTOR : i32 = 2048;
+
+fn rand_pos(rng : &mut StdRng, half : i32) -> Pos3i {
+    Pos3i::new(rng.gen_range(-half..half), rng.gen_range(-half..half), rng.gen_range(-half..half))
+}
+
+//vacuum with a few hollow stations and scattered debris
+fn sector(stations : usize) -> VoxelMap<u16> {
+    let mut map = VoxelMap::new(0.5, [16, 16, 16].into());
+    let mut rng = StdRng::seed_from_u64(1);
+    for i in 0..stations {
+        let min = rand_pos(&mut rng, SECTOR - 64);
+        let size = Vec3i::new(24 + (i as i32 % 3) * 8, 16, 40);
+        map.fill_box(&min, &size, &1);
+        map.clear_box(&(min + Vec3i::new(1, 1, 1)), &(size - Vec3i::new(2, 2, 2)));
+    }
+    for _ in 0..stations * 20 {
+        map.set_voxel(&rand_pos(&mut rng, SECTOR), 2);
+    }
+    map
+}
+
+fn map_memory(map : &VoxelMap<u16>) -> usize {
+    map.map.values().map(|c| c.memory_usage()).sum()
+}
+
+fn sparse_bench(c : &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(2);
+    let coords : Vec<Pos3i> = (0..4096).map(|_| rand_pos(&mut rng, SECTOR)).collect();
+    let rays : Vec<Ray> = (0..256).map(|_| {
+        let pos = rand_pos(&mut rng, SECTOR / 2).cast::<f32>() * 0.5;
+        let dir = rand_pos(&mut rng, 100).cast::<f32>().coords;
+        Ray { pos, dir : dir + Vec3::new(0.0, 0.01, 0.0) }
+    }).collect();
+
+    for stations in [4, 32] {
+        let map = sector(stations);
+        let tree = VoxelOctree::from_map(&map);
+        println!("{} stations: chunk map {} bytes in {} chunks, octree {} bytes in {} nodes",
+            stations, map_memory(&map), map.map.len(), tree.memory_usage(), tree.node_count());
+
+        c.bench_function(&format!("chunk map get {}", stations), |b| b.iter(|| {
+            let mut sum = 0u32;
+            for pos in &coords {
+                sum += map.get_voxel(pos).cloned().unwrap_or_default() as u32;
+            }
+            black_box(sum)
+        }));
+
+        c.bench_function(&format!("octree get {}", stations), |b| b.iter(|| {
+            let mut sum = 0u32;
+            for pos in &coords {
+                sum += tree.get_voxel(pos).cloned().unwrap_or_default() as u32;
+            }
+            black_box(sum)
+        }));
+
+        c.bench_function(&format!("chunk map set {}", stations), |b| {
+            let mut map = sector(stations);
+            b.iter(|| {
+                for (i, pos) in coords.iter().enumerate() {
+                    map.set_voxel(pos, (i % 2) as u16);
+                }
+            })
+        });
+
+        c.bench_function(&format!("octree set {}", stations), |b| {
+            let mut tree = VoxelOctree::from_map(&map);
+            b.iter(|| {
+                for (i, pos) in coords.iter().enumerate() {
+                    tree.set_voxel(pos, (i % 2) as u16);
+                }
+            })
+        });
+
+        let min = Pos3i::new(-SECTOR, -SECTOR, -SECTOR) / 2;
+        let size = Vec3i::repeat(SECTOR);
+        c.bench_function(&format!("chunk map region {}", stations), |b| b.iter(|| {
+            black_box(map.iter_region(&min, &size).count())
+        }));
+
+        c.bench_function(&format!("octree region {}", stations), |b| b.iter(|| {
+            black_box(tree.iter_region(&min, &size).count())
+        }));
+
+        c.bench_function(&format!("chunk map raycast {}", stations), |b| b.iter(|| {
+            rays.iter().filter(|ray| map.raycast(ray, 500.0, |v| *v != 0).is_some()).count()
+        }));
+
+        c.bench_function(&format!("octree raycast {}", stations), |b| b.iter(|| {
+            rays.iter().filter(|ray| tree.raycast(ray, 500.0, |v| *v != 0).is_some()).count()
+        }));
+    }
+
+    let map = sector(32);
+    c.bench_function("octree from map", |b| b.iter(|| {
+        black_box(VoxelOctree::from_map(&map).node_count())
+    }));
+    let tree = VoxelOctree::from_map(&map);
+    c.bench_function("octree to map", |b| b.iter(|| {
+        black_box(tree.to_map([16, 16, 16].into()).map.len())
+    }));
+}
+
+criterion_group!(benches, sparse_bench);
+criterion_main!(benches);
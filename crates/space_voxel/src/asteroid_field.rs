@@ -0,0 +1,377 @@
+use std::sync::Arc;
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin};
+use space_core::{Pos3, Pos3i, Vec3, Vec3i};
+use crate::smooth_voxel_map::{DensityVoxel, SURFACE_LEVEL};
+use crate::solid_voxel_map::VoxelChunk;
+use crate::streaming::ChunkGenerator;
+
+#[derive(Clone, Debug)]
+pub struct OreVein {
+    pub material : u8,
+    //noise frequency in world units
+    pub frequency : f32,
+    //noise value above threshold turns rock into ore, noise is in -1..1
+    pub threshold : f32
+}
+
+#[derive(Clone, Debug)]
+pub struct AsteroidFieldSettings {
+    pub seed : u32,
+    //world is split into cells, each cell holds at most one asteroid
+    pub cell_size : f32,
+    //chance of a cell to contain an asteroid
+    pub fill_chance : f32,
+    pub min_radius : f32,
+    pub max_radius : f32,
+    //surface displacement as part of radius
+    pub roughness : f32,
+    pub surface_frequency : f32,
+    pub rock_material : u8,
+    pub veins : Vec<OreVein>,
+    pub cavity_frequency : f32,
+    pub cavity_threshold : f32,
+    pub debris_per_asteroid : u32,
+    //empty sphere around world origin, free space for the station
+    pub clear_radius : f32
+}
+
+impl Default for AsteroidFieldSettings {
+    fn default() -> Self {
+        Self {
+            seed : 0,
+            cell_size : 48.0,
+            fill_chance : 0.35,
+            min_radius : 4.0,
+            max_radius : 14.0,
+            roughness : 0.3,
+            surface_frequency : 0.15,
+            rock_material : 0,
+            veins : vec![
+                OreVein {
+                    material : 1,
+                    frequency : 0.2,
+                    threshold : 0.55
+                },
+                OreVein {
+                    material : 2,
+                    frequency : 0.35,
+                    threshold : 0.7
+                }
+            ],
+            cavity_frequency : 0.12,
+            cavity_threshold : 0.45,
+            debris_per_asteroid : 6,
+            clear_radius : 32.0
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Asteroid {
+    pub cell : Pos3i,
+    pub center : Pos3,
+    pub radius : f32,
+    //bounding radius with surface displacement
+    pub extent : f32
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DebrisSpawn {
+    pub pos : Pos3,
+    //euler angles, same as Location
+    pub rotation : Vec3,
+    pub scale : f32,
+    pub kind : u32
+}
+
+//splitmix64 finalizer, stable between runs and platforms
+fn mix(mut h : u64) -> u64 {
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+    h ^ (h >> 31)
+}
+
+fn cell_hash(seed : u32, cell : &Pos3i, salt : u64) -> u64 {
+    let mut h = mix(seed as u64 ^ 0x9e3779b97f4a7c15);
+    h = mix(h ^ cell.x as u32 as u64);
+    h = mix(h ^ ((cell.y as u32 as u64) << 32));
+    h = mix(h ^ cell.z as u32 as u64 ^ (salt << 40));
+    h
+}
+
+//0..1 from hash bits
+fn unit(h : u64, idx : u32) -> f32 {
+    (mix(h.wrapping_add(idx as u64)) >> 40) as f32 / (1u64 << 24) as f32
+}
+
+pub struct AsteroidField {
+    pub settings : AsteroidFieldSettings,
+    surface : Fbm<Perlin>,
+    cavities : Perlin,
+    veins : Vec<Perlin>
+}
+
+impl AsteroidField {
+    pub fn new(settings : AsteroidFieldSettings) -> AsteroidField {
+        let surface = Fbm::<Perlin>::new(settings.seed)
+            .set_octaves(4)
+            .set_frequency(settings.surface_frequency as f64);
+        let cavities = Perlin::new(settings.seed.wrapping_add(1));
+        let veins = (0..settings.veins.len())
+            .map(|i| Perlin::new(settings.seed.wrapping_add(2 + i as u32)))
+            .collect();
+        AsteroidField {
+            settings,
+            surface,
+            cavities,
+            veins
+        }
+    }
+
+    pub fn asteroid_in_cell(&self, cell : &Pos3i) -> Option<Asteroid> {
+        let s = &self.settings;
+        let h = cell_hash(s.seed, cell, 0);
+        if unit(h, 0) >= s.fill_chance {
+            return None;
+        }
+
+        //whole asteroid stays inside its cell
+        let half = s.cell_size * 0.5;
+        let radius = (s.min_radius + (s.max_radius - s.min_radius) * unit(h, 1))
+            .min(half / (1.0 + s.roughness));
+        let extent = radius * (1.0 + s.roughness);
+        let margin = half - extent;
+        let center = Pos3::new(
+            (cell.x as f32 + 0.5) * s.cell_size + (unit(h, 2) * 2.0 - 1.0) * margin,
+            (cell.y as f32 + 0.5) * s.cell_size + (unit(h, 3) * 2.0 - 1.0) * margin,
+            (cell.z as f32 + 0.5) * s.cell_size + (unit(h, 4) * 2.0 - 1.0) * margin);
+
+        if center.coords.norm() < s.clear_radius + extent {
+            return None;
+        }
+
+        Some(Asteroid {
+            cell : *cell,
+            center,
+            radius,
+            extent
+        })
+    }
+
+    //asteroids whose bounds touch the box
+    pub fn asteroids_in_box(&self, min : &Pos3, max : &Pos3) -> Vec<Asteroid> {
+        let cell_size = self.settings.cell_size;
+        let first = (min.coords / cell_size).map(|v| v.floor() as i32);
+        let last = (max.coords / cell_size).map(|v| v.floor() as i32);
+
+        let mut res = vec![];
+        for z in first.z..=last.z {
+            for y in first.y..=last.y {
+                for x in first.x..=last.x {
+                    if let Some(asteroid) = self.asteroid_in_cell(&Pos3i::new(x, y, z)) {
+                        let closest = asteroid.center.coords.sup(&min.coords).inf(&max.coords);
+                        if (closest - asteroid.center.coords).norm() <= asteroid.extent {
+                            res.push(asteroid);
+                        }
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    pub fn sample(&self, asteroids : &[Asteroid], pos : &Pos3, voxel_size : f32) -> DensityVoxel {
+        let s = &self.settings;
+        let p = [pos.x as f64, pos.y as f64, pos.z as f64];
+
+        let mut density = 0.0f32;
+        for asteroid in asteroids {
+            let dist = (pos - asteroid.center).norm();
+            if dist > asteroid.extent + voxel_size {
+                continue;
+            }
+            let radius = asteroid.radius * (1.0 + s.roughness * self.surface.get(p).clamp(-1.0, 1.0) as f32);
+            density = density.max((SURFACE_LEVEL + (radius - dist) / voxel_size).clamp(0.0, 1.0));
+        }
+        if density <= 0.0 {
+            return DensityVoxel::default();
+        }
+
+        let f = s.cavity_frequency as f64;
+        let cavity = self.cavities.get([p[0] * f, p[1] * f, p[2] * f]) as f32;
+        if cavity > s.cavity_threshold {
+            let carve = ((cavity - s.cavity_threshold) / (1.0 - s.cavity_threshold) * 4.0).clamp(0.0, 1.0);
+            density *= 1.0 - carve;
+        }
+
+        let mut material = s.rock_material;
+        for (vein, noise) in s.veins.iter().zip(&self.veins) {
+            let f = vein.frequency as f64;
+            if noise.get([p[0] * f, p[1] * f, p[2] * f]) as f32 > vein.threshold {
+                material = vein.material;
+                break;
+            }
+        }
+
+        DensityVoxel::new(density, material)
+    }
+
+    //chunks next to a surface are kept even when empty, they own its edges while meshing
+    pub fn generate_chunk(&self, origin : &Pos3i, size : &Vec3i, voxel_size : f32) -> Option<VoxelChunk<DensityVoxel>> {
+        let margin = Vec3::repeat(voxel_size * 2.0);
+        let min = Pos3::from(origin.coords.cast::<f32>() * voxel_size) - margin;
+        let max = Pos3::from((origin.coords + size).cast::<f32>() * voxel_size) + margin;
+        let asteroids = self.asteroids_in_box(&min, &max);
+        if asteroids.is_empty() {
+            return None;
+        }
+
+        let mut chunk = VoxelChunk::new(*origin, *size);
+        for z in 0..size.z {
+            for y in 0..size.y {
+                for x in 0..size.x {
+                    let pos = Pos3::from((origin.coords + Vec3i::new(x, y, z)).cast::<f32>() * voxel_size);
+                    *chunk.get_mut(x, y, z) = self.sample(&asteroids, &pos, voxel_size);
+                }
+            }
+        }
+        Some(chunk)
+    }
+
+    //debris belongs to the chunk that holds asteroid center, so every piece spawns once
+    pub fn debris_in_chunk(&self, origin : &Pos3i, size : &Vec3i, voxel_size : f32) -> Vec<DebrisSpawn> {
+        let min = Pos3::from(origin.coords.cast::<f32>() * voxel_size);
+        let max = Pos3::from((origin.coords + size).cast::<f32>() * voxel_size);
+
+        let mut res = vec![];
+        for asteroid in self.asteroids_in_box(&min, &max) {
+            let c = asteroid.center;
+            let inside = (0..3).all(|i| c[i] >= min[i] && c[i] < max[i]);
+            if !inside {
+                continue;
+            }
+
+            for i in 0..self.settings.debris_per_asteroid {
+                let h = cell_hash(self.settings.seed, &asteroid.cell, 1 + i as u64);
+                let y = unit(h, 0) * 2.0 - 1.0;
+                let phi = unit(h, 1) * std::f32::consts::TAU;
+                let r = (1.0 - y * y).sqrt();
+                let dir = Vec3::new(phi.cos() * r, y, phi.sin() * r);
+                let dist = asteroid.extent * (1.2 + unit(h, 2) * 0.8);
+
+                res.push(DebrisSpawn {
+                    pos : c + dir * dist,
+                    rotation : Vec3::new(unit(h, 3), unit(h, 4), unit(h, 5)) * std::f32::consts::TAU,
+                    scale : asteroid.radius * (0.05 + unit(h, 6) * 0.1),
+                    kind : (h % 4) as u32
+                });
+            }
+        }
+        res
+    }
+
+    pub fn chunk_generator(field : Arc<AsteroidField>, voxel_size : f32) -> ChunkGenerator<DensityVoxel> {
+        Arc::new(move |origin, size| field.generate_chunk(origin, size, voxel_size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(seed : u32) -> AsteroidField {
+        AsteroidField::new(AsteroidFieldSettings {
+            seed,
+            fill_chance : 1.0,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn deterministic_and_seeded() {
+        let a = field(7);
+        let b = field(7);
+        let c = field(8);
+        let cell = Pos3i::new(3, -2, 5);
+
+        assert_eq!(a.asteroid_in_cell(&cell), b.asteroid_in_cell(&cell));
+        assert_ne!(a.asteroid_in_cell(&cell), c.asteroid_in_cell(&cell));
+
+        let asteroid = a.asteroid_in_cell(&cell).unwrap();
+        let origin = (asteroid.center / (0.5 * 16.0)).map(|v| v.floor() as i32 * 16);
+        let size = Vec3i::new(16, 16, 16);
+        assert_eq!(a.generate_chunk(&origin, &size, 0.5).unwrap().data,
+            b.generate_chunk(&origin, &size, 0.5).unwrap().data);
+        assert_eq!(a.debris_in_chunk(&origin, &size, 0.5), b.debris_in_chunk(&origin, &size, 0.5));
+    }
+
+    #[test]
+    fn asteroid_fits_cell() {
+        let f = field(1);
+        for x in -3..3 {
+            for z in -3..3 {
+                let cell = Pos3i::new(x, 4, z);
+                let asteroid = f.asteroid_in_cell(&cell).unwrap();
+                let cell_min = cell.coords.cast::<f32>() * f.settings.cell_size;
+                for i in 0..3 {
+                    assert!(asteroid.center[i] - asteroid.extent >= cell_min[i] - 1e-3);
+                    assert!(asteroid.center[i] + asteroid.extent <= cell_min[i] + f.settings.cell_size + 1e-3);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn solid_core_and_clear_zone() {
+        let f = field(3);
+        assert!(f.asteroid_in_cell(&Pos3i::new(0, 0, 0)).is_none());
+        assert!(f.generate_chunk(&Pos3i::new(0, 0, 0), &Vec3i::new(16, 16, 16), 1.0).is_none());
+
+        let asteroid = f.asteroid_in_cell(&Pos3i::new(5, 0, 0)).unwrap();
+        let asteroids = [asteroid.clone()];
+        let center = f.sample(&asteroids, &asteroid.center, 1.0);
+        let outside = f.sample(&asteroids, &(asteroid.center + Vec3::new(asteroid.extent + 2.0, 0.0, 0.0)), 1.0);
+        assert!(center.density > 0.0);
+        assert_eq!(outside, DensityVoxel::default());
+
+        let origin = (asteroid.center / 16.0).map(|v| v.floor() as i32 * 16);
+        let debris = f.debris_in_chunk(&origin, &Vec3i::new(16, 16, 16), 1.0);
+        assert_eq!(debris.len(), f.settings.debris_per_asteroid as usize);
+        for d in &debris {
+            assert!((d.pos - asteroid.center).norm() > asteroid.extent);
+        }
+        //neighbour chunk does not repeat it
+        assert!(f.debris_in_chunk(&(origin + Vec3i::new(16, 0, 0)), &Vec3i::new(16, 16, 16), 1.0).is_empty());
+    }
+
+    #[test]
+    fn ore_and_cavities() {
+        let f = field(11);
+        let mut materials = [0usize; 3];
+        let mut carved = 0;
+        for cell_x in 2..6 {
+            let asteroid = f.asteroid_in_cell(&Pos3i::new(cell_x, 0, 0)).unwrap();
+            let asteroids = [asteroid.clone()];
+            let r = asteroid.radius * (1.0 - f.settings.roughness) * 0.9;
+            let steps = 12;
+            for x in -steps..=steps {
+                for y in -steps..=steps {
+                    for z in -steps..=steps {
+                        let offset = Vec3::new(x as f32, y as f32, z as f32) / steps as f32 * r;
+                        if offset.norm() > r {
+                            continue;
+                        }
+                        let v = f.sample(&asteroids, &(asteroid.center + offset), 0.5);
+                        materials[v.material as usize] += 1;
+                        if v.density < 1.0 {
+                            carved += 1;
+                        }
+                    }
+                }
+            }
+        }
+        assert!(materials[0] > materials[1]);
+        assert!(materials[1] > 0);
+        assert!(carved > 0);
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::VecDeque;
+use space_core::bevy::utils::{HashMap, HashSet};
+use space_core::{Pos3i, Vec3i};
+use crate::solid_voxel_map::VoxelMap;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MapChange<T> {
+    Voxel {
+        pos : Pos3i,
+        old : T,
+        new : T
+    },
+    //whole chunk appeared or vanished, e.g. streamed from disk
+    ChunkLoaded(Pos3i),
+    ChunkUnloaded(Pos3i)
+}
+
+//chunk content changed, or a neighbour cell it sees through its border
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ChunkChangedEvent {
+    pub origin : Pos3i
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ChangeCursor(u32);
+
+//changes are kept until every consumer has drained them,
+//without consumers nothing is recorded
+pub struct ChangeLog<T> {
+    entries : VecDeque<MapChange<T>>,
+    //sequence number of entries[0]
+    first : u64,
+    cursors : HashMap<ChangeCursor, u64>,
+    next_cursor : u32
+}
+
+impl<T> Default for ChangeLog<T> {
+    fn default() -> Self {
+        Self {
+            entries : VecDeque::new(),
+            first : 0,
+            cursors : HashMap::new(),
+            next_cursor : 0
+        }
+    }
+}
+
+impl<T> ChangeLog<T>
+    where T : Clone {
+
+    fn end(&self) -> u64 {
+        self.first + self.entries.len() as u64
+    }
+
+    fn trim(&mut self) {
+        let min = self.cursors.values().min().cloned().unwrap_or(self.end());
+        while self.first < min {
+            self.entries.pop_front();
+            self.first += 1;
+        }
+    }
+
+    //new consumer sees changes made after this call
+    pub fn subscribe(&mut self) -> ChangeCursor {
+        let cursor = ChangeCursor(self.next_cursor);
+        self.next_cursor += 1;
+        self.cursors.insert(cursor, self.end());
+        cursor
+    }
+
+    pub fn unsubscribe(&mut self, cursor : ChangeCursor) {
+        self.cursors.remove(&cursor);
+        self.trim();
+    }
+
+    pub fn push(&mut self, change : MapChange<T>) {
+        if !self.cursors.is_empty() {
+            self.entries.push_back(change);
+        }
+    }
+
+    pub fn pending(&self, cursor : ChangeCursor) -> usize {
+        self.cursors.get(&cursor).map_or(0, |pos| (self.end() - pos) as usize)
+    }
+
+    pub fn drain(&mut self, cursor : ChangeCursor) -> Vec<MapChange<T>> {
+        let end = self.end();
+        let Some(pos) = self.cursors.insert(cursor, end) else {
+            self.cursors.remove(&cursor);
+            return vec![];
+        };
+        let res = self.entries.range((pos - self.first) as usize..).cloned().collect();
+        self.trim();
+        res
+    }
+}
+
+impl<T> VoxelMap<T>
+    where T : Default + Clone {
+
+    //chunk of pos and existing neighbours that see it through their borders
+    pub fn chunks_seeing(&self, pos : &Pos3i) -> Vec<Pos3i> {
+        let origin = self.get_origin(pos);
+        let mut res = vec![origin];
+
+        let lp = pos - origin;
+        let mut shifts = [[0; 3]; 3];
+        let mut counts = [1; 3];
+        for i in 0..3 {
+            if lp[i] == 0 {
+                shifts[i][1] = -self.chunk_size[i];
+                counts[i] = 2;
+            } else if lp[i] == self.chunk_size[i] - 1 {
+                shifts[i][1] = self.chunk_size[i];
+                counts[i] = 2;
+            }
+        }
+
+        for dz in &shifts[2][..counts[2]] {
+            for dy in &shifts[1][..counts[1]] {
+                for dx in &shifts[0][..counts[0]] {
+                    let neighbour = origin + Vec3i::new(*dx, *dy, *dz);
+                    if neighbour != origin && self.map.contains_key(&neighbour) {
+                        res.push(neighbour);
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    //chunks that have to be refreshed after changes, e.g. remeshed
+    pub fn touched_chunks(&self, changes : &[MapChange<T>]) -> HashSet<Pos3i> {
+        let mut res = HashSet::new();
+        for change in changes {
+            match change {
+                MapChange::Voxel { pos, .. } => {
+                    res.extend(self.chunks_seeing(pos));
+                }
+                MapChange::ChunkLoaded(origin) | MapChange::ChunkUnloaded(origin) => {
+                    res.insert(*origin);
+                    for z in -1..=1 {
+                        for y in -1..=1 {
+                            for x in -1..=1 {
+                                let neighbour = origin + self.chunk_size.component_mul(&Vec3i::new(x, y, z));
+                                if self.map.contains_key(&neighbour) {
+                                    res.insert(neighbour);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    pub fn chunk_events(&self, changes : &[MapChange<T>]) -> Vec<ChunkChangedEvent> {
+        let mut origins : Vec<Pos3i> = self.touched_chunks(changes).into_iter().collect();
+        origins.sort_by_key(|p| (p.x, p.y, p.z));
+        origins.into_iter().map(|origin| ChunkChangedEvent { origin }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_map() -> VoxelMap<i32> {
+        VoxelMap::new(1.0, [4, 4, 4].into())
+    }
+
+    #[test]
+    fn records_old_and_new() {
+        let mut map = new_map();
+        map.set_voxel(&Pos3i::new(1, 1, 1), 5);
+
+        let cursor = map.changes.subscribe();
+        map.set_voxel(&Pos3i::new(1, 1, 1), 6);
+        //same value is not a change
+        map.set_voxel(&Pos3i::new(1, 1, 1), 6);
+        assert_eq!(map.get_voxel(&Pos3i::new(1, 1, 1)), Some(&6));
+
+        assert_eq!(map.changes.drain(cursor), vec![MapChange::Voxel {
+            pos : Pos3i::new(1, 1, 1),
+            old : 5,
+            new : 6
+        }]);
+        assert!(map.changes.drain(cursor).is_empty());
+    }
+
+    #[test]
+    fn reads_are_not_changes() {
+        let mut map = new_map();
+        let cursor = map.changes.subscribe();
+        assert_eq!(map.get_voxel(&Pos3i::new(0, 0, 0)), None);
+        map.update_voxel(&Pos3i::new(0, 0, 0), |_| {});
+        assert_eq!(map.changes.pending(cursor), 0);
+        assert!(map.map.is_empty());
+    }
+
+    #[test]
+    fn independent_consumers() {
+        let mut map = new_map();
+        let mesher = map.changes.subscribe();
+        map.set_voxel(&Pos3i::new(0, 0, 0), 1);
+        let network = map.changes.subscribe();
+        map.set_voxel(&Pos3i::new(0, 0, 1), 2);
+
+        assert_eq!(map.changes.pending(mesher), 2);
+        assert_eq!(map.changes.pending(network), 1);
+        assert_eq!(map.changes.drain(mesher).len(), 2);
+        assert_eq!(map.changes.drain(network).len(), 1);
+
+        map.set_voxel(&Pos3i::new(0, 0, 2), 3);
+        map.changes.unsubscribe(mesher);
+        assert_eq!(map.changes.drain(mesher).len(), 0);
+        assert_eq!(map.changes.drain(network).len(), 1);
+        assert!(map.changes.entries.is_empty());
+    }
+
+    #[test]
+    fn border_changes_touch_neighbours() {
+        let mut map = new_map();
+        map.fill_box(&Pos3i::new(0, 0, 0), &Vec3i::new(8, 4, 4), &1);
+
+        let cursor = map.changes.subscribe();
+        map.set_voxel(&Pos3i::new(4, 1, 1), 0);
+        map.set_voxel(&Pos3i::new(6, 1, 1), 0);
+        let changes = map.changes.drain(cursor);
+        let events = map.chunk_events(&changes);
+        assert_eq!(events, vec![
+            ChunkChangedEvent { origin : Pos3i::new(0, 0, 0) },
+            ChunkChangedEvent { origin : Pos3i::new(4, 0, 0) }]);
+
+        let changes = vec![MapChange::ChunkUnloaded(Pos3i::new(8, 0, 0))];
+        assert_eq!(map.touched_chunks(&changes).len(), 2);
+    }
+}
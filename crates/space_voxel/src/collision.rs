@@ -0,0 +1,278 @@
+use space_core::{Aabb, Pos3i, Vec3, Vec3i};
+use crate::solid_voxel_map::VoxelMap;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SweepHit {
+    //part of motion done before contact, 0..1
+    pub toi : f32,
+    pub normal : Vec3i,
+    pub voxel : Pos3i
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SlideResult {
+    pub aabb : Aabb,
+    pub moved : Vec3,
+    //normals of walls touched during the move
+    pub contacts : Vec<Vec3i>
+}
+
+//time of impact and axis of moving box against static box, None if they don't meet during motion
+fn sweep_box(moving : &Aabb, motion : &Vec3, target : &Aabb) -> Option<(f32, usize)> {
+    //boxes that already overlap are ignored, so objects can leave walls they got stuck in
+    if moving.intersects(target) {
+        return None;
+    }
+
+    let mut t_enter = f32::NEG_INFINITY;
+    let mut t_exit = f32::INFINITY;
+    let mut axis = 0;
+    for i in 0..3 {
+        if motion[i] == 0.0 {
+            if moving.max[i] <= target.min[i] || moving.min[i] >= target.max[i] {
+                return None;
+            }
+            continue;
+        }
+        let (t0, t1) = if motion[i] > 0.0 {
+            ((target.min[i] - moving.max[i]) / motion[i], (target.max[i] - moving.min[i]) / motion[i])
+        } else {
+            ((target.max[i] - moving.min[i]) / motion[i], (target.min[i] - moving.max[i]) / motion[i])
+        };
+        if t0 > t_enter {
+            t_enter = t0;
+            axis = i;
+        }
+        t_exit = t_exit.min(t1);
+    }
+
+    //equal enter and exit is a graze along an edge
+    if t_enter >= t_exit || t_enter > 1.0 || t_exit <= 0.0 {
+        return None;
+    }
+    Some((t_enter.max(0.0), axis))
+}
+
+impl<T> VoxelMap<T>
+    where T : Default + Clone {
+
+    //voxel i covers [(i - 0.5) * voxel_size, (i + 0.5) * voxel_size], same as raycast
+    pub fn voxel_aabb(&self, voxel : &Pos3i) -> Aabb {
+        Aabb::from_center(&self.get_world_pos(voxel), &Vec3::repeat(self.voxel_size * 0.5))
+    }
+
+    //inclusive range of voxels that may intersect box
+    fn voxel_range(&self, aabb : &Aabb) -> (Pos3i, Pos3i) {
+        let min = aabb.min.coords / self.voxel_size + Vec3::repeat(0.5);
+        let max = aabb.max.coords / self.voxel_size + Vec3::repeat(0.5);
+        (Pos3i::from(min.map(|v| v.floor() as i32)), Pos3i::from(max.map(|v| v.ceil() as i32 - 1)))
+    }
+
+    pub fn overlapping_voxels<F>(&self, aabb : &Aabb, is_solid : F) -> Vec<Pos3i>
+        where F : Fn(&T) -> bool {
+
+        let (min, max) = self.voxel_range(aabb);
+        let mut res = vec![];
+        for z in min.z..=max.z {
+            for y in min.y..=max.y {
+                for x in min.x..=max.x {
+                    let voxel = Pos3i::new(x, y, z);
+                    match self.get_voxel(&voxel) {
+                        Some(val) if is_solid(val) && self.voxel_aabb(&voxel).intersects(aabb) => {
+                            res.push(voxel);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    pub fn overlaps_aabb<F>(&self, aabb : &Aabb, is_solid : F) -> bool
+        where F : Fn(&T) -> bool {
+        !self.overlapping_voxels(aabb, is_solid).is_empty()
+    }
+
+    //first solid voxel hit by box moving along motion
+    //motion is walked in steps of one voxel, so cost grows with distance, not its cube
+    pub fn sweep_aabb<F>(&self, aabb : &Aabb, motion : &Vec3, is_solid : F) -> Option<SweepHit>
+        where F : Fn(&T) -> bool {
+
+        if motion.norm_squared() == 0.0 {
+            return None;
+        }
+
+        let steps = (motion.amax() / self.voxel_size).ceil().max(1.0) as usize;
+        let mut from = *aabb;
+        for step in 1..=steps {
+            let to = aabb.translated(&(motion * (step as f32 / steps as f32)));
+            //voxel touched first during this step is in its range, later steps can only hit later
+            let (min, max) = self.voxel_range(&from.union(&to));
+            if let Some(hit) = self.sweep_range(aabb, motion, &min, &max, &is_solid) {
+                return Some(hit);
+            }
+            from = to;
+        }
+        None
+    }
+
+    fn sweep_range<F>(&self, aabb : &Aabb, motion : &Vec3, min : &Pos3i, max : &Pos3i, is_solid : &F) -> Option<SweepHit>
+        where F : Fn(&T) -> bool {
+
+        let mut best : Option<SweepHit> = None;
+        for z in min.z..=max.z {
+            for y in min.y..=max.y {
+                for x in min.x..=max.x {
+                    let voxel = Pos3i::new(x, y, z);
+                    match self.get_voxel(&voxel) {
+                        Some(val) if is_solid(val) => {}
+                        _ => continue
+                    }
+                    if let Some((toi, axis)) = sweep_box(aabb, motion, &self.voxel_aabb(&voxel)) {
+                        if best.as_ref().is_none_or(|b| toi < b.toi) {
+                            let mut normal = Vec3i::zeros();
+                            normal[axis] = if motion[axis] > 0.0 { -1 } else { 1 };
+                            best = Some(SweepHit {
+                                toi,
+                                normal,
+                                voxel
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        best
+    }
+
+    //moves box until contact, then keeps moving along the touched walls
+    pub fn move_and_slide<F>(&self, aabb : &Aabb, motion : &Vec3, is_solid : F) -> SlideResult
+        where F : Fn(&T) -> bool {
+
+        //gap left between box and wall, next sweep starts outside of it
+        let skin = self.voxel_size * 1e-3;
+        let start = aabb.min;
+        let mut aabb = *aabb;
+        let mut remaining = *motion;
+        let mut contacts = vec![];
+
+        //every contact removes one axis from motion
+        for _ in 0..3 {
+            if remaining.norm_squared() == 0.0 {
+                break;
+            }
+            match self.sweep_aabb(&aabb, &remaining, &is_solid) {
+                Some(hit) => {
+                    let normal = hit.normal.cast::<f32>();
+                    aabb = aabb.translated(&(remaining * hit.toi + normal * skin));
+                    remaining *= 1.0 - hit.toi;
+                    remaining -= normal * normal.dot(&remaining);
+                    contacts.push(hit.normal);
+                }
+                None => {
+                    aabb = aabb.translated(&remaining);
+                    break;
+                }
+            }
+        }
+
+        SlideResult {
+            moved : aabb.min - start,
+            aabb,
+            contacts
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use space_core::Pos3;
+    use super::*;
+
+    //floor at y = 0 and wall at x = 3, both one voxel thick
+    fn room() -> VoxelMap<i32> {
+        let mut map = VoxelMap::new(1.0, [4, 4, 4].into());
+        map.fill_box(&Pos3i::new(-5, 0, -5), &Vec3i::new(10, 1, 10), &1);
+        map.fill_box(&Pos3i::new(3, 1, -5), &Vec3i::new(1, 4, 10), &2);
+        map
+    }
+
+    fn unit_box(center : Pos3) -> Aabb {
+        Aabb::from_center(&center, &Vec3::repeat(0.4))
+    }
+
+    #[test]
+    fn sweep_onto_floor() {
+        let map = room();
+        let hit = map.sweep_aabb(&unit_box(Pos3::new(0.0, 3.0, 0.0)), &Vec3::new(0.0, -5.0, 0.0), |v| *v != 0).unwrap();
+        assert!((hit.toi - 0.42).abs() < 1e-5);
+        assert_eq!(hit.normal, Vec3i::new(0, 1, 0));
+        assert_eq!(hit.voxel.y, 0);
+
+        //short move stops before floor
+        assert!(map.sweep_aabb(&unit_box(Pos3::new(0.0, 3.0, 0.0)), &Vec3::new(0.0, -1.0, 0.0), |v| *v != 0).is_none());
+    }
+
+    #[test]
+    fn no_tunneling() {
+        let map = room();
+        let hit = map.sweep_aabb(&unit_box(Pos3::new(-2.0, 2.0, 0.0)), &Vec3::new(100.0, 0.0, 0.0), |v| *v != 0).unwrap();
+        assert_eq!(hit.voxel.x, 3);
+        assert_eq!(hit.normal, Vec3i::new(-1, 0, 0));
+
+        //predicate decides what is solid
+        assert!(map.sweep_aabb(&unit_box(Pos3::new(-2.0, 2.0, 0.0)), &Vec3::new(100.0, 0.0, 0.0), |v| *v == 1).is_none());
+    }
+
+    #[test]
+    fn stepped_sweep_matches_full_scan() {
+        let mut map = room();
+        map.set_voxel(&Pos3i::new(-1, 3, 1), 3);
+        map.set_voxel(&Pos3i::new(1, 2, -2), 3);
+        let start = unit_box(Pos3::new(-3.0, 3.5, 3.0));
+        for motion in [Vec3::new(7.0, -4.0, -6.0), Vec3::new(3.0, 0.2, -3.0), Vec3::new(0.3, -2.7, 0.1)] {
+            let (min, max) = map.voxel_range(&start.union(&start.translated(&motion)));
+            let full = map.sweep_range(&start, &motion, &min, &max, &|v : &i32| *v != 0);
+            let stepped = map.sweep_aabb(&start, &motion, |v| *v != 0);
+            assert_eq!(stepped.map(|h| (h.toi, h.voxel)), full.map(|h| (h.toi, h.voxel)));
+        }
+    }
+
+    #[test]
+    fn overlap_queries() {
+        let map = room();
+        assert!(map.overlaps_aabb(&unit_box(Pos3::new(0.0, 0.5, 0.0)), |v| *v != 0));
+        assert!(!map.overlaps_aabb(&unit_box(Pos3::new(0.0, 1.0, 0.0)), |v| *v != 0));
+        //touching faces do not overlap
+        assert!(!map.overlaps_aabb(&unit_box(Pos3::new(0.0, 0.9, 0.0)), |v| *v != 0));
+
+        let voxels = map.overlapping_voxels(&unit_box(Pos3::new(0.5, 0.5, 0.5)), |v| *v != 0);
+        assert_eq!(voxels.len(), 4);
+    }
+
+    #[test]
+    fn slide_along_walls() {
+        let map = room();
+        let start = unit_box(Pos3::new(0.0, 1.0, 0.0));
+
+        //walking into wall keeps z motion
+        let res = map.move_and_slide(&start, &Vec3::new(5.0, 0.0, 2.0), |v| *v != 0);
+        assert_eq!(res.contacts, vec![Vec3i::new(-1, 0, 0)]);
+        assert!((res.aabb.max.x - 2.5).abs() < 0.01 && res.aabb.max.x < 2.5);
+        assert!((res.moved.z - 2.0).abs() < 1e-5);
+        assert!(!map.overlaps_aabb(&res.aabb, |v| *v != 0));
+
+        //falling diagonally lands on the floor and keeps sliding
+        let res = map.move_and_slide(&start.translated(&Vec3::new(0.0, 2.0, 0.0)), &Vec3::new(1.0, -4.0, 0.0), |v| *v != 0);
+        assert_eq!(res.contacts, vec![Vec3i::new(0, 1, 0)]);
+        assert!((res.aabb.min.y - 0.5).abs() < 0.01);
+        assert!((res.moved.x - 1.0).abs() < 1e-5);
+
+        //resting box can move along the floor
+        let rested = res.aabb;
+        let res = map.move_and_slide(&rested, &Vec3::new(0.0, -1.0, 1.0), |v| *v != 0);
+        assert!((res.moved.z - 1.0).abs() < 1e-5);
+        assert!(res.moved.y.abs() < 0.01);
+    }
+}
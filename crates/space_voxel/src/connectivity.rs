@@ -0,0 +1,388 @@
+use std::collections::VecDeque;
+use space_core::bevy::utils::{HashMap, HashSet};
+use space_core::{Pos3i, Vec3i};
+use crate::change_log::MapChange;
+use crate::solid_voxel_map::VoxelMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Connectivity {
+    //6 neighbours sharing a face
+    Faces,
+    //26 neighbours sharing a face, edge or corner
+    All
+}
+
+impl Connectivity {
+    pub fn offsets(&self) -> Vec<Vec3i> {
+        let mut res = vec![];
+        for z in -1..=1 {
+            for y in -1..=1 {
+                for x in -1..=1 {
+                    let offset = Vec3i::new(x, y, z);
+                    let dist = offset.abs().sum();
+                    let used = match self {
+                        Connectivity::Faces => dist == 1,
+                        Connectivity::All => dist > 0
+                    };
+                    if used {
+                        res.push(offset);
+                    }
+                }
+            }
+        }
+        res
+    }
+}
+
+//breadth first walk over loaded cells, can_step(to, from value, to value) decides if step is allowed
+fn walk<T, F>(map : &VoxelMap<T>, seed : &Pos3i, offsets : &[Vec3i], visited : &mut HashSet<Pos3i>, can_step : F) -> Vec<Pos3i>
+    where T : Default + Clone, F : Fn(&Pos3i, &T, &T) -> bool {
+
+    let mut res = vec![];
+    if map.get_voxel(seed).is_none() || !visited.insert(*seed) {
+        return res;
+    }
+    let mut queue = VecDeque::from([*seed]);
+    while let Some(pos) = queue.pop_front() {
+        let val = map.get_voxel(&pos).unwrap();
+        for offset in offsets {
+            let next = pos + offset;
+            if visited.contains(&next) {
+                continue;
+            }
+            let Some(next_val) = map.get_voxel(&next) else {
+                continue;
+            };
+            if can_step(&next, val, next_val) {
+                visited.insert(next);
+                queue.push_back(next);
+            }
+        }
+        res.push(pos);
+    }
+    res
+}
+
+//cells reachable from seed, seed included if its chunk is loaded
+pub fn flood_fill<T, F>(map : &VoxelMap<T>, seed : &Pos3i, connectivity : Connectivity, connects : F) -> Vec<Pos3i>
+    where T : Default + Clone, F : Fn(&T, &T) -> bool {
+    walk(map, seed, &connectivity.offsets(), &mut HashSet::new(), |_, a, b| connects(a, b))
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ComponentUpdate {
+    //components that were created or got new or fewer cells
+    pub changed : Vec<u32>,
+    //labels merged into others or emptied, they are never used again
+    pub removed : Vec<u32>
+}
+
+impl ComponentUpdate {
+    fn finish(mut self) -> Self {
+        self.removed.sort_unstable();
+        self.removed.dedup();
+        self.changed.sort_unstable();
+        self.changed.dedup();
+        self.changed.retain(|l| self.removed.binary_search(l).is_err());
+        self
+    }
+}
+
+//labelled connected components of member cells, e.g. rooms, pipe or power networks
+//connects is only asked about two member cells and must be symmetric
+pub struct VoxelComponents {
+    pub connectivity : Connectivity,
+    labels : HashMap<Pos3i, u32>,
+    members : HashMap<u32, HashSet<Pos3i>>,
+    next_label : u32
+}
+
+impl VoxelComponents {
+    pub fn new(connectivity : Connectivity) -> Self {
+        Self {
+            connectivity,
+            labels : HashMap::new(),
+            members : HashMap::new(),
+            next_label : 0
+        }
+    }
+
+    pub fn build<T, M, F>(map : &VoxelMap<T>, connectivity : Connectivity, is_member : M, connects : F) -> Self
+        where T : Default + Clone, M : Fn(&T) -> bool, F : Fn(&T, &T) -> bool {
+
+        let mut res = Self::new(connectivity);
+        res.rebuild(map, is_member, connects);
+        res
+    }
+
+    pub fn rebuild<T, M, F>(&mut self, map : &VoxelMap<T>, is_member : M, connects : F)
+        where T : Default + Clone, M : Fn(&T) -> bool, F : Fn(&T, &T) -> bool {
+
+        self.labels.clear();
+        self.members.clear();
+        let offsets = self.connectivity.offsets();
+        let mut visited = HashSet::new();
+        for chunk in map.map.values() {
+            for z in 0..chunk.size.z {
+                for y in 0..chunk.size.y {
+                    for x in 0..chunk.size.x {
+                        if !is_member(chunk.get(x, y, z)) {
+                            continue;
+                        }
+                        let pos = chunk.origin + Vec3i::new(x, y, z);
+                        if visited.contains(&pos) {
+                            continue;
+                        }
+                        let cells = walk(map, &pos, &offsets, &mut visited, |_, a, b| is_member(b) && connects(a, b));
+                        self.insert_component(cells);
+                    }
+                }
+            }
+        }
+    }
+
+    fn insert_component(&mut self, cells : Vec<Pos3i>) -> u32 {
+        let label = self.next_label;
+        self.next_label += 1;
+        for pos in &cells {
+            self.labels.insert(*pos, label);
+        }
+        self.members.insert(label, cells.into_iter().collect());
+        label
+    }
+
+    pub fn label(&self, pos : &Pos3i) -> Option<u32> {
+        self.labels.get(pos).cloned()
+    }
+
+    pub fn component_count(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn labels(&self) -> impl Iterator<Item = u32> + '_ {
+        self.members.keys().cloned()
+    }
+
+    pub fn cells(&self, label : u32) -> Option<&HashSet<Pos3i>> {
+        self.members.get(&label)
+    }
+
+    pub fn size(&self, label : u32) -> usize {
+        self.members.get(&label).map_or(0, |cells| cells.len())
+    }
+
+    //keeps labels up to date after cell at pos got its current value in map
+    //merges are cheap, a split walks the component that lost the cell
+    pub fn update<T, M, F>(&mut self, map : &VoxelMap<T>, pos : &Pos3i, is_member : M, connects : F) -> ComponentUpdate
+        where T : Default + Clone, M : Fn(&T) -> bool, F : Fn(&T, &T) -> bool {
+
+        let mut res = ComponentUpdate::default();
+        let offsets = self.connectivity.offsets();
+
+        if let Some(old) = self.labels.remove(pos) {
+            let cells = self.members.get_mut(&old).unwrap();
+            cells.remove(pos);
+            let near : Vec<Pos3i> = offsets.iter()
+                .map(|offset| pos + offset)
+                .filter(|n| cells.contains(n))
+                .collect();
+            if cells.is_empty() {
+                self.members.remove(&old);
+                res.removed.push(old);
+            } else if near.len() > 1 {
+                self.split(map, old, &near, &connects, &mut res);
+            } else {
+                res.changed.push(old);
+            }
+        }
+
+        let Some(val) = map.get_voxel(pos) else {
+            return res.finish();
+        };
+        if !is_member(val) {
+            return res.finish();
+        }
+
+        let mut joined : Vec<u32> = vec![];
+        for offset in &offsets {
+            let n = pos + offset;
+            let Some(label) = self.labels.get(&n) else {
+                continue;
+            };
+            if !joined.contains(label) && connects(val, map.get_voxel(&n).unwrap()) {
+                joined.push(*label);
+            }
+        }
+
+        //largest component keeps its label
+        joined.sort_by_key(|label| std::cmp::Reverse(self.size(*label)));
+        let label = match joined.first() {
+            Some(label) => *label,
+            None => self.insert_component(vec![])
+        };
+        for other in joined.iter().skip(1) {
+            let cells = self.members.remove(other).unwrap();
+            for cell in &cells {
+                self.labels.insert(*cell, label);
+            }
+            self.members.get_mut(&label).unwrap().extend(cells);
+            res.removed.push(*other);
+        }
+        self.labels.insert(*pos, label);
+        self.members.get_mut(&label).unwrap().insert(*pos);
+        res.changed.push(label);
+        res.finish()
+    }
+
+    //old component lost a cell, its neighbours may now be apart
+    fn split<T, F>(&mut self, map : &VoxelMap<T>, old : u32, near : &[Pos3i], connects : &F, res : &mut ComponentUpdate)
+        where T : Default + Clone, F : Fn(&T, &T) -> bool {
+
+        let offsets = self.connectivity.offsets();
+        let cells = self.members.remove(&old).unwrap();
+        let mut visited = HashSet::new();
+        let mut parts : Vec<Vec<Pos3i>> = vec![];
+        for start in near {
+            if visited.contains(start) {
+                continue;
+            }
+            parts.push(walk(map, start, &offsets, &mut visited, |p, a, b| cells.contains(p) && connects(a, b)));
+            //rest of component is one piece
+            if visited.len() == cells.len() {
+                break;
+            }
+        }
+
+        //largest piece keeps old label
+        parts.sort_by_key(|part| std::cmp::Reverse(part.len()));
+        let mut parts = parts.into_iter();
+        self.members.insert(old, parts.next().unwrap().into_iter().collect());
+        res.changed.push(old);
+        for part in parts {
+            res.changed.push(self.insert_component(part));
+        }
+    }
+
+    //applies map changes, loaded or unloaded chunks rebuild everything
+    pub fn update_changes<T, M, F>(&mut self, map : &VoxelMap<T>, changes : &[MapChange<T>], is_member : M, connects : F) -> ComponentUpdate
+        where T : Default + Clone, M : Fn(&T) -> bool, F : Fn(&T, &T) -> bool {
+
+        let mut res = ComponentUpdate::default();
+        for change in changes {
+            match change {
+                MapChange::Voxel { pos, old, new } => {
+                    if is_member(old) || is_member(new) {
+                        let step = self.update(map, pos, &is_member, &connects);
+                        res.changed.extend(step.changed);
+                        res.removed.extend(step.removed);
+                    }
+                }
+                MapChange::ChunkLoaded(_) | MapChange::ChunkUnloaded(_) => {
+                    res.removed.extend(self.labels());
+                    self.rebuild(map, &is_member, &connects);
+                    return ComponentUpdate {
+                        changed : self.labels().collect(),
+                        removed : res.removed
+                    };
+                }
+            }
+        }
+        res.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+    use super::*;
+
+    fn empty(v : &i32) -> bool {
+        *v == 0
+    }
+
+    fn same(a : &i32, b : &i32) -> bool {
+        a == b
+    }
+
+    //two rooms split by a wall at x = 4
+    fn rooms() -> VoxelMap<i32> {
+        let mut map = VoxelMap::new(1.0, [4, 4, 4].into());
+        map.allocate_box(&Pos3i::new(0, 0, 0), &Vec3i::new(8, 4, 4));
+        map.fill_box(&Pos3i::new(4, 0, 0), &Vec3i::new(1, 4, 4), &1);
+        map
+    }
+
+    //components as sorted cell lists, labels themselves may differ
+    fn partition(components : &VoxelComponents) -> Vec<Vec<(i32, i32, i32)>> {
+        let mut res : Vec<Vec<(i32, i32, i32)>> = components.labels().map(|label| {
+            let mut cells : Vec<(i32, i32, i32)> = components.cells(label).unwrap().iter().map(|p| (p.x, p.y, p.z)).collect();
+            cells.sort();
+            cells
+        }).collect();
+        res.sort();
+        res
+    }
+
+    #[test]
+    fn flood_fill_connectivity() {
+        let mut map = VoxelMap::new(1.0, [4, 4, 4].into());
+        map.set_voxel(&Pos3i::new(0, 0, 0), 1);
+        map.set_voxel(&Pos3i::new(1, 1, 0), 1);
+        map.set_voxel(&Pos3i::new(2, 2, 1), 1);
+
+        assert_eq!(Connectivity::Faces.offsets().len(), 6);
+        assert_eq!(Connectivity::All.offsets().len(), 26);
+        assert_eq!(flood_fill(&map, &Pos3i::new(0, 0, 0), Connectivity::Faces, same).len(), 1);
+        assert_eq!(flood_fill(&map, &Pos3i::new(0, 0, 0), Connectivity::All, same).len(), 3);
+
+        let map = rooms();
+        assert_eq!(flood_fill(&map, &Pos3i::new(0, 0, 0), Connectivity::Faces, same).len(), 4 * 4 * 4);
+        assert!(flood_fill(&map, &Pos3i::new(40, 0, 0), Connectivity::Faces, same).is_empty());
+    }
+
+    #[test]
+    fn rooms_merge_and_split() {
+        let mut map = rooms();
+        let mut components = VoxelComponents::build(&map, Connectivity::Faces, empty, same);
+        assert_eq!(components.component_count(), 2);
+        let left = components.label(&Pos3i::new(0, 0, 0)).unwrap();
+        let right = components.label(&Pos3i::new(7, 0, 0)).unwrap();
+        assert_ne!(left, right);
+        assert_eq!(components.label(&Pos3i::new(4, 0, 0)), None);
+
+        //door opens, right room joins left one
+        map.set_voxel(&Pos3i::new(4, 1, 1), 0);
+        let update = components.update(&map, &Pos3i::new(4, 1, 1), empty, same);
+        assert_eq!(components.component_count(), 1);
+        assert_eq!(update.removed.len(), 1);
+        assert_eq!(components.size(update.changed[0]), 4 * 4 * 8 - 15);
+
+        //door closes again
+        map.set_voxel(&Pos3i::new(4, 1, 1), 1);
+        let update = components.update(&map, &Pos3i::new(4, 1, 1), empty, same);
+        assert_eq!(components.component_count(), 2);
+        assert_eq!(update.changed.len(), 2);
+        assert_ne!(components.label(&Pos3i::new(0, 0, 0)), components.label(&Pos3i::new(7, 0, 0)));
+    }
+
+    #[test]
+    fn incremental_matches_rebuild() {
+        for connectivity in [Connectivity::Faces, Connectivity::All] {
+            let mut map = rooms();
+            let cursor = map.changes.subscribe();
+            let mut components = VoxelComponents::build(&map, connectivity, empty, same);
+
+            let mut rng = StdRng::seed_from_u64(1);
+            for _ in 0..300 {
+                let pos = Pos3i::new(rng.gen_range(0..8), rng.gen_range(0..4), rng.gen_range(0..4));
+                map.set_voxel(&pos, rng.gen_bool(1.0 / 3.0) as i32);
+
+                let changes = map.changes.drain(cursor);
+                components.update_changes(&map, &changes, empty, same);
+                let expected = VoxelComponents::build(&map, connectivity, empty, same);
+                assert_eq!(partition(&components), partition(&expected));
+            }
+        }
+    }
+}
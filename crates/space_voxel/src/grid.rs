@@ -0,0 +1,109 @@
+use space_core::{Pos3, Ray, Vec3};
+use space_core::nalgebra::UnitQuaternion;
+
+//placement of voxel grid in world, grid-local space is the space of its VoxelMap
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GridTransform {
+    pub pos : Vec3,
+    pub rotation : UnitQuaternion<f32>
+}
+
+impl Default for GridTransform {
+    fn default() -> Self {
+        Self {
+            pos : Vec3::zeros(),
+            rotation : UnitQuaternion::identity()
+        }
+    }
+}
+
+impl GridTransform {
+    pub fn new(pos : Vec3, rotation : UnitQuaternion<f32>) -> Self {
+        Self {
+            pos,
+            rotation
+        }
+    }
+
+    pub fn to_local(&self, pos : &Pos3) -> Pos3 {
+        self.rotation.inverse_transform_point(&(pos - self.pos))
+    }
+
+    pub fn to_world(&self, pos : &Pos3) -> Pos3 {
+        self.rotation.transform_point(pos) + self.pos
+    }
+
+    pub fn dir_to_local(&self, dir : &Vec3) -> Vec3 {
+        self.rotation.inverse_transform_vector(dir)
+    }
+
+    pub fn dir_to_world(&self, dir : &Vec3) -> Vec3 {
+        self.rotation.transform_vector(dir)
+    }
+
+    //distances along ray are the same in both spaces
+    pub fn ray_to_local(&self, ray : &Ray) -> Ray {
+        Ray {
+            pos : self.to_local(&ray.pos),
+            dir : self.dir_to_local(&ray.dir)
+        }
+    }
+
+    //euler angles (as in Location) of object rotated by local euler angles inside grid
+    pub fn euler_to_world(&self, local : &Vec3) -> Vec3 {
+        let rot = self.rotation * UnitQuaternion::from_euler_angles(local.x, local.y, local.z);
+        let (roll, pitch, yaw) = rot.euler_angles();
+        Vec3::new(roll, pitch, yaw)
+    }
+
+    //moves grid by linear and angular (axis * radians) velocity during dt
+    pub fn integrate(&mut self, linear : &Vec3, angular : &Vec3, dt : f32) {
+        self.pos += linear * dt;
+        self.rotation = UnitQuaternion::from_scaled_axis(angular * dt) * self.rotation;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+    use crate::solid_voxel_map::VoxelMap;
+    use super::*;
+
+    fn turned() -> GridTransform {
+        GridTransform::new(Vec3::new(10.0, 0.0, 0.0), UnitQuaternion::from_euler_angles(0.0, FRAC_PI_2, 0.0))
+    }
+
+    #[test]
+    fn local_world_roundtrip() {
+        let transform = turned();
+        let pos = Pos3::new(1.0, 2.0, 3.0);
+        let world = transform.to_world(&pos);
+        assert!((world - Pos3::new(13.0, 2.0, -1.0)).norm() < 1e-5);
+        assert!((transform.to_local(&world) - pos).norm() < 1e-5);
+
+        let euler = transform.euler_to_world(&Vec3::zeros());
+        assert!((euler - Vec3::new(0.0, FRAC_PI_2, 0.0)).norm() < 1e-3);
+    }
+
+    #[test]
+    fn raycast_moved_grid() {
+        let mut map : VoxelMap<i32> = VoxelMap::new(1.0, [4, 4, 4].into());
+        map.set_voxel(&[0, 0, 2].into(), 1);
+        let transform = turned();
+
+        //local +z points to world +x
+        let ray = Ray {
+            pos : Pos3::new(5.0, 0.0, 0.0),
+            dir : Vec3::new(1.0, 0.0, 0.0)
+        };
+        let hit = map.raycast(&transform.ray_to_local(&ray), 100.0, |v| *v != 0).unwrap();
+        assert_eq!(hit.voxel, [0, 0, 2].into());
+        assert!((hit.distance - 6.5).abs() < 1e-4);
+        let normal = transform.dir_to_world(&hit.normal.cast::<f32>());
+        assert!((normal - Vec3::new(-1.0, 0.0, 0.0)).norm() < 1e-5);
+
+        //resting grid is world space
+        let hit = map.raycast(&GridTransform::default().ray_to_local(&ray), 100.0, |v| *v != 0);
+        assert!(hit.is_none());
+    }
+}
@@ -1,3 +1,17 @@
 pub mod solid_voxel_map;
+pub mod change_log;
+pub mod grid;
 pub mod objected_voxel_map;
+pub mod smooth_voxel_map;
+pub mod asteroid_field;
+pub mod palette_chunk;
+pub mod serialization;
+pub mod region;
+pub mod collision;
+pub mod connectivity;
+pub mod light;
+pub mod octree;
+pub mod streaming;
+pub mod voxel_mesher;
 
+pub mod remesher;
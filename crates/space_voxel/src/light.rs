@@ -0,0 +1,459 @@
+use std::collections::{HashSet, VecDeque};
+use space_core::{Pos3i, Vec3i};
+use crate::change_log::MapChange;
+use crate::solid_voxel_map::{VoxelChunk, VoxelMap};
+
+//rgb light level per cell, 0..=MAX_LIGHT
+pub type LightColor = [u8; 3];
+
+pub const MAX_LIGHT : u8 = 15;
+
+const NEIGHBOURS : [[i32; 3]; 6] = [[-1, 0, 0], [1, 0, 0], [0, -1, 0], [0, 1, 0], [0, 0, -1], [0, 0, 1]];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LightProps {
+    //opaque cells keep only their own emission and stop light
+    pub opaque : bool,
+    pub emission : LightColor
+}
+
+//per channel max, how light of several sources adds up
+pub fn max_color(a : LightColor, b : LightColor) -> LightColor {
+    [a[0].max(b[0]), a[1].max(b[1]), a[2].max(b[2])]
+}
+
+//flood fill light, every step away from source loses one level
+//cells changes can be consumed like voxel map changes, e.g. to remesh lit chunks
+pub struct VoxelLight {
+    pub cells : VoxelMap<LightColor>,
+    //light of open space, outside cells get it without fading
+    pub space_light : LightColor,
+    //open space cells, reachable from unloaded chunks without passing opaque cells
+    outside : VoxelMap<bool>,
+    //bulk fills are announced per chunk instead of per cell
+    record_cells : bool
+}
+
+impl VoxelLight {
+    pub fn new<T>(map : &VoxelMap<T>, space_light : LightColor) -> Self
+        where T : Default + Clone {
+        Self {
+            cells : VoxelMap::new(map.voxel_size, map.chunk_size),
+            space_light,
+            outside : VoxelMap::new(map.voxel_size, map.chunk_size),
+            record_cells : true
+        }
+    }
+
+    //cells outside of loaded chunks are open space
+    pub fn get(&self, pos : &Pos3i) -> LightColor {
+        self.cells.get_voxel(pos).cloned().unwrap_or(self.space_light)
+    }
+
+    //(size + 2)^3 light around chunk, same layout as padded voxels
+    pub fn padded(&self, origin : &Pos3i) -> Vec<LightColor> {
+        let size = self.cells.chunk_size;
+        let mut res = Vec::with_capacity(((size.x + 2) * (size.y + 2) * (size.z + 2)) as usize);
+        for z in -1..=size.z {
+            for y in -1..=size.y {
+                for x in -1..=size.x {
+                    res.push(self.get(&(origin + Vec3i::new(x, y, z))));
+                }
+            }
+        }
+        res
+    }
+
+    fn stored(&self, pos : &Pos3i) -> Option<LightColor> {
+        self.cells.get_voxel(pos).cloned()
+    }
+
+    fn set(&mut self, pos : &Pos3i, val : LightColor) {
+        let origin = self.cells.get_origin(pos);
+        if let Some(chunk) = self.cells.map.get_mut(&origin) {
+            let lp = pos - origin;
+            let cell = chunk.get_mut(lp.x, lp.y, lp.z);
+            if *cell != val {
+                let old = *cell;
+                *cell = val;
+                if !self.record_cells {
+                    return;
+                }
+                self.cells.changes.push(MapChange::Voxel {
+                    pos : *pos,
+                    old,
+                    new : val
+                });
+            }
+        }
+    }
+
+    fn is_outside(&self, pos : &Pos3i) -> bool {
+        self.outside.get_voxel(pos).cloned().unwrap_or(false)
+    }
+
+    fn is_open<T, F>(map : &VoxelMap<T>, pos : &Pos3i, props : &F) -> bool
+        where T : Default + Clone, F : Fn(&T) -> LightProps {
+        map.get_voxel(pos).is_some_and(|val| !props(val).opaque)
+    }
+
+    //open cell next to unloaded chunk
+    fn is_border<T, F>(map : &VoxelMap<T>, pos : &Pos3i, props : &F) -> bool
+        where T : Default + Clone, F : Fn(&T) -> LightProps {
+        Self::is_open(map, pos, props)
+            && NEIGHBOURS.iter().any(|n| map.get_voxel(&(pos + Vec3i::from(*n))).is_none())
+    }
+
+    //marks open cells reachable from queued ones as outside, returns newly marked cells
+    fn spread_outside<T, F>(&mut self, map : &VoxelMap<T>, mut queue : VecDeque<Pos3i>, props : &F) -> Vec<Pos3i>
+        where T : Default + Clone, F : Fn(&T) -> LightProps {
+
+        let mut marked = vec![];
+        while let Some(pos) = queue.pop_front() {
+            if self.is_outside(&pos) || !Self::is_open(map, &pos, props) {
+                continue;
+            }
+            let origin = self.outside.get_origin(&pos);
+            let Some(chunk) = self.outside.map.get_mut(&origin) else {
+                continue;
+            };
+            let lp = pos - origin;
+            *chunk.get_mut(lp.x, lp.y, lp.z) = true;
+            marked.push(pos);
+            for n in NEIGHBOURS {
+                queue.push_back(pos + Vec3i::from(n));
+            }
+        }
+        marked
+    }
+
+    fn find_outside<T, F>(&mut self, map : &VoxelMap<T>, props : &F)
+        where T : Default + Clone, F : Fn(&T) -> LightProps {
+
+        self.outside.map.clear();
+        let mut queue = VecDeque::new();
+        for (origin, chunk) in &map.map {
+            self.outside.map.insert(*origin, VoxelChunk::new(*origin, chunk.size));
+            for z in 0..chunk.size.z {
+                for y in 0..chunk.size.y {
+                    for x in 0..chunk.size.x {
+                        let pos = origin + Vec3i::new(x, y, z);
+                        if Self::is_border(map, &pos, props) {
+                            queue.push_back(pos);
+                        }
+                    }
+                }
+            }
+        }
+        self.spread_outside(map, queue, props);
+    }
+
+    //light cell has without neighbours
+    fn base<T, F>(&self, map : &VoxelMap<T>, pos : &Pos3i, props : &F) -> LightColor
+        where T : Default + Clone, F : Fn(&T) -> LightProps {
+
+        let Some(val) = map.get_voxel(pos) else {
+            return [0; 3];
+        };
+        let p = props(val);
+        if p.opaque {
+            return p.emission;
+        }
+        if self.is_outside(pos) {
+            max_color(p.emission, self.space_light)
+        } else {
+            p.emission
+        }
+    }
+
+    fn propagate<T, F>(&mut self, map : &VoxelMap<T>, queue : &mut VecDeque<Pos3i>, props : &F)
+        where T : Default + Clone, F : Fn(&T) -> LightProps {
+
+        while let Some(pos) = queue.pop_front() {
+            let Some(light) = self.stored(&pos) else {
+                continue;
+            };
+            if light.iter().all(|l| *l <= 1) {
+                continue;
+            }
+            for n in NEIGHBOURS {
+                let next = pos + Vec3i::from(n);
+                match map.get_voxel(&next) {
+                    Some(val) if !props(val).opaque => {}
+                    _ => continue
+                }
+                let Some(cur) = self.stored(&next) else {
+                    continue;
+                };
+                let lit = max_color(cur, light.map(|l| l.saturating_sub(1)));
+                if lit != cur {
+                    self.set(&next, lit);
+                    queue.push_back(next);
+                }
+            }
+        }
+    }
+
+    //darkens everything lit through start cells, light of other sources is queued to flow back
+    fn remove<T, F>(&mut self, map : &VoxelMap<T>, starts : &[(Pos3i, LightColor)], relight : &mut VecDeque<Pos3i>, props : &F)
+        where T : Default + Clone, F : Fn(&T) -> LightProps {
+
+        for c in 0..3 {
+            let mut queue : VecDeque<(Pos3i, u8)> = VecDeque::new();
+            for (pos, old) in starts {
+                if old[c] > 0 {
+                    queue.push_back((*pos, old[c]));
+                }
+            }
+
+            while let Some((pos, level)) = queue.pop_front() {
+                for n in NEIGHBOURS {
+                    let next = pos + Vec3i::from(n);
+                    let Some(mut cur) = self.stored(&next) else {
+                        continue;
+                    };
+                    if cur[c] == 0 {
+                        continue;
+                    }
+                    if cur[c] < level {
+                        queue.push_back((next, cur[c]));
+                        //sources keep shining, their light flows back later
+                        cur[c] = self.base(map, &next, props)[c];
+                        self.set(&next, cur);
+                        if cur[c] > 0 {
+                            relight.push_back(next);
+                        }
+                    } else {
+                        relight.push_back(next);
+                    }
+                }
+            }
+        }
+    }
+
+    //recomputes all light, needed after chunks appear or vanish
+    pub fn rebuild<T, F>(&mut self, map : &VoxelMap<T>, props : F)
+        where T : Default + Clone, F : Fn(&T) -> LightProps {
+
+        for origin in self.cells.map.keys() {
+            if !map.map.contains_key(origin) {
+                self.cells.changes.push(MapChange::ChunkUnloaded(*origin));
+            }
+        }
+        self.cells.map.clear();
+        self.find_outside(map, &props);
+
+        let mut queue = VecDeque::new();
+        for (origin, chunk) in &map.map {
+            let mut light = VoxelChunk::<LightColor>::new(*origin, chunk.size);
+            for z in 0..chunk.size.z {
+                for y in 0..chunk.size.y {
+                    for x in 0..chunk.size.x {
+                        let pos = origin + Vec3i::new(x, y, z);
+                        let base = self.base(map, &pos, &props);
+                        if base != [0; 3] {
+                            *light.get_mut(x, y, z) = base;
+                            queue.push_back(pos);
+                        }
+                    }
+                }
+            }
+            self.cells.map.insert(*origin, light);
+            self.cells.changes.push(MapChange::ChunkLoaded(*origin));
+        }
+        self.record_cells = false;
+        self.propagate(map, &mut queue, &props);
+        self.record_cells = true;
+    }
+
+    //applies voxel changes incrementally, loaded and unloaded chunks rebuild everything
+    pub fn update<T, F>(&mut self, map : &VoxelMap<T>, changes : &[MapChange<T>], props : F)
+        where T : Default + Clone, F : Fn(&T) -> LightProps {
+
+        let mut starts = vec![];
+        for change in changes {
+            match change {
+                MapChange::Voxel { pos, old, new } => {
+                    if props(old) != props(new) {
+                        starts.push(*pos);
+                    }
+                }
+                MapChange::ChunkLoaded(_) | MapChange::ChunkUnloaded(_) => {
+                    self.rebuild(map, props);
+                    return;
+                }
+            }
+        }
+        if starts.is_empty() {
+            return;
+        }
+
+        //new chunk closes open space around it
+        if starts.iter().any(|pos| !self.cells.map.contains_key(&self.cells.get_origin(pos))) {
+            self.rebuild(map, props);
+            return;
+        }
+
+        //cells that joined or left open space get new base light too
+        let sealed = starts.iter().any(|pos| self.is_outside(pos) && !Self::is_open(map, pos, &props));
+        let moved = if sealed {
+            let old = std::mem::replace(&mut self.outside, VoxelMap::new(map.voxel_size, map.chunk_size));
+            self.find_outside(map, &props);
+            let mut moved = vec![];
+            for (origin, chunk) in &old.map {
+                for z in 0..chunk.size.z {
+                    for y in 0..chunk.size.y {
+                        for x in 0..chunk.size.x {
+                            let pos = origin + Vec3i::new(x, y, z);
+                            if *chunk.get(x, y, z) != self.is_outside(&pos) {
+                                moved.push(pos);
+                            }
+                        }
+                    }
+                }
+            }
+            moved
+        } else {
+            //breach joins open space when it touches it
+            let breaches : VecDeque<Pos3i> = starts.iter()
+                .filter(|pos| Self::is_border(map, pos, &props)
+                    || NEIGHBOURS.iter().any(|n| self.is_outside(&(*pos + Vec3i::from(*n)))))
+                .cloned()
+                .collect();
+            self.spread_outside(map, breaches, &props)
+        };
+        let known : HashSet<Pos3i> = starts.iter().cloned().collect();
+        starts.extend(moved.into_iter().filter(|pos| !known.contains(pos)));
+
+        let mut relight = VecDeque::new();
+        let removed : Vec<(Pos3i, LightColor)> = starts.iter()
+            .map(|pos| (*pos, self.stored(pos).unwrap_or_default()))
+            .collect();
+        for pos in &starts {
+            self.set(pos, [0; 3]);
+        }
+        self.remove(map, &removed, &mut relight, &props);
+
+        for pos in &starts {
+            let base = self.base(map, pos, &props);
+            let cur = self.stored(pos).unwrap_or_default();
+            self.set(pos, max_color(cur, base));
+            relight.push_back(*pos);
+            //open cell lets neighbours light flow in
+            for n in NEIGHBOURS {
+                relight.push_back(pos + Vec3i::from(n));
+            }
+        }
+        self.propagate(map, &mut relight, &props);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //1 is wall, 2 is lamp
+    fn props(v : &i32) -> LightProps {
+        match v {
+            1 => LightProps { opaque : true, emission : [0; 3] },
+            2 => LightProps { opaque : true, emission : [MAX_LIGHT, 8, 0] },
+            _ => LightProps::default()
+        }
+    }
+
+    //closed box of walls, inside from 1 to 6
+    fn room() -> VoxelMap<i32> {
+        let mut map = VoxelMap::new(1.0, [4, 4, 4].into());
+        map.allocate_box(&Pos3i::new(-4, -4, -4), &Vec3i::new(16, 16, 16));
+        map.fill_box(&Pos3i::new(0, 0, 0), &Vec3i::new(8, 8, 8), &1);
+        map.clear_box(&Pos3i::new(1, 1, 1), &Vec3i::new(6, 6, 6));
+        map
+    }
+
+    #[test]
+    fn lamp_lights_room() {
+        let mut map = room();
+        map.set_voxel(&Pos3i::new(1, 1, 1), 2);
+        let mut light = VoxelLight::new(&map, [4, 4, 4]);
+        light.rebuild(&map, props);
+
+        assert_eq!(light.get(&Pos3i::new(1, 1, 1)), [15, 8, 0]);
+        assert_eq!(light.get(&Pos3i::new(2, 1, 1)), [14, 7, 0]);
+        assert_eq!(light.get(&Pos3i::new(4, 4, 4)), [6, 0, 0]);
+        //walls stop both lamp and space light
+        assert_eq!(light.get(&Pos3i::new(0, 1, 1)), [0; 3]);
+        //open space does not fade away from unloaded chunks
+        assert_eq!(light.get(&Pos3i::new(-4, 0, 0)), [4, 4, 4]);
+        assert_eq!(light.get(&Pos3i::new(-3, 0, 0)), [4, 4, 4]);
+        assert_eq!(light.get(&Pos3i::new(4, -2, 4)), [4, 4, 4]);
+    }
+
+    #[test]
+    fn breach_lets_space_in() {
+        let mut map = room();
+        let mut light = VoxelLight::new(&map, [6, 6, 6]);
+        light.rebuild(&map, props);
+        let cursor = map.changes.subscribe();
+        assert_eq!(light.get(&Pos3i::new(4, 4, 4)), [0; 3]);
+
+        //hole is inside loaded chunks, far from unloaded ones
+        map.set_voxel(&Pos3i::new(0, 3, 3), 0);
+        let changes = map.changes.drain(cursor);
+        light.update(&map, &changes, props);
+        assert_eq!(light.get(&Pos3i::new(4, 4, 4)), [6, 6, 6]);
+
+        map.set_voxel(&Pos3i::new(0, 3, 3), 1);
+        let changes = map.changes.drain(cursor);
+        light.update(&map, &changes, props);
+        assert_eq!(light.get(&Pos3i::new(4, 4, 4)), [0; 3]);
+    }
+
+    #[test]
+    fn incremental_matches_rebuild() {
+        let mut map = room();
+        let mut light = VoxelLight::new(&map, [6, 6, 6]);
+        light.rebuild(&map, props);
+        let cursor = map.changes.subscribe();
+
+        let edits = [
+            (Pos3i::new(3, 3, 3), 2),
+            (Pos3i::new(4, 3, 3), 1),
+            //hole in the hull lets space in
+            (Pos3i::new(0, 3, 3), 0),
+            (Pos3i::new(3, 3, 3), 0),
+            (Pos3i::new(0, 3, 3), 1)
+        ];
+        for (pos, val) in edits {
+            map.set_voxel(&pos, val);
+            let changes = map.changes.drain(cursor);
+            light.update(&map, &changes, props);
+
+            let mut expected = VoxelLight::new(&map, [6, 6, 6]);
+            expected.rebuild(&map, props);
+            for (origin, chunk) in &expected.cells.map {
+                assert!(chunk.data == light.cells.map[origin].data, "light differs after setting {:?}", pos);
+            }
+        }
+    }
+
+    #[test]
+    fn light_changes_are_recorded() {
+        let mut map = room();
+        let mut light = VoxelLight::new(&map, [0; 3]);
+        light.rebuild(&map, props);
+        let cursor = light.cells.changes.subscribe();
+        let map_cursor = map.changes.subscribe();
+
+        map.set_voxel(&Pos3i::new(6, 6, 6), 2);
+        let changes = map.changes.drain(map_cursor);
+        light.update(&map, &changes, props);
+
+        let changes = light.cells.changes.drain(cursor);
+        assert!(changes.contains(&MapChange::Voxel {
+            pos : Pos3i::new(5, 6, 6),
+            old : [0; 3],
+            new : [14, 7, 0]
+        }));
+        assert_eq!(light.cells.touched_chunks(&changes).len(), 8);
+    }
+}
@@ -1,21 +1,126 @@
 use block_mesh::{greedy_quads, GreedyQuadsBuffer, MergeVoxel, RIGHT_HANDED_Y_UP_CONFIG, Voxel, VoxelVisibility};
 use space_core::ecs::Entity;
-use space_core::Vec3i;
+use space_core::{Pos3i, Vec3i};
+use space_core::serde::*;
 use crate::solid_voxel_map::{VoxelChunk, VoxelMap};
+use crate::region::GridRotation;
 
 use block_mesh::ndshape::*;
 
+//cells taken by object, relative to its min corner
+#[derive(Clone, Debug, PartialEq)]
 pub enum VoxelFilling {
     Point,
     BBox(Vec3i),
+    //occupancy mask in VoxelChunk layout
     Map(Vec<bool>, Vec3i)
 }
 
+impl VoxelFilling {
+    //layers go up along y, rows along z, chars along x, '#' is a filled cell
+    pub fn from_layers(layers : &[Vec<String>]) -> Option<VoxelFilling> {
+        let depth = layers.first()?.len();
+        let width = layers[0].first()?.chars().count();
+        let size = Vec3i::new(width as i32, layers.len() as i32, depth as i32);
+        let mut mask = vec![false; width * layers.len() * depth];
+        for (y, layer) in layers.iter().enumerate() {
+            if layer.len() != depth {
+                return None;
+            }
+            for (z, row) in layer.iter().enumerate() {
+                if row.chars().count() != width {
+                    return None;
+                }
+                for (x, c) in row.chars().enumerate() {
+                    mask[(z * layers.len() + y) * width + x] = c == '#';
+                }
+            }
+        }
+        Some(VoxelFilling::Map(mask, size))
+    }
+
+    pub fn size(&self) -> Vec3i {
+        match self {
+            VoxelFilling::Point => Vec3i::new(1, 1, 1),
+            VoxelFilling::BBox(size) => *size,
+            VoxelFilling::Map(_, size) => *size
+        }
+    }
+
+    pub fn is_filled(&self, pos : &Vec3i) -> bool {
+        let size = self.size();
+        if (0..3).any(|i| pos[i] < 0 || pos[i] >= size[i]) {
+            return false;
+        }
+        match self {
+            VoxelFilling::Map(mask, size) => mask[((pos.z * size.y + pos.y) * size.x + pos.x) as usize],
+            _ => true
+        }
+    }
+
+    pub fn cells(&self) -> Vec<Vec3i> {
+        let size = self.size();
+        let mut res = vec![];
+        for z in 0..size.z {
+            for y in 0..size.y {
+                for x in 0..size.x {
+                    let pos = Vec3i::new(x, y, z);
+                    if self.is_filled(&pos) {
+                        res.push(pos);
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    //rotated filling keeps its min corner at zero, same as VoxelMap::paste
+    pub fn rotated(&self, rot : &GridRotation) -> VoxelFilling {
+        match self {
+            VoxelFilling::Point => VoxelFilling::Point,
+            VoxelFilling::BBox(size) => VoxelFilling::BBox(rot.rotate_size(size)),
+            VoxelFilling::Map(_, size) => {
+                let new_size = rot.rotate_size(size);
+                let shift = rot.apply(&(size - Vec3i::new(1, 1, 1))).inf(&Vec3i::zeros());
+                let mut mask = vec![false; (new_size.x * new_size.y * new_size.z) as usize];
+                for cell in self.cells() {
+                    let pos = rot.apply(&cell) - shift;
+                    mask[((pos.z * new_size.y + pos.y) * new_size.x + pos.x) as usize] = true;
+                }
+                VoxelFilling::Map(mask, new_size)
+            }
+        }
+    }
+}
+
+impl<T> VoxelMap<T>
+    where T : Default + Clone + PartialEq {
+
+    pub fn is_filling_empty(&self, min : &Pos3i, filling : &VoxelFilling) -> bool {
+        match filling {
+            VoxelFilling::Map(..) => filling.cells().iter()
+                .all(|cell| self.get_voxel(&(min + cell)).is_none_or(|v| *v == T::default())),
+            _ => self.is_box_empty(min, &filling.size())
+        }
+    }
+
+    pub fn fill_filling(&mut self, min : &Pos3i, filling : &VoxelFilling, val : &T) {
+        match filling {
+            VoxelFilling::Map(..) => {
+                for cell in filling.cells() {
+                    self.set_voxel(&(min + cell), val.clone());
+                }
+            }
+            _ => self.fill_box(min, &filling.size(), val)
+        }
+    }
+}
+
 pub trait VoxelObject {
 
 }
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub enum VoxelVal<VoxelID> {
     None,
     Voxel(VoxelID),
@@ -41,12 +146,79 @@ impl<VoxelID : PartialEq + Eq + Clone> MergeVoxel for VoxelVal<VoxelID> {
 }
 
 
+//one voxel shell around a chunk taken from its neighbours, interior stays default
+pub struct ChunkBorders<T> {
+    pub size : Vec3i,
+    pub data : Vec<T>
+}
+
+impl<T> ChunkBorders<T>
+    where T : Default + Clone {
+
+    pub fn empty(size : Vec3i) -> ChunkBorders<T> {
+        let padded = size + Vec3i::new(2, 2, 2);
+        ChunkBorders {
+            size,
+            data : vec![T::default(); (padded.x * padded.y * padded.z) as usize]
+        }
+    }
+
+    pub fn from_map(map : &VoxelMap<T>, origin : &Pos3i) -> ChunkBorders<T> {
+        let mut res = ChunkBorders::empty(map.chunk_size);
+        let size = map.chunk_size;
+        for z in -1..=size.z {
+            for y in -1..=size.y {
+                for x in -1..=size.x {
+                    let inside = x >= 0 && y >= 0 && z >= 0 && x < size.x && y < size.y && z < size.z;
+                    if inside {
+                        continue;
+                    }
+                    if let Some(val) = map.get_voxel(&(origin + Vec3i::new(x, y, z))) {
+                        *res.get_mut(x, y, z) = val.clone();
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    fn index(&self, x : i32, y : i32, z : i32) -> usize {
+        let padded = self.size + Vec3i::new(2, 2, 2);
+        (((z + 1) * padded.y + y + 1) * padded.x + x + 1) as usize
+    }
+
+    //coordinates are chunk local, from -1 to size inclusive
+    pub fn get(&self, x : i32, y : i32, z : i32) -> &T {
+        &self.data[self.index(x, y, z)]
+    }
+
+    pub fn get_mut(&mut self, x : i32, y : i32, z : i32) -> &mut T {
+        let idx = self.index(x, y, z);
+        &mut self.data[idx]
+    }
+}
+
 pub fn generate_mesh<T: PartialEq + Eq + Clone>(chunk : &VoxelChunk<VoxelVal<T>>) -> GreedyQuadsBuffer {
-    let mut buffer = GreedyQuadsBuffer::new(chunk.data.len());
+    generate_mesh_with_borders(chunk, &ChunkBorders::empty(chunk.size))
+}
+
+//faces hidden by neighbour chunks are culled
+pub fn generate_mesh_with_borders<T: PartialEq + Eq + Clone>(
+    chunk : &VoxelChunk<VoxelVal<T>>,
+    borders : &ChunkBorders<VoxelVal<T>>) -> GreedyQuadsBuffer {
+
+    let padded_data = padded_chunk(chunk, borders);
+    greedy_padded(&chunk.size, &padded_data)
+}
+
+//chunk data inside its border shell, layout of ChunkBorders
+pub(crate) fn padded_chunk<T : Default + Clone>(
+    chunk : &VoxelChunk<T>,
+    borders : &ChunkBorders<T>) -> Vec<T> {
 
     let size = [chunk.size.x as u32 + 2, chunk.size.y as u32 + 2, chunk.size.z as u32 + 2];
 
-    let mut padded_data = vec![VoxelVal::None; (size[0] * size[1] * size[2]) as usize];
+    let mut padded_data = borders.data.clone();
     for z in 0..chunk.size.z {
         for y in 0..chunk.size.y {
             for x in 0..chunk.size.x {
@@ -58,12 +230,22 @@ pub fn generate_mesh<T: PartialEq + Eq + Clone>(chunk : &VoxelChunk<VoxelVal<T>>
             }
         }
     }
+    padded_data
+}
+
+pub(crate) fn greedy_padded<V : MergeVoxel>(
+    chunk_size : &Vec3i,
+    padded_data : &[V]) -> GreedyQuadsBuffer {
+
+    let mut buffer = GreedyQuadsBuffer::new(padded_data.len());
+    let size = [chunk_size.x as u32 + 2, chunk_size.y as u32 + 2, chunk_size.z as u32 + 2];
 
     greedy_quads(
-        &padded_data,
-        &block_mesh::ndshape::RuntimeShape::<u32, 3>::new(size.clone()),
-        [1; 3],
-        [chunk.size.x as u32 + 1, chunk.size.y as u32 + 1, chunk.size.z as u32 + 1],
+        padded_data,
+        &block_mesh::ndshape::RuntimeShape::<u32, 3>::new(size),
+        //kernel skips the outer layer, so the whole padded extent is passed
+        [0; 3],
+        [chunk_size.x as u32 + 1, chunk_size.y as u32 + 1, chunk_size.z as u32 + 1],
         &RIGHT_HANDED_Y_UP_CONFIG.faces,
         &mut buffer
     );
@@ -78,7 +260,7 @@ impl<T> Default for VoxelVal<T> {
 
 #[cfg(test)]
 mod tests {
-    use crate::objected_voxel_map::{generate_mesh, VoxelVal};
+    use crate::objected_voxel_map::*;
     use crate::solid_voxel_map::VoxelChunk;
 
     #[test]
@@ -90,4 +272,94 @@ mod tests {
             println!("{:?}", g);
         }
     }
-}
\ No newline at end of file
+
+    fn solid_map() -> VoxelMap<VoxelVal<usize>> {
+        let mut map = VoxelMap::new(1.0, [4, 4, 4].into());
+        map.fill_box(&Pos3i::new(0, 0, 0), &Vec3i::new(8, 4, 4), &VoxelVal::Voxel(1));
+        map
+    }
+
+    //quads of face group i, block-mesh orders groups -x, -y, -z, +x, +y, +z
+    fn face_count(buffer : &GreedyQuadsBuffer, group : usize) -> usize {
+        buffer.quads.groups[group].len()
+    }
+
+    #[test]
+    fn seam_faces_culled() {
+        let map = solid_map();
+        let left = map.get_chunk_by_voxel(&Pos3i::new(0, 0, 0)).unwrap();
+        let right = map.get_chunk_by_voxel(&Pos3i::new(4, 0, 0)).unwrap();
+
+        let isolated = generate_mesh(left);
+        assert_eq!(face_count(&isolated, 3), 1);
+
+        let left_mesh = generate_mesh_with_borders(left, &ChunkBorders::from_map(&map, &left.origin));
+        let right_mesh = generate_mesh_with_borders(right, &ChunkBorders::from_map(&map, &right.origin));
+        assert_eq!(face_count(&left_mesh, 3), 0);
+        assert_eq!(face_count(&left_mesh, 0), 1);
+        assert_eq!(face_count(&right_mesh, 0), 0);
+        assert_eq!(face_count(&right_mesh, 3), 1);
+        assert_eq!(left_mesh.quads.num_quads() + right_mesh.quads.num_quads(), 10);
+    }
+
+    #[test]
+    fn seam_opens_after_border_change() {
+        let mut map = solid_map();
+        let cursor = map.changes.subscribe();
+        map.set_voxel(&Pos3i::new(4, 1, 1), VoxelVal::None);
+
+        //left chunk has to be remeshed, its face now looks into a hole
+        let changes = map.changes.drain(cursor);
+        let touched = map.touched_chunks(&changes);
+        assert!(touched.contains(&Pos3i::new(0, 0, 0)));
+        assert!(touched.contains(&Pos3i::new(4, 0, 0)));
+
+        let left = map.get_chunk_by_voxel(&Pos3i::new(0, 0, 0)).unwrap();
+        let left_mesh = generate_mesh_with_borders(left, &ChunkBorders::from_map(&map, &left.origin));
+        assert_eq!(face_count(&left_mesh, 3), 1);
+        assert_eq!(left_mesh.quads.groups[3][0].minimum, [4, 2, 2]);
+    }
+
+    #[test]
+    fn filling_mask_rotation() {
+        //L shaped console, two cells on the floor and one on top
+        let layers = vec![
+            vec!["##".to_string()],
+            vec!["#.".to_string()]
+        ];
+        let filling = VoxelFilling::from_layers(&layers).unwrap();
+        assert_eq!(filling.size(), Vec3i::new(2, 2, 1));
+        assert_eq!(filling.cells(), vec![Vec3i::new(0, 0, 0), Vec3i::new(1, 0, 0), Vec3i::new(0, 1, 0)]);
+        assert!(VoxelFilling::from_layers(&[vec!["##".to_string()], vec!["#".to_string()]]).is_none());
+
+        //quarter turn around y moves +x arm to -z, then back into positive range
+        let turned = filling.rotated(&GridRotation::around_y(1));
+        assert_eq!(turned.size(), Vec3i::new(1, 2, 2));
+        assert!(turned.is_filled(&Vec3i::new(0, 0, 0)));
+        assert!(turned.is_filled(&Vec3i::new(0, 0, 1)));
+        assert!(turned.is_filled(&Vec3i::new(0, 1, 1)));
+        assert!(!turned.is_filled(&Vec3i::new(0, 1, 0)));
+        assert_eq!(VoxelFilling::BBox(Vec3i::new(2, 1, 3)).rotated(&GridRotation::around_z(1)).size(), Vec3i::new(1, 2, 3));
+    }
+
+    #[test]
+    fn hollow_frame_placement() {
+        let mut map = VoxelMap::<VoxelVal<usize>>::new(1.0, [4, 4, 4].into());
+        let frame = VoxelFilling::from_layers(&[vec![
+            "###".to_string(),
+            "#.#".to_string(),
+            "###".to_string()
+        ]]).unwrap();
+        map.fill_filling(&Pos3i::new(2, 0, 2), &frame, &VoxelVal::Voxel(1));
+        assert_eq!(map.get_voxel(&Pos3i::new(3, 0, 3)), Some(&VoxelVal::None));
+        assert_eq!(map.get_voxel(&Pos3i::new(4, 0, 4)), Some(&VoxelVal::Voxel(1)));
+
+        //pillar fits into the hole, but not into the frame
+        let pillar = VoxelFilling::BBox(Vec3i::new(1, 1, 1));
+        assert!(map.is_filling_empty(&Pos3i::new(3, 0, 3), &pillar));
+        assert!(!map.is_filling_empty(&Pos3i::new(2, 0, 3), &pillar));
+        //same frame overlaps itself, one level up it is free
+        assert!(!map.is_filling_empty(&Pos3i::new(2, 0, 2), &frame));
+        assert!(map.is_filling_empty(&Pos3i::new(2, 1, 2), &frame));
+    }
+}
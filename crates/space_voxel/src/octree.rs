@@ -0,0 +1,551 @@
+use space_core::{Pos3, Pos3i, Ray, Vec3, Vec3i};
+use crate::solid_voxel_map::{VoxelHit, VoxelMap};
+
+//uniform leaf covers its whole cube, so empty space costs one node at any size
+enum Node<T> {
+    Leaf(T),
+    Branch(Box<[Node<T>; 8]>)
+}
+
+//coords must stay inside +-2^MAX_DEPTH / 2
+const MAX_DEPTH : u32 = 30;
+
+fn child_index(lo : &Pos3i, half : i32, pos : &Pos3i) -> usize {
+    (pos.x >= lo.x + half) as usize
+        | ((pos.y >= lo.y + half) as usize) << 1
+        | ((pos.z >= lo.z + half) as usize) << 2
+}
+
+fn child_lo(lo : &Pos3i, half : i32, idx : usize) -> Pos3i {
+    lo + Vec3i::new(
+        (idx & 1) as i32 * half,
+        (idx >> 1 & 1) as i32 * half,
+        (idx >> 2 & 1) as i32 * half)
+}
+
+fn split<T : Clone>(val : &T) -> Node<T> {
+    Node::Branch(Box::new(std::array::from_fn(|_| Node::Leaf(val.clone()))))
+}
+
+//branch of equal leaves becomes one leaf
+fn collapse<T : Default + PartialEq>(node : &mut Node<T>) {
+    let Node::Branch(children) = node else {
+        return;
+    };
+    let Node::Leaf(first) = &children[0] else {
+        return;
+    };
+    if children[1..].iter().all(|c| matches!(c, Node::Leaf(v) if v == first)) {
+        *node = std::mem::replace(&mut children[0], Node::Leaf(T::default()));
+    }
+}
+
+//sparse voxel octree with VoxelMap-like api, for huge maps that are mostly empty
+//there are no chunks, every cell inside bounds exists and defaults to T::default()
+pub struct VoxelOctree<T> {
+    root : Node<T>,
+    //root covers 2^depth cells per axis, centered at 0
+    depth : u32,
+    pub voxel_size : f32
+}
+
+impl<T> VoxelOctree<T>
+    where T : Default + Clone + PartialEq {
+
+    pub fn new(voxel_size : f32) -> Self {
+        Self {
+            root : Node::Leaf(T::default()),
+            depth : 4,
+            voxel_size
+        }
+    }
+
+    fn side(&self) -> i32 {
+        1 << self.depth
+    }
+
+    //min cell of root cube
+    fn lo(&self) -> Pos3i {
+        Pos3i::new(-self.side() / 2, -self.side() / 2, -self.side() / 2)
+    }
+
+    pub fn contains(&self, pos : &Pos3i) -> bool {
+        let lo = self.lo();
+        let side = self.side();
+        (0..3).all(|i| pos[i] >= lo[i] && pos[i] < lo[i] + side)
+    }
+
+    //min cell and size of root cube
+    pub fn bounds(&self) -> (Pos3i, Vec3i) {
+        (self.lo(), Vec3i::repeat(self.side()))
+    }
+
+    pub fn get_voxel_pos(&self, pos : &Pos3) -> Pos3i {
+        Pos3i::new(
+            (pos.x / self.voxel_size).round() as i32,
+            (pos.y / self.voxel_size).round() as i32,
+            (pos.z / self.voxel_size).round() as i32,
+        )
+    }
+
+    //doubles root cube, old octants move to the inner corners of new ones
+    //false when root already has MAX_DEPTH
+    fn grow(&mut self) -> bool {
+        if self.depth >= MAX_DEPTH {
+            return false;
+        }
+        self.depth += 1;
+        let old = std::mem::replace(&mut self.root, Node::Leaf(T::default()));
+        let mut children = match old {
+            Node::Leaf(val) if val == T::default() => {
+                return true;
+            }
+            Node::Leaf(val) => std::array::from_fn(|_| Some(Node::Leaf(val.clone()))),
+            Node::Branch(children) => (*children).map(Some)
+        };
+        self.root = Node::Branch(Box::new(std::array::from_fn(|idx| {
+            let mut sub = std::array::from_fn(|_| Node::Leaf(T::default()));
+            sub[7 - idx] = children[idx].take().unwrap();
+            let mut node = Node::Branch(Box::new(sub));
+            collapse(&mut node);
+            node
+        })));
+        true
+    }
+
+    //value and cube (min cell, size) of leaf containing pos
+    fn leaf_at(&self, pos : &Pos3i) -> (&T, Pos3i, i32) {
+        let mut node = &self.root;
+        let mut lo = self.lo();
+        let mut size = self.side();
+        loop {
+            match node {
+                Node::Leaf(val) => return (val, lo, size),
+                Node::Branch(children) => {
+                    size /= 2;
+                    let idx = child_index(&lo, size, pos);
+                    lo = child_lo(&lo, size, idx);
+                    node = &children[idx];
+                }
+            }
+        }
+    }
+
+    pub fn get_voxel(&self, pos : &Pos3i) -> Option<&T> {
+        if !self.contains(pos) {
+            return None;
+        }
+        Some(self.leaf_at(pos).0)
+    }
+
+    pub fn get_cloned(&self, pos : &Pos3) -> T {
+        self.get_voxel(&self.get_voxel_pos(pos)).cloned().unwrap_or_default()
+    }
+
+    fn set_node(node : &mut Node<T>, lo : &Pos3i, size : i32, pos : &Pos3i, val : T) -> bool {
+        if let Node::Leaf(cur) = node {
+            if *cur == val {
+                return false;
+            }
+            if size == 1 {
+                *cur = val;
+                return true;
+            }
+            *node = split(cur);
+        }
+        let Node::Branch(children) = node else {
+            return false;
+        };
+        let half = size / 2;
+        let idx = child_index(lo, half, pos);
+        let changed = Self::set_node(&mut children[idx], &child_lo(lo, half, idx), half, pos, val);
+        if changed {
+            collapse(node);
+        }
+        changed
+    }
+
+    //returns whether cell changed, cells out of MAX_DEPTH range are never set
+    pub fn set_voxel(&mut self, pos : &Pos3i, val : T) -> bool {
+        while !self.contains(pos) {
+            if val == T::default() || !self.grow() {
+                return false;
+            }
+        }
+        let lo = self.lo();
+        let side = self.side();
+        Self::set_node(&mut self.root, &lo, side, pos, val)
+    }
+
+    pub fn set(&mut self, pos : &Pos3, val : T) -> bool {
+        let vp = self.get_voxel_pos(pos);
+        self.set_voxel(&vp, val)
+    }
+
+    //max is exclusive
+    fn fill_node(node : &mut Node<T>, lo : &Pos3i, size : i32, min : &Pos3i, max : &Pos3i, val : &T) {
+        let hi = lo + Vec3i::repeat(size);
+        if (0..3).any(|i| max[i] <= lo[i] || min[i] >= hi[i]) {
+            return;
+        }
+        if (0..3).all(|i| min[i] <= lo[i] && max[i] >= hi[i]) {
+            *node = Node::Leaf(val.clone());
+            return;
+        }
+        if let Node::Leaf(cur) = node {
+            if cur == val {
+                return;
+            }
+            *node = split(cur);
+        }
+        if let Node::Branch(children) = node {
+            let half = size / 2;
+            for (idx, child) in children.iter_mut().enumerate() {
+                Self::fill_node(child, &child_lo(lo, half, idx), half, min, max, val);
+            }
+        }
+        collapse(node);
+    }
+
+    //whole covered subtrees become single leaves
+    //returns false and fills nothing when box reaches out of MAX_DEPTH range
+    pub fn fill_box(&mut self, min : &Pos3i, size : &Vec3i, val : &T) -> bool {
+        if size.iter().any(|s| *s <= 0) {
+            return true;
+        }
+        let max = min + size;
+        if *val != T::default() {
+            while !self.contains(min) || !self.contains(&(max - Vec3i::repeat(1))) {
+                if !self.grow() {
+                    return false;
+                }
+            }
+        }
+        let lo = self.lo();
+        let side = self.side();
+        Self::fill_node(&mut self.root, &lo, side, min, &max, val);
+        true
+    }
+
+    //clearing never grows, so it always succeeds
+    pub fn clear_box(&mut self, min : &Pos3i, size : &Vec3i) {
+        self.fill_box(min, size, &T::default());
+    }
+
+    //non default cells in box, empty subtrees are skipped whole
+    pub fn iter_region(&self, min : &Pos3i, size : &Vec3i) -> OctreeRegionIter<'_, T> {
+        OctreeRegionIter {
+            stack : vec![(&self.root, self.lo(), self.side())],
+            min : *min,
+            max : min + size,
+            block : None
+        }
+    }
+
+    pub fn iter(&self) -> OctreeRegionIter<'_, T> {
+        let (lo, size) = self.bounds();
+        self.iter_region(&lo, &size)
+    }
+
+    pub fn is_box_empty(&self, min : &Pos3i, size : &Vec3i) -> bool {
+        self.iter_region(min, size).next().is_none()
+    }
+
+    //same traversal and results as VoxelMap::raycast, but whole empty leaves are crossed in one step
+    pub fn raycast<F>(&self, ray : &Ray, max_dist : f32, is_solid : F) -> Option<VoxelHit>
+        where F : Fn(&T) -> bool {
+
+        let dir = ray.dir.normalize();
+        //voxel i covers [i, i + 1) in this space
+        let start = ray.pos.coords / self.voxel_size + Vec3::new(0.5, 0.5, 0.5);
+        let max_t = max_dist / self.voxel_size;
+
+        let (root_lo, root_size) = self.bounds();
+        let root_lo = root_lo.coords.cast::<f32>();
+        let root_hi = root_lo + root_size.cast::<f32>();
+
+        //enter root cube first, outside of it everything is empty
+        let mut t = 0.0;
+        let mut normal = Vec3i::zeros();
+        for i in 0..3 {
+            let (near, far) = if dir[i] > 0.0 {
+                ((root_lo[i] - start[i]) / dir[i], (root_hi[i] - start[i]) / dir[i])
+            } else if dir[i] < 0.0 {
+                ((root_hi[i] - start[i]) / dir[i], (root_lo[i] - start[i]) / dir[i])
+            } else if start[i] < root_lo[i] || start[i] >= root_hi[i] {
+                return None;
+            } else {
+                continue;
+            };
+            if near > t {
+                t = near;
+                normal = Vec3i::zeros();
+                normal[i] = if dir[i] > 0.0 { -1 } else { 1 };
+            }
+            if far <= t {
+                return None;
+            }
+        }
+
+        let entry = start + dir * t;
+        let mut voxel = Pos3i::new(
+            entry.x.floor() as i32,
+            entry.y.floor() as i32,
+            entry.z.floor() as i32);
+        if normal != Vec3i::zeros() {
+            let axis = normal.iamax();
+            voxel[axis] = if normal[axis] < 0 { root_lo[axis] as i32 } else { root_hi[axis] as i32 - 1 };
+        }
+
+        while t <= max_t {
+            //float error at leaf borders must not leave the cube we just entered
+            if !self.contains(&voxel) {
+                return None;
+            }
+            let (val, lo, size) = self.leaf_at(&voxel);
+            if is_solid(val) {
+                return Some(VoxelHit {
+                    voxel,
+                    normal,
+                    distance : t * self.voxel_size
+                });
+            }
+
+            //exit of whole leaf cube
+            let hi = lo + Vec3i::repeat(size);
+            let mut exit = f32::INFINITY;
+            let mut axis = 0;
+            for i in 0..3 {
+                let t_i = if dir[i] > 0.0 {
+                    (hi[i] as f32 - start[i]) / dir[i]
+                } else if dir[i] < 0.0 {
+                    (lo[i] as f32 - start[i]) / dir[i]
+                } else {
+                    f32::INFINITY
+                };
+                if t_i < exit {
+                    exit = t_i;
+                    axis = i;
+                }
+            }
+            if exit == f32::INFINITY {
+                break;
+            }
+
+            let point = start + dir * exit;
+            for i in 0..3 {
+                voxel[i] = (point[i].floor() as i32).clamp(lo[i], hi[i] - 1);
+            }
+            if dir[axis] > 0.0 {
+                voxel[axis] = hi[axis];
+                normal = Vec3i::zeros();
+                normal[axis] = -1;
+            } else {
+                voxel[axis] = lo[axis] - 1;
+                normal = Vec3i::zeros();
+                normal[axis] = 1;
+            }
+            t = exit.max(t);
+        }
+
+        None
+    }
+
+    pub fn node_count(&self) -> usize {
+        fn count<T>(node : &Node<T>) -> usize {
+            match node {
+                Node::Leaf(_) => 1,
+                Node::Branch(children) => 1 + children.iter().map(count).sum::<usize>()
+            }
+        }
+        count(&self.root)
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.node_count() * std::mem::size_of::<Node<T>>()
+    }
+
+    pub fn from_map(map : &VoxelMap<T>) -> Self {
+        let mut res = Self::new(map.voxel_size);
+        for chunk in map.map.values() {
+            for z in 0..chunk.size.z {
+                for y in 0..chunk.size.y {
+                    for x in 0..chunk.size.x {
+                        let val = chunk.get(x, y, z);
+                        if *val != T::default() {
+                            res.set_voxel(&(chunk.origin + Vec3i::new(x, y, z)), val.clone());
+                        }
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    //only chunks with solid cells are created
+    pub fn to_map(&self, chunk_size : Vec3i) -> VoxelMap<T> {
+        let mut map = VoxelMap::new(self.voxel_size, chunk_size);
+        for (pos, val) in self.iter() {
+            map.set_voxel(&pos, val.clone());
+        }
+        map
+    }
+}
+
+pub struct OctreeRegionIter<'a, T> {
+    stack : Vec<(&'a Node<T>, Pos3i, i32)>,
+    min : Pos3i,
+    //exclusive
+    max : Pos3i,
+    //solid leaf clipped to region: value, min, max, next cell
+    block : Option<(&'a T, Pos3i, Pos3i, Pos3i)>
+}
+
+impl<'a, T> Iterator for OctreeRegionIter<'a, T>
+    where T : Default + PartialEq {
+    type Item = (Pos3i, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((val, min, max, cur)) = &mut self.block {
+                let res = *cur;
+                cur.x += 1;
+                if cur.x == max.x {
+                    cur.x = min.x;
+                    cur.y += 1;
+                    if cur.y == max.y {
+                        cur.y = min.y;
+                        cur.z += 1;
+                    }
+                }
+                let val = *val;
+                if cur.z == max.z {
+                    self.block = None;
+                }
+                return Some((res, val));
+            }
+
+            let (node, lo, size) = self.stack.pop()?;
+            let hi = lo + Vec3i::repeat(size);
+            if (0..3).any(|i| self.max[i] <= lo[i] || self.min[i] >= hi[i]) {
+                continue;
+            }
+            match node {
+                Node::Leaf(val) => {
+                    if *val == T::default() {
+                        continue;
+                    }
+                    let min = lo.sup(&self.min);
+                    let max = hi.inf(&self.max);
+                    self.block = Some((val, min, max, min));
+                }
+                Node::Branch(children) => {
+                    let half = size / 2;
+                    for (idx, child) in children.iter().enumerate().rev() {
+                        self.stack.push((child, child_lo(&lo, half, idx), half));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+    use super::*;
+
+    fn scattered_map() -> VoxelMap<i32> {
+        let mut map = VoxelMap::new(0.5, [8, 8, 8].into());
+        map.fill_box(&Pos3i::new(-40, -3, 5), &Vec3i::new(12, 6, 9), &1);
+        map.fill_box(&Pos3i::new(60, 20, -70), &Vec3i::new(16, 16, 16), &2);
+        map.clear_box(&Pos3i::new(64, 24, -66), &Vec3i::new(8, 8, 8));
+        map.set_voxel(&Pos3i::new(-200, 100, 3), 3);
+        map
+    }
+
+    #[test]
+    fn refuses_cells_out_of_range() {
+        let mut tree = VoxelOctree::<i32>::new(1.0);
+        assert!(!tree.set_voxel(&Pos3i::new(1 << 29, 0, 0), 4));
+        assert!(!tree.set_voxel(&Pos3i::new(i32::MIN, 0, 0), 4));
+        assert!(!tree.fill_box(&Pos3i::new(-(1 << 29) - 1, 0, 0), &Vec3i::new(2, 2, 2), &4));
+        assert!(tree.set_voxel(&Pos3i::new((1 << 29) - 1, 0, 0), 4));
+        assert!(tree.set_voxel(&Pos3i::new(-(1 << 29), 0, 0), 4));
+        assert_eq!(tree.get_voxel(&Pos3i::new((1 << 29) - 1, 0, 0)), Some(&4));
+    }
+
+    #[test]
+    fn set_grow_and_collapse() {
+        let mut tree = VoxelOctree::<i32>::new(1.0);
+        assert_eq!(tree.get_voxel(&Pos3i::new(1, 2, 3)), Some(&0));
+        assert_eq!(tree.get_voxel(&Pos3i::new(1000, 0, 0)), None);
+
+        assert!(tree.set_voxel(&Pos3i::new(1000, -5, 7), 4));
+        assert!(!tree.set_voxel(&Pos3i::new(1000, -5, 7), 4));
+        assert_eq!(tree.get_voxel(&Pos3i::new(1000, -5, 7)), Some(&4));
+        assert_eq!(tree.get_voxel(&Pos3i::new(1000, -5, 8)), Some(&0));
+        //clearing outside of bounds does not grow
+        assert!(!tree.set_voxel(&Pos3i::new(1 << 20, 0, 0), 0));
+
+        assert!(tree.set_voxel(&Pos3i::new(1000, -5, 7), 0));
+        assert_eq!(tree.node_count(), 1);
+
+        tree.fill_box(&Pos3i::new(0, 0, 0), &Vec3i::new(16, 16, 16), &2);
+        assert_eq!(tree.iter().count(), 16 * 16 * 16);
+        //aligned box is a single leaf inside branches
+        assert!(tree.node_count() < 64);
+    }
+
+    #[test]
+    fn matches_chunk_map() {
+        let map = scattered_map();
+        let tree = VoxelOctree::from_map(&map);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..2000 {
+            let pos = Pos3i::new(rng.gen_range(-220..80), rng.gen_range(-10..120), rng.gen_range(-80..20));
+            assert_eq!(tree.get_voxel(&pos).cloned().unwrap_or_default(),
+                map.get_voxel(&pos).cloned().unwrap_or_default());
+        }
+
+        let min = Pos3i::new(-38, -10, 0);
+        let size = Vec3i::new(120, 40, 12);
+        let mut expected : Vec<(Pos3i, i32)> = map.iter_region(&min, &size).map(|(p, v)| (p, *v)).collect();
+        let mut got : Vec<(Pos3i, i32)> = tree.iter_region(&min, &size).map(|(p, v)| (p, *v)).collect();
+        expected.sort_by_key(|(p, _)| (p.x, p.y, p.z));
+        got.sort_by_key(|(p, _)| (p.x, p.y, p.z));
+        assert_eq!(expected, got);
+
+        let back = tree.to_map([16, 16, 16].into());
+        assert_eq!(back.iter_region(&Pos3i::new(-256, -256, -256), &Vec3i::repeat(512)).count(),
+            tree.iter().count());
+        assert_eq!(back.get_voxel(&Pos3i::new(-200, 100, 3)), Some(&3));
+    }
+
+    #[test]
+    fn raycast_matches_chunk_map() {
+        let map = scattered_map();
+        let tree = VoxelOctree::from_map(&map);
+
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut hits = 0;
+        for _ in 0..500 {
+            let mut unit = || rng.gen_range(-1.0..1.0);
+            let target = if hits % 2 == 0 { Vec3::new(-17.0, 0.0, 4.5) } else { Vec3::new(34.0, 14.0, -31.0) };
+            let pos = Pos3::new(unit() * 60.0, unit() * 60.0, unit() * 60.0);
+            let dir = target - pos.coords + Vec3::new(unit(), unit(), unit()) * 4.0;
+            let ray = Ray { pos, dir };
+
+            let expected = map.raycast(&ray, 150.0, |v| *v != 0);
+            let got = tree.raycast(&ray, 150.0, |v| *v != 0);
+            assert_eq!(expected.as_ref().map(|h| (h.voxel, h.normal)), got.as_ref().map(|h| (h.voxel, h.normal)), "ray from {:?} along {:?}", ray.pos, ray.dir);
+            if let (Some(a), Some(b)) = (expected, got) {
+                assert!((a.distance - b.distance).abs() < 1e-3);
+                hits += 1;
+            }
+        }
+        assert!(hits > 100);
+    }
+}
@@ -0,0 +1,349 @@
+use std::ops::{Deref, DerefMut};
+use space_core::{Pos3i, Vec3i};
+use crate::solid_voxel_map::VoxelChunk;
+
+enum PaletteStorage<T> {
+    Uniform(T),
+    Packed {
+        palette : Vec<T>,
+        //cells using each palette entry, free entries are reused
+        counts : Vec<u32>,
+        bits : usize,
+        words : Vec<u64>
+    }
+}
+
+//same api as VoxelChunk, but keeps palette + bit-packed indices
+//only difference is get_mut: cells are packed, so it hands out a PaletteCellMut guard instead of &mut T
+//palette looks after itself: one value left turns back to uniform, mostly unused palette is repacked
+//VoxelMap keeps dense chunks, code there relies on &mut T cells.
+//chunks that are kept but rarely touched go through from_chunk / to_chunk
+pub struct PaletteChunk<T> {
+    pub origin : Pos3i,
+    pub size : Vec3i,
+    storage : PaletteStorage<T>
+}
+
+//palette never outgrows chunk volume, so 32 bits is plenty and keeps shifts in range
+const MAX_BITS : usize = 32;
+
+fn bits_for(count : usize) -> usize {
+    let mut bits = 1;
+    while bits < MAX_BITS && (1usize << bits) < count {
+        bits += 1;
+    }
+    bits
+}
+
+fn word_count(len : usize, bits : usize) -> usize {
+    let per_word = 64 / bits;
+    len.div_ceil(per_word)
+}
+
+fn read_index(words : &[u64], bits : usize, idx : usize) -> usize {
+    let per_word = 64 / bits;
+    let shift = (idx % per_word) * bits;
+    ((words[idx / per_word] >> shift) & ((1u64 << bits) - 1)) as usize
+}
+
+fn write_index(words : &mut [u64], bits : usize, idx : usize, val : usize) {
+    let per_word = 64 / bits;
+    let shift = (idx % per_word) * bits;
+    let mask = ((1u64 << bits) - 1) << shift;
+    let word = &mut words[idx / per_word];
+    *word = (*word & !mask) | ((val as u64) << shift);
+}
+
+impl<T> PaletteChunk<T>
+    where T : Default + Clone + PartialEq {
+
+    pub fn new(origin : Pos3i, size : Vec3i) -> PaletteChunk<T> {
+        PaletteChunk {
+            origin,
+            size,
+            storage : PaletteStorage::Uniform(T::default())
+        }
+    }
+
+    pub fn from_chunk(chunk : &VoxelChunk<T>) -> PaletteChunk<T> {
+        let mut res = PaletteChunk::new(chunk.origin, chunk.size);
+        for (idx, val) in chunk.data.iter().enumerate() {
+            res.set_index(idx, val.clone());
+        }
+        res
+    }
+
+    pub fn to_chunk(&self) -> VoxelChunk<T> {
+        let mut res = VoxelChunk::new(self.origin, self.size);
+        for idx in 0..self.len() {
+            res.data[idx] = self.get_index(idx).clone();
+        }
+        res
+    }
+
+    pub fn len(&self) -> usize {
+        (self.size.x * self.size.y * self.size.z) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_uniform(&self) -> bool {
+        matches!(self.storage, PaletteStorage::Uniform(_))
+    }
+
+    pub fn palette_len(&self) -> usize {
+        match &self.storage {
+            PaletteStorage::Uniform(_) => 1,
+            PaletteStorage::Packed { palette, .. } => palette.len()
+        }
+    }
+
+    //heap + inline bytes, without memory owned by T itself
+    pub fn memory_usage(&self) -> usize {
+        let heap = match &self.storage {
+            PaletteStorage::Uniform(_) => 0,
+            PaletteStorage::Packed { palette, counts, words, .. } => {
+                palette.capacity() * std::mem::size_of::<T>()
+                    + counts.capacity() * std::mem::size_of::<u32>()
+                    + words.capacity() * std::mem::size_of::<u64>()
+            }
+        };
+        std::mem::size_of::<Self>() + heap
+    }
+
+    fn linear_index(&self, x : i32, y : i32, z : i32) -> usize {
+        ((z * self.size.y + y) * self.size.x + x) as usize
+    }
+
+    fn get_index(&self, idx : usize) -> &T {
+        match &self.storage {
+            PaletteStorage::Uniform(val) => val,
+            PaletteStorage::Packed { palette, bits, words, .. } => {
+                &palette[read_index(words, *bits, idx)]
+            }
+        }
+    }
+
+    fn set_index(&mut self, idx : usize, val : T) {
+        let len = self.len();
+        if let PaletteStorage::Uniform(uniform) = &self.storage {
+            if *uniform == val {
+                return;
+            }
+            self.storage = PaletteStorage::Packed {
+                palette : vec![uniform.clone()],
+                counts : vec![len as u32],
+                bits : 1,
+                words : vec![0; word_count(len, 1)]
+            };
+        }
+
+        let PaletteStorage::Packed { palette, counts, bits, words } = &mut self.storage else {
+            return;
+        };
+        let old_idx = read_index(words, *bits, idx);
+        if palette[old_idx] == val {
+            return;
+        }
+        let palette_idx = match palette.iter().position(|v| *v == val) {
+            Some(p) => p,
+            None => match counts.iter().position(|c| *c == 0) {
+                Some(p) => {
+                    palette[p] = val;
+                    p
+                }
+                None => {
+                    palette.push(val);
+                    counts.push(0);
+                    if palette.len() > (1 << *bits) {
+                        let new_bits = bits_for(palette.len());
+                        let mut new_words = vec![0; word_count(len, new_bits)];
+                        for i in 0..len {
+                            write_index(&mut new_words, new_bits, i, read_index(words, *bits, i));
+                        }
+                        *bits = new_bits;
+                        *words = new_words;
+                    }
+                    palette.len() - 1
+                }
+            }
+        };
+        counts[old_idx] -= 1;
+        counts[palette_idx] += 1;
+        write_index(words, *bits, idx, palette_idx);
+
+        if counts[palette_idx] as usize == len {
+            self.storage = PaletteStorage::Uniform(palette.swap_remove(palette_idx));
+        } else if palette.len() > 2 && counts.iter().filter(|c| **c == 0).count() * 2 > palette.len() {
+            self.compact();
+        }
+    }
+
+    pub fn get(&self, x : i32, y : i32, z : i32) -> &T {
+        self.get_index(self.linear_index(x, y, z))
+    }
+
+    //edits a copy of the cell, it is stored back when the guard drops
+    pub fn get_mut(&mut self, x : i32, y : i32, z : i32) -> PaletteCellMut<'_, T> {
+        let idx = self.linear_index(x, y, z);
+        let val = self.get_index(idx).clone();
+        PaletteCellMut {
+            chunk : self,
+            idx,
+            val
+        }
+    }
+
+    pub fn set(&mut self, x : i32, y : i32, z : i32, val : T) {
+        let idx = self.linear_index(x, y, z);
+        self.set_index(idx, val);
+    }
+
+    pub fn fill(&mut self, val : &T) {
+        self.storage = PaletteStorage::Uniform(val.clone());
+    }
+
+    //drops unused palette entries and repacks indices with fewer bits, set does it on its own
+    pub fn compact(&mut self) {
+        if self.is_uniform() {
+            return;
+        }
+        let values : Vec<T> = (0..self.len()).map(|i| self.get_index(i).clone()).collect();
+        self.storage = PaletteStorage::Uniform(values[0].clone());
+        for (idx, val) in values.into_iter().enumerate() {
+            self.set_index(idx, val);
+        }
+    }
+}
+
+//writes value back into the palette on drop
+pub struct PaletteCellMut<'a, T>
+    where T : Default + Clone + PartialEq {
+    chunk : &'a mut PaletteChunk<T>,
+    idx : usize,
+    val : T
+}
+
+impl<'a, T> Deref for PaletteCellMut<'a, T>
+    where T : Default + Clone + PartialEq {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.val
+    }
+}
+
+impl<'a, T> DerefMut for PaletteCellMut<'a, T>
+    where T : Default + Clone + PartialEq {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.val
+    }
+}
+
+impl<'a, T> Drop for PaletteCellMut<'a, T>
+    where T : Default + Clone + PartialEq {
+    fn drop(&mut self) {
+        if *self.chunk.get_index(self.idx) != self.val {
+            self.chunk.set_index(self.idx, self.val.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_chunk() -> PaletteChunk<i32> {
+        PaletteChunk::new(Pos3i::new(0, 0, 0), Vec3i::new(16, 16, 16))
+    }
+
+    #[test]
+    fn get_set_test() {
+        let mut chunk = new_chunk();
+        assert_eq!(*chunk.get(5, 5, 5), 0);
+        assert!(chunk.is_uniform());
+
+        chunk.set(5, 5, 5, 10);
+        assert_eq!(*chunk.get(5, 5, 5), 10);
+        assert_eq!(*chunk.get(5, 5, 4), 0);
+        assert!(!chunk.is_uniform());
+
+        *chunk.get_mut(1, 2, 3) = 7;
+        assert_eq!(*chunk.get(1, 2, 3), 7);
+        assert_eq!(chunk.palette_len(), 3);
+    }
+
+    #[test]
+    fn palette_growth() {
+        let mut chunk = new_chunk();
+        for i in 0..300 {
+            chunk.set(i % 16, (i / 16) % 16, i / 256, i);
+        }
+        for i in 0..300 {
+            assert_eq!(*chunk.get(i % 16, (i / 16) % 16, i / 256), i);
+        }
+        assert_eq!(*chunk.get(15, 15, 15), 0);
+    }
+
+    #[test]
+    fn bits_stay_in_range() {
+        assert_eq!(bits_for(2), 1);
+        assert_eq!(bits_for(300), 9);
+        assert_eq!(bits_for(usize::MAX), MAX_BITS);
+    }
+
+    #[test]
+    fn fill_test() {
+        let mut chunk = new_chunk();
+        chunk.set(1, 1, 1, 3);
+        chunk.fill(&11);
+        assert!(chunk.is_uniform());
+        assert_eq!(*chunk.get(1, 1, 1), 11);
+        assert_eq!(*chunk.get(5, 5, 5), 11);
+    }
+
+    #[test]
+    fn compact_test() {
+        let mut chunk = new_chunk();
+        chunk.set(1, 1, 1, 3);
+        chunk.set(2, 1, 1, 4);
+        chunk.set(1, 1, 1, 0);
+        //freed entry is reused
+        chunk.set(1, 1, 1, 5);
+        assert_eq!(chunk.palette_len(), 3);
+        chunk.set(1, 1, 1, 0);
+        chunk.set(2, 1, 1, 0);
+        assert!(chunk.is_uniform());
+        assert_eq!(*chunk.get(1, 1, 1), 0);
+
+        //mostly unused palette is repacked without compact call
+        for i in 1..=8 {
+            chunk.set(i, 0, 0, i);
+        }
+        assert_eq!(chunk.palette_len(), 9);
+        let full = chunk.memory_usage();
+        for i in 1..=6 {
+            chunk.set(i, 0, 0, 0);
+        }
+        assert!(chunk.palette_len() < 9);
+        assert!(chunk.memory_usage() < full);
+        assert_eq!(*chunk.get(7, 0, 0), 7);
+        assert_eq!(*chunk.get(8, 0, 0), 8);
+        assert_eq!(*chunk.get(3, 0, 0), 0);
+    }
+
+    #[test]
+    fn dense_round_trip() {
+        let mut dense = VoxelChunk::<i32>::new(Pos3i::new(-16, 0, 16), Vec3i::new(16, 16, 16));
+        *dense.get_mut(3, 4, 5) = 2;
+        *dense.get_mut(15, 15, 15) = 9;
+
+        let packed = PaletteChunk::from_chunk(&dense);
+        assert_eq!(packed.origin, dense.origin);
+        assert_eq!(*packed.get(3, 4, 5), 2);
+        assert_eq!(packed.to_chunk().data, dense.data);
+        assert!(packed.memory_usage() < dense.memory_usage());
+    }
+}
@@ -0,0 +1,319 @@
+use space_core::nalgebra::Matrix3;
+use space_core::{Pos3i, Vec3i};
+use crate::change_log::MapChange;
+use crate::solid_voxel_map::{VoxelChunk, VoxelMap};
+
+//standalone block of voxels, result of VoxelMap::copy_region
+#[derive(Clone, Debug, PartialEq)]
+pub struct VoxelBuffer<T> {
+    pub size : Vec3i,
+    pub data : Vec<T>
+}
+
+impl<T> VoxelBuffer<T>
+    where T : Default + Clone {
+
+    pub fn new(size : Vec3i) -> VoxelBuffer<T> {
+        VoxelBuffer {
+            size,
+            data : vec![T::default(); (size.x * size.y * size.z) as usize]
+        }
+    }
+
+    pub fn get(&self, x : i32, y : i32, z : i32) -> &T {
+        &self.data[((z * self.size.y + y) * self.size.x + x) as usize]
+    }
+
+    pub fn get_mut(&mut self, x : i32, y : i32, z : i32) -> &mut T {
+        &mut self.data[((z * self.size.y + y) * self.size.x + x) as usize]
+    }
+}
+
+//rotation by quarter turns, keeps voxels on the grid
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GridRotation {
+    pub mat : Matrix3<i32>
+}
+
+impl Default for GridRotation {
+    fn default() -> Self {
+        GridRotation::identity()
+    }
+}
+
+impl GridRotation {
+    pub fn identity() -> Self {
+        Self {
+            mat : Matrix3::identity()
+        }
+    }
+
+    fn quarter(turns : i32) -> (i32, i32) {
+        match turns.rem_euclid(4) {
+            0 => (1, 0),
+            1 => (0, 1),
+            2 => (-1, 0),
+            _ => (0, -1)
+        }
+    }
+
+    pub fn around_x(turns : i32) -> Self {
+        let (c, s) = GridRotation::quarter(turns);
+        Self {
+            mat : Matrix3::new(
+                1, 0, 0,
+                0, c, -s,
+                0, s, c)
+        }
+    }
+
+    pub fn around_y(turns : i32) -> Self {
+        let (c, s) = GridRotation::quarter(turns);
+        Self {
+            mat : Matrix3::new(
+                c, 0, s,
+                0, 1, 0,
+                -s, 0, c)
+        }
+    }
+
+    pub fn around_z(turns : i32) -> Self {
+        let (c, s) = GridRotation::quarter(turns);
+        Self {
+            mat : Matrix3::new(
+                c, -s, 0,
+                s, c, 0,
+                0, 0, 1)
+        }
+    }
+
+    //first self, then other
+    pub fn then(&self, other : &GridRotation) -> GridRotation {
+        GridRotation {
+            mat : other.mat * self.mat
+        }
+    }
+
+    pub fn apply(&self, v : &Vec3i) -> Vec3i {
+        self.mat * v
+    }
+
+    pub fn rotate_size(&self, size : &Vec3i) -> Vec3i {
+        self.apply(size).abs()
+    }
+}
+
+impl<T> VoxelMap<T>
+    where T : Default + Clone + PartialEq {
+
+    //(chunk origin, local min, local max exclusive) for every chunk touching the box
+    fn chunk_spans(&self, min : &Pos3i, size : &Vec3i) -> Vec<(Pos3i, Vec3i, Vec3i)> {
+        let mut res = vec![];
+        if size.x <= 0 || size.y <= 0 || size.z <= 0 {
+            return res;
+        }
+        let max = min + size;
+        let first = self.get_origin(min);
+        let last = self.get_origin(&(max - Vec3i::new(1, 1, 1)));
+
+        let mut cz = first.z;
+        while cz <= last.z {
+            let mut cy = first.y;
+            while cy <= last.y {
+                let mut cx = first.x;
+                while cx <= last.x {
+                    let origin = Pos3i::new(cx, cy, cz);
+                    let local_min = min.coords.sup(&origin.coords) - origin.coords;
+                    let local_max = max.coords.inf(&(origin.coords + self.chunk_size)) - origin.coords;
+                    res.push((origin, local_min, local_max));
+                    cx += self.chunk_size.x;
+                }
+                cy += self.chunk_size.y;
+            }
+            cz += self.chunk_size.z;
+        }
+        res
+    }
+
+    pub fn fill_box(&mut self, min : &Pos3i, size : &Vec3i, val : &T) {
+        let chunk_size = self.chunk_size;
+        for (origin, local_min, local_max) in self.chunk_spans(min, size) {
+            if *val == T::default() && !self.map.contains_key(&origin) {
+                continue;
+            }
+            let chunk = self.map.entry(origin)
+                .or_insert_with(|| VoxelChunk::new(origin, chunk_size));
+            for z in local_min.z..local_max.z {
+                for y in local_min.y..local_max.y {
+                    for x in local_min.x..local_max.x {
+                        let cell = chunk.get_mut(x, y, z);
+                        if *cell != *val {
+                            let old = std::mem::replace(cell, val.clone());
+                            self.changes.push(MapChange::Voxel {
+                                pos : origin + Vec3i::new(x, y, z),
+                                old,
+                                new : val.clone()
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    //creates missing chunks covering the box, cells are untouched
+    pub fn allocate_box(&mut self, min : &Pos3i, size : &Vec3i) {
+        let chunk_size = self.chunk_size;
+        for (origin, _, _) in self.chunk_spans(min, size) {
+            self.map.entry(origin)
+                .or_insert_with(|| VoxelChunk::new(origin, chunk_size));
+        }
+    }
+
+    pub fn clear_box(&mut self, min : &Pos3i, size : &Vec3i) {
+        self.fill_box(min, size, &T::default());
+    }
+
+    pub fn is_box_empty(&self, min : &Pos3i, size : &Vec3i) -> bool {
+        self.iter_region(min, size).next().is_none()
+    }
+
+    pub fn copy_region(&self, min : &Pos3i, size : &Vec3i) -> VoxelBuffer<T> {
+        let mut res = VoxelBuffer::new(*size);
+        for (pos, val) in self.iter_region(min, size) {
+            let lp = pos - min;
+            *res.get_mut(lp.x, lp.y, lp.z) = val.clone();
+        }
+        res
+    }
+
+    //rotated buffer keeps its min corner at min, default cells do not overwrite the map
+    pub fn paste(&mut self, buffer : &VoxelBuffer<T>, min : &Pos3i, rot : &GridRotation) {
+        if buffer.data.is_empty() {
+            return;
+        }
+        let corner = rot.apply(&(buffer.size - Vec3i::new(1, 1, 1)));
+        let shift = corner.inf(&Vec3i::zeros());
+
+        for z in 0..buffer.size.z {
+            for y in 0..buffer.size.y {
+                for x in 0..buffer.size.x {
+                    let val = buffer.get(x, y, z);
+                    if *val == T::default() {
+                        continue;
+                    }
+                    let pos = min + rot.apply(&Vec3i::new(x, y, z)) - shift;
+                    self.set_voxel(&pos, val.clone());
+                }
+            }
+        }
+    }
+
+    //non default cells inside the box
+    pub fn iter_region(&self, min : &Pos3i, size : &Vec3i) -> impl Iterator<Item = (Pos3i, &T)> + '_ {
+        self.chunk_spans(min, size).into_iter()
+            .filter_map(|(origin, local_min, local_max)| {
+                self.map.get(&origin).map(|chunk| (chunk, local_min, local_max))
+            })
+            .flat_map(|(chunk, local_min, local_max)| {
+                (local_min.z..local_max.z).flat_map(move |z| {
+                    (local_min.y..local_max.y).flat_map(move |y| {
+                        (local_min.x..local_max.x).map(move |x| {
+                            (chunk.origin + Vec3i::new(x, y, z), chunk.get(x, y, z))
+                        })
+                    })
+                })
+            })
+            .filter(|(_, val)| **val != T::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_map() -> VoxelMap<i32> {
+        VoxelMap::new(1.0, [4, 4, 4].into())
+    }
+
+    #[test]
+    fn fill_across_chunks() {
+        let mut map = new_map();
+        let cursor = map.changes.subscribe();
+        map.fill_box(&Pos3i::new(-3, -1, 2), &Vec3i::new(6, 2, 3), &5);
+
+        assert_eq!(map.iter_region(&Pos3i::new(-10, -10, -10), &Vec3i::new(20, 20, 20)).count(), 36);
+        assert_eq!(map.get_voxel(&Pos3i::new(-3, -1, 2)), Some(&5));
+        assert_eq!(map.get_voxel(&Pos3i::new(2, 0, 4)), Some(&5));
+        assert_eq!(map.get_voxel(&Pos3i::new(3, 0, 4)), Some(&0));
+        let changes = map.changes.drain(cursor);
+        assert_eq!(changes.len(), 36);
+        let touched = map.touched_chunks(&changes);
+        assert_eq!(touched.len(), 8);
+        assert!(touched.contains(&Pos3i::new(-4, -4, 4)));
+
+        map.clear_box(&Pos3i::new(-3, -1, 2), &Vec3i::new(3, 2, 3));
+        assert_eq!(map.iter_region(&Pos3i::new(-10, -10, -10), &Vec3i::new(20, 20, 20)).count(), 18);
+        assert!(!map.is_box_empty(&Pos3i::new(0, -1, 2), &Vec3i::new(1, 1, 1)));
+        assert!(map.is_box_empty(&Pos3i::new(-3, -1, 2), &Vec3i::new(3, 2, 3)));
+        //chunks right of the cleared box see it through their borders
+        let changes = map.changes.drain(cursor);
+        assert_eq!(changes.len(), 18);
+        assert_eq!(map.touched_chunks(&changes).len(), 8);
+    }
+
+    #[test]
+    fn clear_does_not_create_chunks() {
+        let mut map = new_map();
+        let cursor = map.changes.subscribe();
+        map.clear_box(&Pos3i::new(0, 0, 0), &Vec3i::new(10, 10, 10));
+        assert!(map.map.is_empty());
+        assert_eq!(map.changes.pending(cursor), 0);
+    }
+
+    #[test]
+    fn copy_and_paste() {
+        let mut map = new_map();
+        map.set_voxel(&Pos3i::new(0, 0, 0), 1);
+        map.set_voxel(&Pos3i::new(1, 0, 0), 2);
+        map.set_voxel(&Pos3i::new(2, 0, 0), 3);
+
+        let buffer = map.copy_region(&Pos3i::new(0, 0, 0), &Vec3i::new(3, 1, 1));
+        assert_eq!(buffer.data, vec![1, 2, 3]);
+
+        map.paste(&buffer, &Pos3i::new(10, 10, 10), &GridRotation::identity());
+        assert_eq!(map.copy_region(&Pos3i::new(10, 10, 10), &Vec3i::new(3, 1, 1)), buffer);
+
+        //x axis turns into y axis
+        let rot = GridRotation::around_z(1);
+        assert_eq!(rot.rotate_size(&buffer.size), Vec3i::new(1, 3, 1));
+        map.paste(&buffer, &Pos3i::new(-5, -5, -5), &rot);
+        assert_eq!(map.get_voxel(&Pos3i::new(-5, -5, -5)), Some(&1));
+        assert_eq!(map.get_voxel(&Pos3i::new(-5, -3, -5)), Some(&3));
+
+        //negative direction still starts at min corner
+        let rot = GridRotation::around_y(2);
+        map.paste(&buffer, &Pos3i::new(20, 0, 0), &rot);
+        assert_eq!(map.get_voxel(&Pos3i::new(20, 0, 0)), Some(&3));
+        assert_eq!(map.get_voxel(&Pos3i::new(22, 0, 0)), Some(&1));
+    }
+
+    #[test]
+    fn paste_skips_empty_cells() {
+        let mut map = new_map();
+        map.fill_box(&Pos3i::new(0, 0, 0), &Vec3i::new(3, 1, 1), &7);
+
+        let mut buffer = VoxelBuffer::new(Vec3i::new(3, 1, 1));
+        *buffer.get_mut(1, 0, 0) = 9;
+        map.paste(&buffer, &Pos3i::new(0, 0, 0), &GridRotation::identity());
+
+        assert_eq!(map.copy_region(&Pos3i::new(0, 0, 0), &Vec3i::new(3, 1, 1)).data, vec![7, 9, 7]);
+    }
+
+    #[test]
+    fn rotation_compose() {
+        let rot = GridRotation::around_x(1).then(&GridRotation::around_x(3));
+        assert_eq!(rot, GridRotation::identity());
+        assert_eq!(GridRotation::around_y(1).apply(&Vec3i::new(0, 0, 1)), Vec3i::new(1, 0, 0));
+    }
+}
@@ -0,0 +1,193 @@
+use std::sync::{Arc, Mutex};
+use space_core::bevy::utils::HashSet;
+use space_core::{Pos3, Pos3i, TaskServer};
+use crate::objected_voxel_map::ChunkBorders;
+use crate::solid_voxel_map::{VoxelChunk, VoxelMap};
+
+//builds mesh of chunk snapshot, runs on task server threads
+//extra is data snapshotted next to chunk, e.g. its light
+pub type ChunkMesher<T, M, S = ()> = Arc<dyn Fn(&VoxelChunk<T>, &ChunkBorders<T>, &S) -> M + Send + Sync>;
+
+type MeshQueue<M> = Arc<Mutex<Vec<(Pos3i, M)>>>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RemeshResult<M> {
+    Meshed(Pos3i, M),
+    //chunk is gone from map, its mesh should be dropped
+    Removed(Pos3i)
+}
+
+//meshes dirty chunks in background, nearest to focus first
+pub struct RemeshScheduler<T, M, S = ()> {
+    pub mesher : ChunkMesher<T, M, S>,
+    pub max_in_flight : usize,
+    dirty : HashSet<Pos3i>,
+    in_flight : HashSet<Pos3i>,
+    finished : MeshQueue<M>,
+    removed : Vec<Pos3i>
+}
+
+impl<T, M, S> RemeshScheduler<T, M, S>
+    where T : Default + Clone + Send + Sync + 'static, M : Send + 'static, S : Send + Sync + 'static {
+
+    pub fn new(mesher : ChunkMesher<T, M, S>, max_in_flight : usize) -> Self {
+        Self {
+            mesher,
+            max_in_flight : max_in_flight.max(1),
+            dirty : HashSet::new(),
+            in_flight : HashSet::new(),
+            finished : Arc::new(Mutex::new(vec![])),
+            removed : vec![]
+        }
+    }
+
+    pub fn mark_dirty(&mut self, origin : Pos3i) {
+        self.dirty.insert(origin);
+    }
+
+    pub fn mark_all(&mut self, map : &VoxelMap<T>) {
+        self.dirty.extend(map.map.keys().cloned());
+    }
+
+    pub fn dirty_count(&self) -> usize {
+        self.dirty.len()
+    }
+
+    pub fn in_flight_count(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    pub fn is_busy(&self) -> bool {
+        !self.dirty.is_empty() || !self.in_flight.is_empty()
+    }
+
+    //focus is in map space
+    pub fn update_with<F>(&mut self, map : &VoxelMap<T>, focus : &Pos3, tasks : &TaskServer, extra : F)
+        where F : Fn(&Pos3i) -> S {
+        //chunk already being meshed waits for its job, its snapshot is outdated anyway
+        let mut ready : Vec<(f32, Pos3i)> = self.dirty.iter()
+            .filter(|origin| !self.in_flight.contains(origin))
+            .map(|origin| {
+                let center = origin.cast::<f32>() + map.chunk_size.cast::<f32>() / 2.0;
+                ((center.coords * map.voxel_size - focus.coords).norm_squared(), *origin)
+            })
+            .collect();
+        ready.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let free = self.max_in_flight.saturating_sub(self.in_flight.len());
+        let mut spawned = 0;
+        for (_, origin) in ready {
+            let Some(chunk) = map.map.get(&origin) else {
+                self.dirty.remove(&origin);
+                self.removed.push(origin);
+                continue;
+            };
+            if spawned == free {
+                continue;
+            }
+            spawned += 1;
+            self.dirty.remove(&origin);
+            self.in_flight.insert(origin);
+
+            let chunk = chunk.clone();
+            let borders = ChunkBorders::from_map(map, &origin);
+            let extra = extra(&origin);
+            let mesher = self.mesher.clone();
+            let finished = self.finished.clone();
+            tasks.spawn(&format!("Meshing chunk {:?}", origin), move || {
+                let mesh = mesher(&chunk, &borders, &extra);
+                finished.lock().unwrap().push((origin, mesh));
+            });
+        }
+    }
+
+    //results ready to swap in, old meshes should be kept until then
+    pub fn drain_finished(&mut self) -> Vec<RemeshResult<M>> {
+        let finished = std::mem::take(&mut *self.finished.lock().unwrap());
+        let mut res : Vec<RemeshResult<M>> = self.removed.drain(..).map(RemeshResult::Removed).collect();
+        for (origin, mesh) in finished {
+            self.in_flight.remove(&origin);
+            res.push(RemeshResult::Meshed(origin, mesh));
+        }
+        res
+    }
+}
+
+impl<T, M> RemeshScheduler<T, M>
+    where T : Default + Clone + Send + Sync + 'static, M : Send + 'static {
+
+    pub fn update(&mut self, map : &VoxelMap<T>, focus : &Pos3, tasks : &TaskServer) {
+        self.update_with(map, focus, tasks, |_| ());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use space_core::Vec3i;
+    use super::*;
+
+    //mesh is number of solid voxels seen by chunk
+    fn counting_mesher() -> ChunkMesher<i32, usize> {
+        Arc::new(|chunk, borders, _| {
+            chunk.data.iter().filter(|v| **v != 0).count() + borders.data.iter().filter(|v| **v != 0).count()
+        })
+    }
+
+    fn wait(scheduler : &mut RemeshScheduler<i32, usize>, map : &VoxelMap<i32>, tasks : &TaskServer) -> Vec<RemeshResult<usize>> {
+        let mut res = vec![];
+        for _ in 0..500 {
+            scheduler.update(map, &Pos3::origin(), tasks);
+            res.extend(scheduler.drain_finished());
+            if !scheduler.is_busy() {
+                return res;
+            }
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        panic!("remeshing did not finish");
+    }
+
+    #[test]
+    fn nearest_chunks_go_first() {
+        let tasks = TaskServer::new();
+        let mut map = VoxelMap::<i32>::new(1.0, [4, 4, 4].into());
+        map.fill_box(&Pos3i::new(-16, 0, 0), &Vec3i::new(32, 1, 1), &1);
+
+        let mut scheduler = RemeshScheduler::new(counting_mesher(), 2);
+        scheduler.mark_all(&map);
+        assert_eq!(scheduler.dirty_count(), 8);
+
+        scheduler.update(&map, &Pos3::new(10.0, 0.0, 0.0), &tasks);
+        assert_eq!(scheduler.in_flight_count(), 2);
+        assert!(scheduler.in_flight.contains(&Pos3i::new(8, 0, 0)));
+        assert!(scheduler.in_flight.contains(&Pos3i::new(4, 0, 0)) || scheduler.in_flight.contains(&Pos3i::new(12, 0, 0)));
+
+        let res = wait(&mut scheduler, &map, &tasks);
+        assert_eq!(res.len(), 8);
+        //inner chunks also see both neighbours through borders
+        assert!(res.contains(&RemeshResult::Meshed(Pos3i::new(0, 0, 0), 6)));
+        assert!(res.contains(&RemeshResult::Meshed(Pos3i::new(-16, 0, 0), 5)));
+    }
+
+    #[test]
+    fn removed_and_repeated_chunks() {
+        let tasks = TaskServer::new();
+        let mut map = VoxelMap::<i32>::new(1.0, [4, 4, 4].into());
+        map.set_voxel(&Pos3i::new(1, 1, 1), 1);
+
+        let mut scheduler = RemeshScheduler::new(counting_mesher(), 4);
+        scheduler.mark_dirty(Pos3i::new(0, 0, 0));
+        scheduler.mark_dirty(Pos3i::new(40, 0, 0));
+        scheduler.update(&map, &Pos3::origin(), &tasks);
+
+        //dirty again while in flight, meshed once more after first job
+        map.set_voxel(&Pos3i::new(2, 1, 1), 1);
+        scheduler.mark_dirty(Pos3i::new(0, 0, 0));
+        scheduler.update(&map, &Pos3::origin(), &tasks);
+        assert_eq!(scheduler.in_flight_count(), 1);
+
+        let res = wait(&mut scheduler, &map, &tasks);
+        assert_eq!(res[0], RemeshResult::Removed(Pos3i::new(40, 0, 0)));
+        assert_eq!(res.last(), Some(&RemeshResult::Meshed(Pos3i::new(0, 0, 0), 2)));
+    }
+}
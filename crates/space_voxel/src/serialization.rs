@@ -0,0 +1,330 @@
+use std::path::Path;
+use space_core::serde::Serialize;
+use space_core::serde::de::DeserializeOwned;
+use space_core::{Pos3i, SpaceResult, Vec3i};
+use crate::solid_voxel_map::{VoxelChunk, VoxelMap};
+
+pub const VOXEL_FORMAT_VERSION : u16 = 1;
+
+const MAP_MAGIC : &[u8; 4] = b"SVXM";
+const CHUNK_MAGIC : &[u8; 4] = b"SVXC";
+
+//data comes from files and network, bigger chunks are treated as broken
+pub const MAX_CHUNK_VOLUME : usize = 1 << 24;
+
+fn check_chunk_size(size : &Vec3i) -> SpaceResult<usize> {
+    if size.x <= 0 || size.y <= 0 || size.z <= 0 {
+        return Err("Wrong voxel chunk size".into());
+    }
+    (size.x as usize).checked_mul(size.y as usize)
+        .and_then(|v| v.checked_mul(size.z as usize))
+        .filter(|v| *v <= MAX_CHUNK_VOLUME)
+        .ok_or_else(|| "Voxel chunk is too big".into())
+}
+
+struct ByteReader<'a> {
+    data : &'a [u8],
+    pos : usize
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(data : &'a [u8]) -> Self {
+        Self {
+            data,
+            pos : 0
+        }
+    }
+
+    fn read_bytes(&mut self, count : usize) -> SpaceResult<&'a [u8]> {
+        if self.pos + count > self.data.len() {
+            return Err("Unexpected end of voxel data".into());
+        }
+        let res = &self.data[self.pos..(self.pos + count)];
+        self.pos += count;
+        Ok(res)
+    }
+
+    fn read_u16(&mut self) -> SpaceResult<u16> {
+        Ok(u16::from_le_bytes(self.read_bytes(2)?.try_into()?))
+    }
+
+    fn read_u32(&mut self) -> SpaceResult<u32> {
+        Ok(u32::from_le_bytes(self.read_bytes(4)?.try_into()?))
+    }
+
+    fn read_i32(&mut self) -> SpaceResult<i32> {
+        Ok(i32::from_le_bytes(self.read_bytes(4)?.try_into()?))
+    }
+
+    fn read_f32(&mut self) -> SpaceResult<f32> {
+        Ok(f32::from_le_bytes(self.read_bytes(4)?.try_into()?))
+    }
+
+    fn read_vec3i(&mut self) -> SpaceResult<Vec3i> {
+        Ok(Vec3i::new(self.read_i32()?, self.read_i32()?, self.read_i32()?))
+    }
+
+    //anything left after the data is a sign of broken or foreign bytes
+    fn finish(&self) -> SpaceResult<()> {
+        if self.pos != self.data.len() {
+            return Err("Unexpected data after voxel data".into());
+        }
+        Ok(())
+    }
+
+    fn read_header(&mut self, magic : &[u8; 4]) -> SpaceResult<()> {
+        if self.read_bytes(4)? != magic {
+            return Err("Wrong voxel data magic".into());
+        }
+        let version = self.read_u16()?;
+        if version != VOXEL_FORMAT_VERSION {
+            return Err(format!("Unsupported voxel format version {}", version).into());
+        }
+        Ok(())
+    }
+}
+
+fn write_vec3i(out : &mut Vec<u8>, v : &Vec3i) {
+    out.extend_from_slice(&v.x.to_le_bytes());
+    out.extend_from_slice(&v.y.to_le_bytes());
+    out.extend_from_slice(&v.z.to_le_bytes());
+}
+
+fn write_header(out : &mut Vec<u8>, magic : &[u8; 4]) {
+    out.extend_from_slice(magic);
+    out.extend_from_slice(&VOXEL_FORMAT_VERSION.to_le_bytes());
+}
+
+impl<T> VoxelChunk<T>
+    where T : Default + Clone + PartialEq + Serialize + DeserializeOwned {
+
+    //origin, size, then runs of (length, value)
+    fn write_body(&self, out : &mut Vec<u8>) -> SpaceResult<()> {
+        write_vec3i(out, &self.origin.coords);
+        write_vec3i(out, &self.size);
+
+        let mut runs : Vec<(u32, &T)> = vec![];
+        for val in &self.data {
+            match runs.last_mut() {
+                Some((len, last)) if *last == val => {
+                    *len += 1;
+                }
+                _ => {
+                    runs.push((1, val));
+                }
+            }
+        }
+
+        out.extend_from_slice(&(runs.len() as u32).to_le_bytes());
+        for (len, val) in runs {
+            let bytes = bincode::serialize(val)?;
+            out.extend_from_slice(&len.to_le_bytes());
+            out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+            out.extend_from_slice(&bytes);
+        }
+        Ok(())
+    }
+
+    fn read_body(reader : &mut ByteReader) -> SpaceResult<VoxelChunk<T>> {
+        let origin = Pos3i::from(reader.read_vec3i()?);
+        let size = reader.read_vec3i()?;
+        let total = check_chunk_size(&size)?;
+        if (0..3).any(|i| origin[i].rem_euclid(size[i]) != 0) {
+            return Err("Voxel chunk origin is not aligned to chunk size".into());
+        }
+
+        let run_count = reader.read_u32()?;
+        let mut data = Vec::with_capacity(total);
+        for _ in 0..run_count {
+            let len = reader.read_u32()? as usize;
+            let byte_len = reader.read_u32()? as usize;
+            let val : T = bincode::deserialize(reader.read_bytes(byte_len)?)?;
+            if data.len() + len > total {
+                return Err("Voxel chunk runs overflow chunk size".into());
+            }
+            data.resize(data.len() + len, val);
+        }
+        if data.len() != total {
+            return Err("Voxel chunk runs do not cover chunk".into());
+        }
+
+        Ok(VoxelChunk {
+            origin,
+            size,
+            data
+        })
+    }
+
+    pub fn to_bytes(&self) -> SpaceResult<Vec<u8>> {
+        let mut out = vec![];
+        write_header(&mut out, CHUNK_MAGIC);
+        self.write_body(&mut out)?;
+        Ok(out)
+    }
+
+    pub fn from_bytes(bytes : &[u8]) -> SpaceResult<VoxelChunk<T>> {
+        let mut reader = ByteReader::new(bytes);
+        reader.read_header(CHUNK_MAGIC)?;
+        let chunk = VoxelChunk::read_body(&mut reader)?;
+        reader.finish()?;
+        Ok(chunk)
+    }
+}
+
+impl<T> VoxelMap<T>
+    where T : Default + Clone + PartialEq + Serialize + DeserializeOwned {
+
+    pub fn to_bytes(&self) -> SpaceResult<Vec<u8>> {
+        let mut out = vec![];
+        write_header(&mut out, MAP_MAGIC);
+        out.extend_from_slice(&self.voxel_size.to_le_bytes());
+        write_vec3i(&mut out, &self.chunk_size);
+
+        //stable order, so equal maps give equal bytes
+        let mut origins : Vec<&Pos3i> = self.map.keys().collect();
+        origins.sort_by_key(|p| (p.x, p.y, p.z));
+
+        out.extend_from_slice(&(origins.len() as u32).to_le_bytes());
+        for origin in origins {
+            self.map[origin].write_body(&mut out)?;
+        }
+        Ok(out)
+    }
+
+    pub fn from_bytes(bytes : &[u8]) -> SpaceResult<VoxelMap<T>> {
+        let mut reader = ByteReader::new(bytes);
+        reader.read_header(MAP_MAGIC)?;
+        let voxel_size = reader.read_f32()?;
+        if !voxel_size.is_finite() || voxel_size <= 0.0 {
+            return Err("Wrong voxel size".into());
+        }
+        let chunk_size = reader.read_vec3i()?;
+        check_chunk_size(&chunk_size)?;
+
+        let mut map = VoxelMap::new(voxel_size, chunk_size);
+        let chunk_count = reader.read_u32()?;
+        for _ in 0..chunk_count {
+            let chunk = VoxelChunk::read_body(&mut reader)?;
+            if chunk.size != chunk_size {
+                return Err("Voxel chunk size differs from map chunk size".into());
+            }
+            if map.map.contains_key(&chunk.origin) {
+                return Err("Voxel chunk origin is repeated".into());
+            }
+            map.map.insert(chunk.origin, chunk);
+        }
+        reader.finish()?;
+        Ok(map)
+    }
+
+    pub fn save(&self, path : &Path) -> SpaceResult<()> {
+        std::fs::write(path, self.to_bytes()?)?;
+        Ok(())
+    }
+
+    pub fn load(path : &Path) -> SpaceResult<VoxelMap<T>> {
+        VoxelMap::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use space_core::Pos3;
+    use super::*;
+
+    #[test]
+    fn chunk_round_trip() {
+        let mut chunk = VoxelChunk::<i32>::new(Pos3i::new(-16, -32, 48), Vec3i::new(16, 16, 16));
+        *chunk.get_mut(0, 0, 0) = 5;
+        *chunk.get_mut(15, 15, 15) = -7;
+        for x in 3..9 {
+            *chunk.get_mut(x, 4, 2) = 1;
+        }
+
+        let bytes = chunk.to_bytes().unwrap();
+        assert!(bytes.len() < chunk.data.len());
+
+        let loaded = VoxelChunk::<i32>::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.origin, chunk.origin);
+        assert_eq!(loaded.size, chunk.size);
+        assert_eq!(loaded.data, chunk.data);
+    }
+
+    #[test]
+    fn map_round_trip() {
+        let mut map = VoxelMap::<String>::new(0.5, [10, 6, 3].into());
+        map.set(&Pos3::new(-3.0, -0.5, -10.5), "door".into());
+        map.set(&Pos3::new(-3.5, -0.5, -10.5), "wall".into());
+        map.set(&Pos3::new(4.0, 2.0, 0.0), "floor".into());
+        map.set(&Pos3::new(-20.0, 7.0, 3.5), "wall".into());
+
+        let bytes = map.to_bytes().unwrap();
+        let loaded = VoxelMap::<String>::from_bytes(&bytes).unwrap();
+
+        assert_eq!(loaded.voxel_size, map.voxel_size);
+        assert_eq!(loaded.chunk_size, map.chunk_size);
+        assert_eq!(loaded.map.len(), map.map.len());
+        for (origin, chunk) in &map.map {
+            assert_eq!(loaded.map[origin].data, chunk.data);
+        }
+        assert_eq!(loaded.get_cloned(&Pos3::new(-3.0, -0.5, -10.5)), "door");
+        assert_eq!(loaded.get_cloned(&Pos3::new(-20.0, 7.0, 3.5)), "wall");
+        assert_eq!(loaded.get_cloned(&Pos3::new(-20.5, 7.0, 3.5)), "");
+    }
+
+    #[test]
+    fn rejects_broken_data() {
+        let mut map = VoxelMap::<i32>::new(1.0, [4, 4, 4].into());
+        map.set(&Pos3::new(1.0, 2.0, 3.0), 8);
+        let mut bytes = map.to_bytes().unwrap();
+
+        assert!(VoxelMap::<i32>::from_bytes(&bytes[..bytes.len() - 3]).is_err());
+        assert!(VoxelChunk::<i32>::from_bytes(&bytes).is_err());
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(VoxelMap::<i32>::from_bytes(&trailing).is_err());
+
+        //voxel size follows 6 byte header
+        for size in [f32::NAN, f32::INFINITY, 0.0, -1.0] {
+            let mut broken = bytes.clone();
+            broken[6..10].copy_from_slice(&size.to_le_bytes());
+            assert!(VoxelMap::<i32>::from_bytes(&broken).is_err());
+        }
+
+        bytes[4] = 99;
+        assert!(VoxelMap::<i32>::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn rejects_repeated_chunk() {
+        let mut map = VoxelMap::<i32>::new(1.0, [4, 4, 4].into());
+        map.set(&Pos3::new(1.0, 2.0, 3.0), 8);
+        let mut bytes = map.to_bytes().unwrap();
+        assert!(VoxelMap::<i32>::from_bytes(&bytes).is_ok());
+
+        //chunk count follows header, voxel size and chunk size
+        let count_at = 6 + 4 + 12;
+        let chunk = bytes[(count_at + 4)..].to_vec();
+        bytes[count_at..(count_at + 4)].copy_from_slice(&2u32.to_le_bytes());
+        bytes.extend_from_slice(&chunk);
+        assert!(VoxelMap::<i32>::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_chunk_header() {
+        let chunk = VoxelChunk::<i32>::new(Pos3i::new(4, 0, -8), Vec3i::new(4, 4, 4));
+        let bytes = chunk.to_bytes().unwrap();
+        //origin follows 6 byte header, size follows origin
+        let with = |offset : usize, v : i32| {
+            let mut bytes = bytes.clone();
+            bytes[offset..(offset + 4)].copy_from_slice(&v.to_le_bytes());
+            VoxelChunk::<i32>::from_bytes(&bytes)
+        };
+        assert!(with(6, 4).is_ok());
+        assert!(with(6, 5).is_err());
+        assert!(with(18, 1 << 23).is_err());
+        assert!(with(18, i32::MAX).is_err());
+        assert!(with(18, 0).is_err());
+    }
+}
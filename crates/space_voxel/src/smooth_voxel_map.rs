@@ -0,0 +1,368 @@
+use space_assets::GVertex;
+use space_core::bevy::utils::HashMap;
+use space_core::serde::*;
+use space_core::{Pos3, Pos3i, Vec3, Vec3i};
+use crate::objected_voxel_map::{padded_chunk, ChunkBorders};
+use crate::solid_voxel_map::{VoxelChunk, VoxelMap};
+use crate::voxel_mesher::{VoxelMeshData, VoxelSubMesh};
+
+//density of 0 is empty space, surface goes through SURFACE_LEVEL
+pub const SURFACE_LEVEL : f32 = 0.5;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DensityVoxel {
+    pub density : f32,
+    pub material : u8
+}
+
+impl DensityVoxel {
+    pub fn new(density : f32, material : u8) -> Self {
+        Self {
+            density,
+            material
+        }
+    }
+
+    pub fn is_solid(&self) -> bool {
+        self.density >= SURFACE_LEVEL
+    }
+}
+
+pub type DensityMap = VoxelMap<DensityVoxel>;
+
+//density of a sphere sampled at voxel pos, crosses SURFACE_LEVEL exactly at radius
+fn sphere_density(map : &DensityMap, center : &Pos3, radius : f32, voxel : &Pos3i) -> f32 {
+    let dist = (map.get_world_pos(voxel) - center).norm();
+    (SURFACE_LEVEL + (radius - dist) / map.voxel_size).clamp(0.0, 1.0)
+}
+
+//voxels around sphere with one voxel margin
+fn sphere_bounds(map : &DensityMap, center : &Pos3, radius : f32) -> (Pos3i, Vec3i) {
+    let r = Vec3::repeat(radius + map.voxel_size);
+    let min = map.get_voxel_pos(&(center - r)) - Vec3i::new(1, 1, 1);
+    let max = map.get_voxel_pos(&(center + r)) + Vec3i::new(1, 1, 1);
+    (min, max - min + Vec3i::new(1, 1, 1))
+}
+
+impl VoxelMap<DensityVoxel> {
+
+    //union with a sphere, the margin around it is allocated too,
+    //so the chunk that owns every surface edge exists
+    pub fn add_sphere(&mut self, center : &Pos3, radius : f32, material : u8) {
+        let (min, size) = sphere_bounds(self, center, radius);
+        self.allocate_box(&min, &size);
+        for z in min.z..(min.z + size.z) {
+            for y in min.y..(min.y + size.y) {
+                for x in min.x..(min.x + size.x) {
+                    let pos = Pos3i::new(x, y, z);
+                    let density = sphere_density(self, center, radius, &pos);
+                    self.update_voxel(&pos, |voxel| {
+                        if density > voxel.density {
+                            *voxel = DensityVoxel::new(density, material);
+                        }
+                    });
+                }
+            }
+        }
+    }
+
+    //removes a sphere from existing chunks, returns removed density per material
+    pub fn dig_sphere(&mut self, center : &Pos3, radius : f32) -> HashMap<u8, f32> {
+        let mut removed = HashMap::new();
+        let (min, size) = sphere_bounds(self, center, radius);
+        for z in min.z..(min.z + size.z) {
+            for y in min.y..(min.y + size.y) {
+                for x in min.x..(min.x + size.x) {
+                    let pos = Pos3i::new(x, y, z);
+                    let left = 1.0 - sphere_density(self, center, radius, &pos);
+                    let old = match self.get_voxel(&pos) {
+                        Some(voxel) if voxel.density > left => *voxel,
+                        _ => continue
+                    };
+                    *removed.entry(old.material).or_insert(0.0) += old.density - left;
+                    self.update_voxel(&pos, |voxel| voxel.density = left);
+                }
+            }
+        }
+        removed
+    }
+}
+
+//corners of a cell in x, y, z bit order
+const CELL_CORNERS : [[i32; 3]; 8] = [
+    [0, 0, 0], [1, 0, 0], [0, 1, 0], [1, 1, 0],
+    [0, 0, 1], [1, 0, 1], [0, 1, 1], [1, 1, 1]];
+
+const CELL_EDGES : [(usize, usize); 12] = [
+    (0, 1), (2, 3), (4, 5), (6, 7),
+    (0, 2), (1, 3), (4, 6), (5, 7),
+    (0, 4), (1, 5), (2, 6), (3, 7)];
+
+struct PaddedDensity {
+    size : [i32; 3],
+    data : Vec<DensityVoxel>
+}
+
+impl PaddedDensity {
+    fn index(&self, p : [i32; 3]) -> usize {
+        ((p[2] * self.size[1] + p[1]) * self.size[0] + p[0]) as usize
+    }
+
+    fn get(&self, p : [i32; 3]) -> &DensityVoxel {
+        &self.data[self.index(p)]
+    }
+}
+
+//surface nets vertex of one cell, None when the surface does not cross it
+fn cell_vertex(samples : &PaddedDensity, cell : [i32; 3]) -> Option<(Vec3, Vec3)> {
+    let d = CELL_CORNERS.map(|c| samples.get([cell[0] + c[0], cell[1] + c[1], cell[2] + c[2]]).density);
+
+    let mut sum = Vec3::zeros();
+    let mut count = 0;
+    for (a, b) in CELL_EDGES {
+        if (d[a] >= SURFACE_LEVEL) == (d[b] >= SURFACE_LEVEL) {
+            continue;
+        }
+        let t = (SURFACE_LEVEL - d[a]) / (d[b] - d[a]);
+        let ca = Vec3::new(CELL_CORNERS[a][0] as f32, CELL_CORNERS[a][1] as f32, CELL_CORNERS[a][2] as f32);
+        let cb = Vec3::new(CELL_CORNERS[b][0] as f32, CELL_CORNERS[b][1] as f32, CELL_CORNERS[b][2] as f32);
+        sum += ca + (cb - ca) * t;
+        count += 1;
+    }
+    if count == 0 {
+        return None;
+    }
+
+    //density grows inside, so normal goes against the gradient
+    let grad = Vec3::new(
+        (d[1] + d[3] + d[5] + d[7]) - (d[0] + d[2] + d[4] + d[6]),
+        (d[2] + d[3] + d[6] + d[7]) - (d[0] + d[1] + d[4] + d[5]),
+        (d[4] + d[5] + d[6] + d[7]) - (d[0] + d[1] + d[2] + d[3]));
+    let normal = if grad.norm() > 1e-6 { -grad.normalize() } else { Vec3::y() };
+
+    Some((sum / count as f32, normal))
+}
+
+//planar projection along dominant normal axis, one texture repeat per voxel
+fn project_uv(pos : &Vec3, normal : &Vec3) -> ([f32; 2], [f32; 3]) {
+    let axis = normal.iamax();
+    let (u, v) = match axis {
+        0 => (2, 1),
+        1 => (0, 2),
+        _ => (0, 1)
+    };
+    let mut dir = Vec3::zeros();
+    dir[u] = 1.0;
+    let tangent = (dir - normal * normal.dot(&dir)).normalize();
+    ([pos[u], pos[v]], tangent.into())
+}
+
+//surface nets over chunk samples, sample i sits at i * voxel_size from chunk origin.
+//chunk owns edges starting inside it, so neighbours never produce the same quad
+pub fn build_smooth_mesh<F>(
+    chunk : &VoxelChunk<DensityVoxel>,
+    borders : &ChunkBorders<DensityVoxel>,
+    voxel_size : f32,
+    material_of : F) -> VoxelMeshData
+    where F : Fn(u8) -> usize {
+
+    let samples = PaddedDensity {
+        size : [chunk.size.x + 2, chunk.size.y + 2, chunk.size.z + 2],
+        data : padded_chunk(chunk, borders)
+    };
+
+    //cells with min corner in padded 0..=size surround every owned edge
+    let mut cells = HashMap::new();
+    for z in 0..=chunk.size.z {
+        for y in 0..=chunk.size.y {
+            for x in 0..=chunk.size.x {
+                if let Some(vertex) = cell_vertex(&samples, [x, y, z]) {
+                    cells.insert([x, y, z], vertex);
+                }
+            }
+        }
+    }
+
+    let mut submeshes : HashMap<usize, (VoxelSubMesh, HashMap<[i32; 3], u32>)> = HashMap::new();
+    for z in 1..=chunk.size.z {
+        for y in 1..=chunk.size.y {
+            for x in 1..=chunk.size.x {
+                let p = [x, y, z];
+                let from = samples.get(p);
+                for axis in 0..3 {
+                    let mut q = p;
+                    q[axis] += 1;
+                    let to = samples.get(q);
+                    if from.is_solid() == to.is_solid() {
+                        continue;
+                    }
+
+                    //b, c follow a cyclically, quad goes ccw when seen from +a
+                    let b = (axis + 1) % 3;
+                    let c = (axis + 2) % 3;
+                    let mut quad = [p; 4];
+                    quad[0][b] -= 1;
+                    quad[0][c] -= 1;
+                    quad[1][c] -= 1;
+                    quad[3][b] -= 1;
+
+                    let material = material_of(if from.is_solid() { from.material } else { to.material });
+                    let (sub, indices) = submeshes.entry(material).or_insert_with(|| (VoxelSubMesh {
+                        material,
+                        ..Default::default()
+                    }, HashMap::new()));
+
+                    let mut corners = [0; 4];
+                    for (i, cell) in quad.iter().enumerate() {
+                        corners[i] = *indices.entry(*cell).or_insert_with(|| {
+                            let (offset, normal) = cells[cell];
+                            let pos = (Vec3::new(cell[0] as f32, cell[1] as f32, cell[2] as f32)
+                                + offset - Vec3::new(1.0, 1.0, 1.0)) * voxel_size;
+                            let (uv, tangent) = project_uv(&(pos / voxel_size), &normal);
+                            sub.vertices.push(GVertex {
+                                pos : pos.into(),
+                                normal : normal.into(),
+                                tangent,
+                                uv,
+                                ao : 1.0,
+                                light : [0.0; 3]
+                            });
+                            sub.vertices.len() as u32 - 1
+                        });
+                    }
+
+                    if from.is_solid() {
+                        sub.indices.extend_from_slice(&[corners[0], corners[1], corners[2], corners[0], corners[2], corners[3]]);
+                    } else {
+                        sub.indices.extend_from_slice(&[corners[0], corners[2], corners[1], corners[0], corners[3], corners[2]]);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut submeshes : Vec<VoxelSubMesh> = submeshes.into_values().map(|(sub, _)| sub).collect();
+    submeshes.sort_by_key(|s| s.material);
+    VoxelMeshData {
+        submeshes
+    }
+}
+
+//whole map as one mesh around map origin, for small props like debris
+pub fn build_map_mesh<F>(map : &DensityMap, material_of : F) -> VoxelMeshData
+    where F : Fn(u8) -> usize {
+
+    let mut submeshes : HashMap<usize, VoxelSubMesh> = HashMap::new();
+    for (origin, chunk) in &map.map {
+        let borders = ChunkBorders::from_map(map, origin);
+        let offset = origin.coords.cast::<f32>() * map.voxel_size;
+        for part in build_smooth_mesh(chunk, &borders, map.voxel_size, &material_of).submeshes {
+            let sub = submeshes.entry(part.material).or_insert_with(|| VoxelSubMesh {
+                material : part.material,
+                ..Default::default()
+            });
+            let base = sub.vertices.len() as u32;
+            sub.vertices.extend(part.vertices.into_iter().map(|mut v| {
+                v.pos = (Vec3::from(v.pos) + offset).into();
+                v
+            }));
+            sub.indices.extend(part.indices.into_iter().map(|i| i + base));
+        }
+    }
+
+    let mut submeshes : Vec<VoxelSubMesh> = submeshes.into_values().collect();
+    submeshes.sort_by_key(|s| s.material);
+    VoxelMeshData {
+        submeshes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mesh_map(map : &DensityMap) -> Vec<(Pos3i, VoxelMeshData)> {
+        map.map.iter()
+            .map(|(origin, chunk)| {
+                let borders = ChunkBorders::from_map(map, origin);
+                (*origin, build_smooth_mesh(chunk, &borders, map.voxel_size, |m| m as usize))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sphere_surface() {
+        let mut map = DensityMap::new(0.5, [8, 8, 8].into());
+        let center = Pos3::new(0.2, 0.1, -0.3);
+        map.add_sphere(&center, 2.0, 1);
+
+        let meshes = mesh_map(&map);
+        let mut vertex_count = 0;
+        for (origin, data) in &meshes {
+            for sub in &data.submeshes {
+                assert_eq!(sub.material, 1);
+                for v in &sub.vertices {
+                    let world = map.get_world_pos(origin) + Vec3::from(v.pos);
+                    let dir = world - center;
+                    assert!((dir.norm() - 2.0).abs() < 0.15);
+                    assert!(Vec3::from(v.normal).dot(&dir.normalize()) > 0.9);
+                    vertex_count += 1;
+                }
+            }
+        }
+        assert!(vertex_count > 100);
+    }
+
+    #[test]
+    fn chunks_match_single_chunk() {
+        let center = Pos3::new(0.0, 0.0, 0.0);
+
+        let mut chunked = DensityMap::new(1.0, [4, 4, 4].into());
+        chunked.add_sphere(&center, 3.3, 0);
+        let chunked_triangles : usize = mesh_map(&chunked).iter().map(|(_, m)| m.triangle_count()).sum();
+
+        let mut single = DensityMap::new(1.0, [16, 16, 16].into());
+        single.add_sphere(&Pos3::new(8.0, 8.0, 8.0), 3.3, 0);
+        assert_eq!(single.map.len(), 1);
+        let single_triangles : usize = mesh_map(&single).iter().map(|(_, m)| m.triangle_count()).sum();
+
+        assert!(chunked.map.len() > 8);
+        assert_eq!(chunked_triangles, single_triangles);
+    }
+
+    #[test]
+    fn map_mesh_joins_chunks() {
+        let mut map = DensityMap::new(0.5, [4, 4, 4].into());
+        map.add_sphere(&Pos3::origin(), 1.5, 0);
+        assert!(map.map.len() > 1);
+
+        let chunked : usize = mesh_map(&map).iter().map(|(_, m)| m.triangle_count()).sum();
+        let joined = build_map_mesh(&map, |m| m as usize);
+        assert_eq!(joined.submeshes.len(), 1);
+        assert_eq!(joined.triangle_count(), chunked);
+        for v in &joined.submeshes[0].vertices {
+            assert!((Vec3::from(v.pos).norm() - 1.5).abs() < 0.15);
+        }
+    }
+
+    #[test]
+    fn dig_sphere_hole() {
+        let mut map = DensityMap::new(1.0, [8, 8, 8].into());
+        map.add_sphere(&Pos3::new(4.0, 4.0, 4.0), 3.0, 2);
+        let before : usize = mesh_map(&map).iter().map(|(_, m)| m.triangle_count()).sum();
+        let cursor = map.changes.subscribe();
+
+        let removed = map.dig_sphere(&Pos3::new(4.0, 7.0, 4.0), 1.5);
+        assert!(removed[&2] > 1.0);
+        assert!(!map.get_voxel(&Pos3i::new(4, 7, 4)).unwrap().is_solid());
+        assert!(map.get_voxel(&Pos3i::new(4, 4, 4)).unwrap().is_solid());
+        let changes = map.changes.drain(cursor);
+        assert!(map.touched_chunks(&changes).contains(&Pos3i::new(0, 0, 0)));
+
+        let after : usize = mesh_map(&map).iter().map(|(_, m)| m.triangle_count()).sum();
+        assert_ne!(before, after);
+
+        //nothing left to dig in empty space
+        assert!(map.dig_sphere(&Pos3::new(40.0, 40.0, 40.0), 2.0).is_empty());
+    }
+}
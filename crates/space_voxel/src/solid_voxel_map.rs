@@ -1,12 +1,20 @@
 
 pub use space_core::*;
-use bevy::utils::{HashMap, HashSet};
+use bevy::utils::HashMap;
+use crate::change_log::{ChangeLog, MapChange};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoxelHit {
+    pub voxel : Pos3i,
+    pub normal : Vec3i,
+    pub distance : f32
+}
 
 pub struct VoxelMap<T> {
     pub map : HashMap<Pos3i, VoxelChunk<T>>,
     pub voxel_size : f32,
     pub chunk_size : Vec3i,
-    pub dirty_set : HashSet<Pos3i>
+    pub changes : ChangeLog<T>
 }
 
 impl<T> VoxelMap<T>
@@ -17,7 +25,7 @@ impl<T> VoxelMap<T>
             map : HashMap::new(),
             voxel_size,
             chunk_size,
-            dirty_set : HashSet::new()
+            changes : ChangeLog::default()
         }
     }
 
@@ -29,6 +37,14 @@ impl<T> VoxelMap<T>
         )
     }
 
+    pub fn get_world_pos(&self, pos : &Pos3i) -> Pos3 {
+        Pos3::new(
+            pos.x as f32 * self.voxel_size,
+            pos.y as f32 * self.voxel_size,
+            pos.z as f32 * self.voxel_size,
+        )
+    }
+
     pub fn get_grid_pos(&self, pos : &Pos3) -> Pos3 {
         let vp = self.get_voxel_pos(pos);
         Pos3::new(
@@ -68,34 +84,69 @@ impl<T> VoxelMap<T>
         }
     }
 
-    pub fn get_chunk_mut(&mut self, pos : &Pos3) -> Option<&mut VoxelChunk<T>> {
-        let vp = self.get_voxel_pos(pos);
-        let origin = self.get_origin(&vp);
-
-        if let Some(chunk) = self.map.get_mut(&origin) {
-            Some(chunk)
-        } else {
-            None
-        }
+    pub fn get_voxel(&self, pos : &Pos3i) -> Option<&T> {
+        let chunk = self.get_chunk_by_voxel(pos)?;
+        let lp = pos - chunk.origin;
+        Some(chunk.get(lp.x, lp.y, lp.z))
     }
 
-    pub fn get_mut(&mut self, pos : &Pos3) -> &mut T {
-        let vp = self.get_voxel_pos(pos);
-        let origin = self.get_origin(&vp);
-        let chunk_size = self.chunk_size.clone();
-        self.dirty_set.insert(origin);
-
-        if !self.map.contains_key(&origin) {
-            let mut chunk =
-                VoxelChunk::<T>::new(origin.clone(), chunk_size.clone());
+    //Amanatides-Woo traversal, voxel i covers [(i - 0.5) * voxel_size, (i + 0.5) * voxel_size]
+    pub fn raycast<F>(&self, ray : &Ray, max_dist : f32, is_solid : F) -> Option<VoxelHit>
+        where F : Fn(&T) -> bool {
+
+        let dir = ray.dir.normalize();
+        let start = ray.pos.coords / self.voxel_size + Vec3::new(0.5, 0.5, 0.5);
+        let max_t = max_dist / self.voxel_size;
+
+        let mut voxel = Pos3i::new(
+            start.x.floor() as i32,
+            start.y.floor() as i32,
+            start.z.floor() as i32);
+
+        let mut step = Vec3i::zeros();
+        let mut t_max = Vec3::repeat(f32::INFINITY);
+        let mut t_delta = Vec3::repeat(f32::INFINITY);
+        for i in 0..3 {
+            if dir[i] > 0.0 {
+                step[i] = 1;
+                t_delta[i] = 1.0 / dir[i];
+                t_max[i] = (voxel[i] as f32 + 1.0 - start[i]) * t_delta[i];
+            } else if dir[i] < 0.0 {
+                step[i] = -1;
+                t_delta[i] = -1.0 / dir[i];
+                t_max[i] = (start[i] - voxel[i] as f32) * t_delta[i];
+            }
+        }
 
-            let lp = vp - origin;
-            self.map.insert(origin.clone(), chunk);
+        let mut normal = Vec3i::zeros();
+        let mut t = 0.0;
+        while t <= max_t {
+            if let Some(val) = self.get_voxel(&voxel) {
+                if is_solid(val) {
+                    return Some(VoxelHit {
+                        voxel,
+                        normal,
+                        distance : t * self.voxel_size
+                    });
+                }
+            }
+
+            let axis = if t_max.x < t_max.y {
+                if t_max.x < t_max.z { 0 } else { 2 }
+            } else if t_max.y < t_max.z { 1 } else { 2 };
+
+            if t_max[axis] == f32::INFINITY {
+                break;
+            }
+
+            t = t_max[axis];
+            t_max[axis] += t_delta[axis];
+            voxel[axis] += step[axis];
+            normal = Vec3i::zeros();
+            normal[axis] = -step[axis];
         }
 
-        let chunk = self.map.get_mut(&origin).unwrap();
-        let lp = vp - chunk.origin;
-        chunk.get_mut(lp.x, lp.y, lp.z)
+        None
     }
 
     pub fn get_cloned(&self, pos : &Pos3) -> T {
@@ -106,23 +157,44 @@ impl<T> VoxelMap<T>
             T::default()
         }
     }
+}
 
-    pub fn set(&mut self, pos : &Pos3, val : T) {
-        let vp = self.get_voxel_pos(pos);
-        let origin = self.get_origin(&vp);
-        if let Some(chunk) = self.get_chunk_mut(pos) {
-            let lp = vp - chunk.origin;
-            *chunk.get_mut(lp.x, lp.y, lp.z) = val;
-        } else {
-            let origin = self.get_origin(&vp);
-            let mut chunk =
-                VoxelChunk::<T>::new(origin.clone(), self.chunk_size.clone());
+impl<T> VoxelMap<T>
+    where T : Default + Clone + PartialEq {
+
+    //the only way to modify cells, so every real change is recorded
+    pub fn update_voxel<F>(&mut self, pos : &Pos3i, f : F) -> bool
+        where F : FnOnce(&mut T) {
 
-            let lp = vp - origin;
-            *chunk.get_mut(lp.x, lp.y, lp.z) = val;
-            self.map.insert(origin, chunk);
+        let old = self.get_voxel(pos).cloned().unwrap_or_default();
+        let mut new = old.clone();
+        f(&mut new);
+        if new == old {
+            return false;
         }
-        self.dirty_set.insert(origin);
+
+        let origin = self.get_origin(pos);
+        let chunk_size = self.chunk_size;
+        let chunk = self.map.entry(origin)
+            .or_insert_with(|| VoxelChunk::new(origin, chunk_size));
+        let lp = pos - origin;
+        *chunk.get_mut(lp.x, lp.y, lp.z) = new.clone();
+
+        self.changes.push(MapChange::Voxel {
+            pos : *pos,
+            old,
+            new
+        });
+        true
+    }
+
+    pub fn set_voxel(&mut self, pos : &Pos3i, val : T) -> bool {
+        self.update_voxel(pos, |v| *v = val)
+    }
+
+    pub fn set(&mut self, pos : &Pos3, val : T) -> bool {
+        let vp = self.get_voxel_pos(pos);
+        self.set_voxel(&vp, val)
     }
 }
 
@@ -160,12 +232,65 @@ mod chunk_map_tests {
         let pos = Pos3::new(11.0,10.0,-9.0);
         assert_eq!(map.get_cloned(&pos), 0);
 
+        let cursor = map.changes.subscribe();
         map.set(&pos, 11);
         assert_eq!(map.get_cloned(&pos), 11);
-        assert!(map.dirty_set.contains(&map.get_origin(&map.get_voxel_pos(&pos))));
+        assert_eq!(map.changes.pending(cursor), 1);
+    }
+
+    #[test]
+    fn raycast_hit() {
+        let mut map = VoxelMap::<i32>::new(0.5, [4,4,4].into());
+        map.set(&Pos3::new(2.0, 0.0, -1.0), 1);
+        map.set(&Pos3::new(3.0, 0.0, -1.0), 2);
+
+        let ray = Ray {
+            pos : Pos3::new(0.0, 0.0, -1.0),
+            dir : Vec3::new(1.0, 0.0, 0.0)
+        };
+        let hit = map.raycast(&ray, 10.0, |v| *v != 0).unwrap();
+        assert_eq!(hit.voxel, Pos3i::new(4, 0, -2));
+        assert_eq!(hit.normal, Vec3i::new(-1, 0, 0));
+        assert!((hit.distance - 1.75).abs() < 1e-4);
+
+        let hit = map.raycast(&ray, 10.0, |v| *v == 2).unwrap();
+        assert_eq!(hit.voxel, Pos3i::new(6, 0, -2));
+    }
+
+    #[test]
+    fn raycast_diagonal() {
+        let mut map = VoxelMap::<i32>::new(1.0, [4,4,4].into());
+        map.set(&Pos3::new(-3.0, -3.0, -3.0), 1);
+
+        let ray = Ray {
+            pos : Pos3::new(0.2, 0.1, 0.0),
+            dir : Vec3::new(-1.0, -1.0, -1.0)
+        };
+        let hit = map.raycast(&ray, 100.0, |v| *v != 0).unwrap();
+        assert_eq!(hit.voxel, Pos3i::new(-3, -3, -3));
+        assert_eq!(hit.normal.abs().sum(), 1);
+    }
+
+    #[test]
+    fn raycast_miss() {
+        let mut map = VoxelMap::<i32>::new(1.0, [4,4,4].into());
+        map.set(&Pos3::new(10.0, 0.0, 0.0), 1);
+
+        let ray = Ray {
+            pos : Pos3::new(0.0, 0.0, 0.0),
+            dir : Vec3::new(0.0, 1.0, 0.0)
+        };
+        assert!(map.raycast(&ray, 100.0, |v| *v != 0).is_none());
+
+        let ray = Ray {
+            pos : Pos3::new(0.0, 0.0, 0.0),
+            dir : Vec3::new(1.0, 0.0, 0.0)
+        };
+        assert!(map.raycast(&ray, 5.0, |v| *v != 0).is_none());
     }
 }
 
+#[derive(Clone)]
 pub struct VoxelChunk<T> {
     pub origin : Pos3i,
     pub size : Vec3i,
@@ -197,6 +322,10 @@ impl<T> VoxelChunk<T>
             self.data[i] = val.clone();
         }
     }
+
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>() + self.data.capacity() * std::mem::size_of::<T>()
+    }
 }
 
 #[cfg(test)]
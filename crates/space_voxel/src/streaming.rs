@@ -0,0 +1,464 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use space_core::bevy::log::warn;
+use space_core::bevy::utils::{HashMap, HashSet};
+use space_core::serde::Serialize;
+use space_core::serde::de::DeserializeOwned;
+use space_core::{Pos3, Pos3i, TaskServer, Vec3, Vec3i};
+use crate::change_log::MapChange;
+use crate::solid_voxel_map::{VoxelChunk, VoxelMap};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChunkStreamEvent {
+    Loaded(Pos3i),
+    Unloaded(Pos3i)
+}
+
+//creates chunk content for origins that were never saved
+pub type ChunkGenerator<T> = Arc<dyn Fn(&Pos3i, &Vec3i) -> Option<VoxelChunk<T>> + Send + Sync>;
+
+//origin, chunk and whether cached file could not be read
+type LoadQueue<T> = Arc<Mutex<Vec<(Pos3i, Option<VoxelChunk<T>>, bool)>>>;
+
+//failed save is tried again after this delay, chunk stays in memory until then
+const SAVE_RETRY : Duration = Duration::from_secs(1);
+
+//keeps chunks around focus point resident, everything else lives in disk cache
+pub struct ChunkStreamer<T> {
+    pub cache_dir : PathBuf,
+    pub load_radius : f32,
+    //bigger than load_radius, so chunks on the border do not flicker
+    pub unload_radius : f32,
+    pub generator : Option<ChunkGenerator<T>>,
+    cached : HashSet<Pos3i>,
+    loading : HashSet<Pos3i>,
+    //newest unloaded data, until it reaches disk
+    pending_saves : HashMap<Pos3i, (u64, Arc<VoxelChunk<T>>)>,
+    saving : HashSet<Pos3i>,
+    failed_saves : HashMap<Pos3i, Instant>,
+    generation : u64,
+    finished_loads : LoadQueue<T>,
+    //origin, generation and whether file was written
+    finished_saves : Arc<Mutex<Vec<(Pos3i, u64, bool)>>>,
+    events : Vec<ChunkStreamEvent>
+}
+
+fn chunk_file(dir : &Path, origin : &Pos3i) -> PathBuf {
+    dir.join(format!("chunk_{}_{}_{}.bin", origin.x, origin.y, origin.z))
+}
+
+fn parse_chunk_file(name : &str) -> Option<Pos3i> {
+    let coords : Vec<i32> = name.strip_prefix("chunk_")?
+        .strip_suffix(".bin")?
+        .split('_')
+        .map(|v| v.parse().ok())
+        .collect::<Option<Vec<i32>>>()?;
+    if coords.len() != 3 {
+        return None;
+    }
+    Some(Pos3i::new(coords[0], coords[1], coords[2]))
+}
+
+impl<T> ChunkStreamer<T>
+    where T : Default + Clone + PartialEq + Serialize + DeserializeOwned + Send + Sync + 'static {
+
+    //starts with empty cache, chunk files left from earlier sessions are removed.
+    //they may hold data that means nothing now, e.g. entity ids
+    pub fn new(cache_dir : &Path, load_radius : f32, unload_radius : f32) -> ChunkStreamer<T> {
+        for origin in ChunkStreamer::<T>::cached_files(cache_dir) {
+            if let Err(e) = std::fs::remove_file(chunk_file(cache_dir, &origin)) {
+                warn!("Unable to remove old chunk {:?}: {}", origin, e);
+            }
+        }
+        ChunkStreamer::with_cache(cache_dir, HashSet::new(), load_radius, unload_radius)
+    }
+
+    //adopts chunk files already in cache, only for data that stays valid between sessions
+    pub fn reopen(cache_dir : &Path, load_radius : f32, unload_radius : f32) -> ChunkStreamer<T> {
+        let cached = ChunkStreamer::<T>::cached_files(cache_dir);
+        ChunkStreamer::with_cache(cache_dir, cached, load_radius, unload_radius)
+    }
+
+    fn cached_files(cache_dir : &Path) -> HashSet<Pos3i> {
+        let mut cached = HashSet::new();
+        if let Ok(entries) = std::fs::read_dir(cache_dir) {
+            for entry in entries.flatten() {
+                if let Some(origin) = entry.file_name().to_str().and_then(parse_chunk_file) {
+                    cached.insert(origin);
+                }
+            }
+        }
+        cached
+    }
+
+    fn with_cache(cache_dir : &Path, cached : HashSet<Pos3i>, load_radius : f32, unload_radius : f32) -> ChunkStreamer<T> {
+        ChunkStreamer {
+            cache_dir : cache_dir.to_path_buf(),
+            load_radius,
+            unload_radius : unload_radius.max(load_radius),
+            generator : None,
+            cached,
+            loading : HashSet::new(),
+            pending_saves : HashMap::new(),
+            saving : HashSet::new(),
+            failed_saves : HashMap::new(),
+            generation : 0,
+            finished_loads : Arc::new(Mutex::new(vec![])),
+            finished_saves : Arc::new(Mutex::new(vec![])),
+            events : vec![]
+        }
+    }
+
+    pub fn with_generator(mut self, generator : ChunkGenerator<T>) -> Self {
+        self.generator = Some(generator);
+        self
+    }
+
+    pub fn is_busy(&self) -> bool {
+        !self.loading.is_empty() || !self.saving.is_empty()
+    }
+
+    pub fn drain_events(&mut self) -> Vec<ChunkStreamEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    fn chunk_center(map : &VoxelMap<T>, origin : &Pos3i) -> Pos3 {
+        let center = origin.cast::<f32>() + map.chunk_size.cast::<f32>() / 2.0;
+        Pos3::from(center.coords * map.voxel_size)
+    }
+
+    fn spawn_save(&mut self, tasks : &TaskServer, origin : Pos3i, generation : u64, chunk : Arc<VoxelChunk<T>>) {
+        self.saving.insert(origin);
+        let path = chunk_file(&self.cache_dir, &origin);
+        let dir = self.cache_dir.clone();
+        let finished = self.finished_saves.clone();
+
+        tasks.spawn(&format!("Saving chunk {:?}", origin), move || {
+            let res = std::fs::create_dir_all(&dir)
+                .map_err(|e| e.into())
+                .and_then(|_| chunk.to_bytes())
+                .and_then(|bytes| std::fs::write(&path, bytes).map_err(|e| e.into()));
+            if let Err(e) = &res {
+                warn!("Unable to save chunk {:?}: {}", origin, e);
+            }
+            finished.lock().unwrap().push((origin, generation, res.is_ok()));
+        });
+    }
+
+    fn spawn_load(&mut self, tasks : &TaskServer, origin : Pos3i, chunk_size : Vec3i) {
+        self.loading.insert(origin);
+        let path = self.cached.contains(&origin).then(|| chunk_file(&self.cache_dir, &origin));
+        let generator = self.generator.clone();
+        let finished = self.finished_loads.clone();
+
+        tasks.spawn(&format!("Loading chunk {:?}", origin), move || {
+            let generate = || generator.as_ref().and_then(|g| g(&origin, &chunk_size));
+            let mut broken = false;
+            let chunk = match path {
+                Some(path) => {
+                    match std::fs::read(&path).map_err(|e| e.into()).and_then(|bytes| VoxelChunk::from_bytes(&bytes)) {
+                        Ok(chunk) => Some(chunk),
+                        //corrupt or older format, chunk starts over as if never saved
+                        Err(e) => {
+                            warn!("Unable to load chunk {:?}, dropping its cache: {}", origin, e);
+                            broken = true;
+                            generate()
+                        }
+                    }
+                }
+                None => generate()
+            };
+            finished.lock().unwrap().push((origin, chunk, broken));
+        });
+    }
+
+    fn collect_finished(&mut self, map : &mut VoxelMap<T>, tasks : &TaskServer) {
+        let saves = std::mem::take(&mut *self.finished_saves.lock().unwrap());
+        for (origin, generation, saved) in saves {
+            self.saving.remove(&origin);
+            if !saved {
+                //data stays in pending_saves, it is lost only if it never reaches disk
+                if self.pending_saves.contains_key(&origin) {
+                    self.failed_saves.insert(origin, Instant::now() + SAVE_RETRY);
+                }
+                continue;
+            }
+            self.cached.insert(origin);
+            //chunk was unloaded again while writing, old file is stale
+            if let Some((pending_gen, chunk)) = self.pending_saves.get(&origin).cloned() {
+                if pending_gen == generation {
+                    self.pending_saves.remove(&origin);
+                } else {
+                    self.spawn_save(tasks, origin, pending_gen, chunk);
+                }
+            }
+        }
+
+        let now = Instant::now();
+        let retry : Vec<Pos3i> = self.failed_saves.iter()
+            .filter(|(_, at)| **at <= now)
+            .map(|(origin, _)| *origin)
+            .collect();
+        for origin in retry {
+            self.failed_saves.remove(&origin);
+            //chunk may be back in map already, then next unload saves it
+            if let Some((generation, chunk)) = self.pending_saves.get(&origin).cloned() {
+                if !self.saving.contains(&origin) {
+                    self.spawn_save(tasks, origin, generation, chunk);
+                }
+            }
+        }
+
+        let loads = std::mem::take(&mut *self.finished_loads.lock().unwrap());
+        for (origin, chunk, broken) in loads {
+            self.loading.remove(&origin);
+            //newer save of the same origin may be on its way, it marks chunk cached again
+            if broken && self.cached.remove(&origin) && !self.saving.contains(&origin) {
+                if let Err(e) = std::fs::remove_file(chunk_file(&self.cache_dir, &origin)) {
+                    warn!("Unable to remove broken chunk {:?}: {}", origin, e);
+                }
+            }
+            let Some(chunk) = chunk else {
+                continue;
+            };
+            match map.map.get_mut(&origin) {
+                //edits recreated chunk while it was loading, they win over saved cells.
+                //cells they left empty take saved data, so next save keeps both
+                Some(live) => {
+                    for (cell, saved) in live.data.iter_mut().zip(chunk.data) {
+                        if *cell == T::default() {
+                            *cell = saved;
+                        }
+                    }
+                }
+                None => {
+                    map.map.insert(origin, chunk);
+                }
+            }
+            map.changes.push(MapChange::ChunkLoaded(origin));
+            self.events.push(ChunkStreamEvent::Loaded(origin));
+        }
+    }
+
+    pub fn update(&mut self, map : &mut VoxelMap<T>, focus : &Pos3, tasks : &TaskServer) {
+        self.collect_finished(map, tasks);
+
+        let far : Vec<Pos3i> = map.map.keys()
+            .filter(|origin| (ChunkStreamer::chunk_center(map, origin) - focus).norm() > self.unload_radius)
+            .cloned()
+            .collect();
+        for origin in far {
+            let chunk = map.map.remove(&origin).unwrap();
+            map.changes.push(MapChange::ChunkUnloaded(origin));
+            self.generation += 1;
+            let chunk = Arc::new(chunk);
+            self.pending_saves.insert(origin, (self.generation, chunk.clone()));
+            if !self.saving.contains(&origin) {
+                self.spawn_save(tasks, origin, self.generation, chunk);
+            }
+            self.events.push(ChunkStreamEvent::Unloaded(origin));
+        }
+
+        let reach = Vec3::repeat(self.load_radius);
+        let first = map.get_origin(&map.get_voxel_pos(&(focus - reach)));
+        let last = map.get_origin(&map.get_voxel_pos(&(focus + reach)));
+        let step = map.chunk_size;
+
+        let mut cz = first.z;
+        while cz <= last.z {
+            let mut cy = first.y;
+            while cy <= last.y {
+                let mut cx = first.x;
+                while cx <= last.x {
+                    let origin = Pos3i::new(cx, cy, cz);
+                    cx += step.x;
+
+                    if map.map.contains_key(&origin) || self.loading.contains(&origin) {
+                        continue;
+                    }
+                    if (ChunkStreamer::chunk_center(map, &origin) - focus).norm() > self.load_radius {
+                        continue;
+                    }
+
+                    if let Some((_, chunk)) = self.pending_saves.remove(&origin) {
+                        let chunk = Arc::try_unwrap(chunk).unwrap_or_else(|c| c.as_ref().clone());
+                        map.map.insert(origin, chunk);
+                        map.changes.push(MapChange::ChunkLoaded(origin));
+                        self.events.push(ChunkStreamEvent::Loaded(origin));
+                    } else if self.cached.contains(&origin) || self.generator.is_some() {
+                        self.spawn_load(tasks, origin, step);
+                    }
+                }
+                cy += step.y;
+            }
+            cz += step.z;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    fn temp_dir(name : &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("space_voxel_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn wait(streamer : &mut ChunkStreamer<i32>, map : &mut VoxelMap<i32>, focus : &Pos3, tasks : &TaskServer) {
+        for _ in 0..500 {
+            streamer.update(map, focus, tasks);
+            if !streamer.is_busy() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        panic!("streaming did not finish");
+    }
+
+    #[test]
+    fn unload_and_reload() {
+        let dir = temp_dir("reload");
+        let tasks = TaskServer::new();
+        let mut map = VoxelMap::<i32>::new(1.0, [4, 4, 4].into());
+        map.set_voxel(&Pos3i::new(-1, 0, 0), 3);
+        map.set_voxel(&Pos3i::new(40, 0, 0), 7);
+
+        let mut streamer = ChunkStreamer::new(&dir, 10.0, 12.0);
+        let home = Pos3::new(0.0, 0.0, 0.0);
+        let cursor = map.changes.subscribe();
+        wait(&mut streamer, &mut map, &home, &tasks);
+        assert_eq!(map.changes.drain(cursor), vec![MapChange::ChunkUnloaded(Pos3i::new(40, 0, 0))]);
+
+        assert_eq!(map.get_voxel(&Pos3i::new(40, 0, 0)), None);
+        assert_eq!(map.get_voxel(&Pos3i::new(-1, 0, 0)), Some(&3));
+        assert_eq!(streamer.drain_events(), vec![ChunkStreamEvent::Unloaded(Pos3i::new(40, 0, 0))]);
+        assert!(chunk_file(&dir, &Pos3i::new(40, 0, 0)).exists());
+
+        let far = Pos3::new(40.0, 0.0, 0.0);
+        wait(&mut streamer, &mut map, &far, &tasks);
+        assert_eq!(map.get_voxel(&Pos3i::new(40, 0, 0)), Some(&7));
+        assert_eq!(map.get_voxel(&Pos3i::new(-1, 0, 0)), None);
+        assert!(map.changes.drain(cursor).contains(&MapChange::ChunkLoaded(Pos3i::new(40, 0, 0))));
+
+        let events = streamer.drain_events();
+        assert!(events.contains(&ChunkStreamEvent::Loaded(Pos3i::new(40, 0, 0))));
+        assert!(events.contains(&ChunkStreamEvent::Unloaded(Pos3i::new(-4, 0, 0))));
+
+        //reopened streamer finds old cache on disk
+        let mut map = VoxelMap::<i32>::new(1.0, [4, 4, 4].into());
+        let mut streamer = ChunkStreamer::reopen(&dir, 10.0, 12.0);
+        wait(&mut streamer, &mut map, &home, &tasks);
+        assert_eq!(map.get_voxel(&Pos3i::new(-1, 0, 0)), Some(&3));
+
+        //new session starts clean
+        let mut map = VoxelMap::<i32>::new(1.0, [4, 4, 4].into());
+        let mut streamer = ChunkStreamer::<i32>::new(&dir, 10.0, 12.0);
+        wait(&mut streamer, &mut map, &home, &tasks);
+        assert!(map.map.is_empty());
+        assert!(!chunk_file(&dir, &Pos3i::new(-4, 0, 0)).exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn failed_save_is_retried() {
+        let dir = temp_dir("retry");
+        //file in place of cache dir makes saving fail
+        let _ = std::fs::remove_file(&dir);
+        std::fs::write(&dir, b"").unwrap();
+        let tasks = TaskServer::new();
+        let mut map = VoxelMap::<i32>::new(1.0, [4, 4, 4].into());
+        map.set_voxel(&Pos3i::new(40, 0, 0), 7);
+
+        let mut streamer = ChunkStreamer::new(&dir, 10.0, 12.0);
+        let home = Pos3::new(0.0, 0.0, 0.0);
+        wait(&mut streamer, &mut map, &home, &tasks);
+        assert!(streamer.pending_saves.contains_key(&Pos3i::new(40, 0, 0)));
+        assert!(!streamer.cached.contains(&Pos3i::new(40, 0, 0)));
+
+        std::fs::remove_file(&dir).unwrap();
+        std::thread::sleep(SAVE_RETRY);
+        wait(&mut streamer, &mut map, &home, &tasks);
+        assert!(streamer.pending_saves.is_empty());
+        assert!(chunk_file(&dir, &Pos3i::new(40, 0, 0)).exists());
+
+        let far = Pos3::new(40.0, 0.0, 0.0);
+        wait(&mut streamer, &mut map, &far, &tasks);
+        assert_eq!(map.get_voxel(&Pos3i::new(40, 0, 0)), Some(&7));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn edit_during_load_keeps_saved_cells() {
+        let dir = temp_dir("merge");
+        let tasks = TaskServer::new();
+        let mut map = VoxelMap::<i32>::new(1.0, [4, 4, 4].into());
+        map.set_voxel(&Pos3i::new(40, 0, 0), 7);
+
+        let mut streamer = ChunkStreamer::new(&dir, 10.0, 12.0);
+        wait(&mut streamer, &mut map, &Pos3::new(0.0, 0.0, 0.0), &tasks);
+
+        //load is only collected on next update, edit lands first
+        let far = Pos3::new(40.0, 0.0, 0.0);
+        streamer.update(&mut map, &far, &tasks);
+        assert_eq!(map.get_voxel(&Pos3i::new(40, 0, 0)), None);
+        map.set_voxel(&Pos3i::new(41, 0, 0), 5);
+        wait(&mut streamer, &mut map, &far, &tasks);
+
+        assert_eq!(map.get_voxel(&Pos3i::new(40, 0, 0)), Some(&7));
+        assert_eq!(map.get_voxel(&Pos3i::new(41, 0, 0)), Some(&5));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn broken_cache_falls_back_to_generator() {
+        let dir = temp_dir("broken");
+        let origin = Pos3i::new(0, 0, 0);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(chunk_file(&dir, &origin), b"not a chunk").unwrap();
+        let tasks = TaskServer::new();
+        let mut map = VoxelMap::<i32>::new(1.0, [4, 4, 4].into());
+
+        let mut streamer = ChunkStreamer::reopen(&dir, 4.0, 6.0)
+            .with_generator(Arc::new(|origin, size| {
+                let mut chunk = VoxelChunk::new(*origin, *size);
+                chunk.fill(&1);
+                Some(chunk)
+            }));
+        wait(&mut streamer, &mut map, &Pos3::new(2.0, 2.0, 2.0), &tasks);
+
+        assert_eq!(map.get_voxel(&Pos3i::new(1, 1, 1)), Some(&1));
+        assert!(!streamer.cached.contains(&origin));
+        assert!(!chunk_file(&dir, &origin).exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn generator_fills_new_chunks() {
+        let dir = temp_dir("generator");
+        let tasks = TaskServer::new();
+        let mut map = VoxelMap::<i32>::new(1.0, [4, 4, 4].into());
+
+        let mut streamer = ChunkStreamer::new(&dir, 6.0, 8.0)
+            .with_generator(Arc::new(|origin, size| {
+                let mut chunk = VoxelChunk::new(*origin, *size);
+                chunk.fill(&(origin.x + 100));
+                Some(chunk)
+            }));
+        wait(&mut streamer, &mut map, &Pos3::new(0.0, 0.0, 0.0), &tasks);
+
+        assert!(!map.map.is_empty());
+        assert_eq!(map.get_voxel(&Pos3i::new(1, 1, 1)), Some(&100));
+        assert_eq!(map.get_voxel(&Pos3i::new(-1, 1, 1)), Some(&96));
+        assert_eq!(streamer.drain_events().len(), map.map.len());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,481 @@
+use block_mesh::{MergeVoxel, UnorientedQuad, Voxel, VoxelVisibility, RIGHT_HANDED_Y_UP_CONFIG};
+use space_assets::{GMesh, GVertex};
+use space_core::bevy::utils::HashMap;
+use space_core::{RenderBase, Vec3, Vec3i};
+use crate::objected_voxel_map::{greedy_padded, padded_chunk, ChunkBorders, VoxelVal};
+use crate::solid_voxel_map::VoxelChunk;
+use crate::light::{LightColor, MAX_LIGHT};
+
+//triangles of one material, positions are local to chunk origin
+#[derive(Default)]
+pub struct VoxelSubMesh {
+    pub material : usize,
+    pub vertices : Vec<GVertex>,
+    pub indices : Vec<u32>
+}
+
+impl VoxelSubMesh {
+    pub fn to_gmesh(&self, render : &RenderBase) -> GMesh {
+        GMesh::from_vertices(&render.device, &self.vertices, &self.indices)
+    }
+}
+
+#[derive(Default)]
+pub struct VoxelMeshData {
+    //sorted by material
+    pub submeshes : Vec<VoxelSubMesh>
+}
+
+impl VoxelMeshData {
+    pub fn is_empty(&self) -> bool {
+        self.submeshes.is_empty()
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.submeshes.iter().map(|s| s.indices.len() / 3).sum()
+    }
+}
+
+pub struct VoxelMeshSettings {
+    pub voxel_size : f32,
+    //per-vertex corner occlusion, quads with different occlusion are not merged
+    pub ambient_occlusion : bool
+}
+
+impl Default for VoxelMeshSettings {
+    fn default() -> Self {
+        Self {
+            voxel_size : 1.0,
+            ambient_occlusion : true
+        }
+    }
+}
+
+//brightness for 0..3 open neighbours around vertex
+const AO_LEVELS : [f32; 4] = [0.4, 0.6, 0.8, 1.0];
+
+//corner order of block-mesh quads: minu_minv, maxu_minv, minu_maxv, maxu_maxv
+const CORNER_SIGNS : [(i32, i32); 4] = [(-1, -1), (1, -1), (-1, 1), (1, 1)];
+
+#[derive(Clone)]
+struct AoVoxel<T> {
+    val : VoxelVal<T>,
+    ao : [[u8; 4]; 6],
+    light : [[LightColor; 4]; 6]
+}
+
+impl<T> Voxel for AoVoxel<T> {
+    fn get_visibility(&self) -> VoxelVisibility {
+        self.val.get_visibility()
+    }
+}
+
+impl<T : PartialEq + Eq + Clone> MergeVoxel for AoVoxel<T> {
+    type MergeValue = (VoxelVal<T>, [[u8; 4]; 6], [[LightColor; 4]; 6]);
+
+    fn merge_value(&self) -> Self::MergeValue {
+        (self.val.clone(), self.ao, self.light)
+    }
+}
+
+//normal, u and v of every face in padded grid steps
+fn face_axes() -> [[[i32; 3]; 3]; 6] {
+    let probe = UnorientedQuad {
+        minimum : [1, 1, 1],
+        width : 1,
+        height : 1
+    };
+    RIGHT_HANDED_Y_UP_CONFIG.faces.map(|face| {
+        let c = face.quad_corners(&probe).map(|c| c.to_array().map(|v| v as i32));
+        let n = face.signed_normal().to_array();
+        let u = [c[1][0] - c[0][0], c[1][1] - c[0][1], c[1][2] - c[0][2]];
+        let v = [c[2][0] - c[0][0], c[2][1] - c[0][1], c[2][2] - c[0][2]];
+        [n, u, v]
+    })
+}
+
+//tangent follows u direction of the quad
+fn quad_tangent(pos : &[[f32; 3]; 4], uv : &[[f32; 2]; 4]) -> [f32; 3] {
+    let e1 = Vec3::from(pos[1]) - Vec3::from(pos[0]);
+    let e2 = Vec3::from(pos[2]) - Vec3::from(pos[0]);
+    let du1 = uv[1][0] - uv[0][0];
+    let dv1 = uv[1][1] - uv[0][1];
+    let du2 = uv[2][0] - uv[0][0];
+    let dv2 = uv[2][1] - uv[0][1];
+
+    let r = 1.0 / (du1 * dv2 - du2 * dv1);
+    let tangent = (e1 * dv2 - e2 * dv1) * r;
+    tangent.normalize().into()
+}
+
+fn with_occlusion<T : Clone>(padded : Vec<VoxelVal<T>>, chunk_size : &Vec3i, enabled : bool) -> Vec<AoVoxel<T>> {
+    let size = [chunk_size.x + 2, chunk_size.y + 2, chunk_size.z + 2];
+    let index = |p : [i32; 3]| ((p[2] * size[1] + p[1]) * size[0] + p[0]) as usize;
+    let solid = |p : [i32; 3]| matches!(padded[index(p)], VoxelVal::Voxel(_));
+    let axes = face_axes();
+
+    let mut ao = vec![[[3u8; 4]; 6]; padded.len()];
+    if enabled {
+        for z in 1..=chunk_size.z {
+            for y in 1..=chunk_size.y {
+                for x in 1..=chunk_size.x {
+                    if !solid([x, y, z]) {
+                        continue;
+                    }
+                    for (face, [n, u, v]) in axes.iter().enumerate() {
+                        let p = [x + n[0], y + n[1], z + n[2]];
+                        if solid(p) {
+                            continue;
+                        }
+                        for (corner, (su, sv)) in CORNER_SIGNS.iter().enumerate() {
+                            let side1 = solid([p[0] + su * u[0], p[1] + su * u[1], p[2] + su * u[2]]);
+                            let side2 = solid([p[0] + sv * v[0], p[1] + sv * v[1], p[2] + sv * v[2]]);
+                            let diag = solid([
+                                p[0] + su * u[0] + sv * v[0],
+                                p[1] + su * u[1] + sv * v[1],
+                                p[2] + su * u[2] + sv * v[2]]);
+                            ao[index([x, y, z])][face][corner] = if side1 && side2 {
+                                0
+                            } else {
+                                3 - side1 as u8 - side2 as u8 - diag as u8
+                            };
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    padded.into_iter().zip(ao).map(|(val, ao)| AoVoxel { val, ao, light : [[[0; 3]; 4]; 6] }).collect()
+}
+
+//corner light is average of open cells around corner in front of face
+fn with_light<T : Clone>(mut voxels : Vec<AoVoxel<T>>, light : &[LightColor], chunk_size : &Vec3i) -> Vec<AoVoxel<T>> {
+    let size = [chunk_size.x + 2, chunk_size.y + 2, chunk_size.z + 2];
+    let index = |p : [i32; 3]| ((p[2] * size[1] + p[1]) * size[0] + p[0]) as usize;
+    let axes = face_axes();
+
+    for z in 1..=chunk_size.z {
+        for y in 1..=chunk_size.y {
+            for x in 1..=chunk_size.x {
+                let solid = |p : [i32; 3]| matches!(voxels[index(p)].val, VoxelVal::Voxel(_));
+                if !solid([x, y, z]) {
+                    continue;
+                }
+                let mut faces = [[[0u8; 3]; 4]; 6];
+                for (face, [n, u, v]) in axes.iter().enumerate() {
+                    let p = [x + n[0], y + n[1], z + n[2]];
+                    if solid(p) {
+                        continue;
+                    }
+                    for (corner, (su, sv)) in CORNER_SIGNS.iter().enumerate() {
+                        let side1 = [p[0] + su * u[0], p[1] + su * u[1], p[2] + su * u[2]];
+                        let side2 = [p[0] + sv * v[0], p[1] + sv * v[1], p[2] + sv * v[2]];
+                        let diag = [side1[0] + sv * v[0], side1[1] + sv * v[1], side1[2] + sv * v[2]];
+
+                        let mut cells = vec![p];
+                        cells.extend([side1, side2].into_iter().filter(|c| !solid(*c)));
+                        //diagonal is hidden behind two solid sides
+                        if cells.len() > 1 && !solid(diag) {
+                            cells.push(diag);
+                        }
+                        let count = cells.len() as u32;
+                        for c in 0..3 {
+                            let sum : u32 = cells.iter().map(|cell| light[index(*cell)][c] as u32).sum();
+                            faces[face][corner][c] = ((sum + count / 2) / count) as u8;
+                        }
+                    }
+                }
+                voxels[index([x, y, z])].light = faces;
+            }
+        }
+    }
+    voxels
+}
+
+//voxel i occupies [i, i + 1] * voxel_size, uvs repeat once per voxel over merged quads
+pub fn build_voxel_mesh<T, F>(
+    chunk : &VoxelChunk<VoxelVal<T>>,
+    borders : &ChunkBorders<VoxelVal<T>>,
+    settings : &VoxelMeshSettings,
+    material_of : F) -> VoxelMeshData
+    where T : PartialEq + Eq + Clone, F : Fn(&T) -> usize {
+    build_mesh(chunk, borders, None, settings, material_of)
+}
+
+//light is padded like chunk with borders, see VoxelLight::padded
+pub fn build_lit_voxel_mesh<T, F>(
+    chunk : &VoxelChunk<VoxelVal<T>>,
+    borders : &ChunkBorders<VoxelVal<T>>,
+    light : &[LightColor],
+    settings : &VoxelMeshSettings,
+    material_of : F) -> VoxelMeshData
+    where T : PartialEq + Eq + Clone, F : Fn(&T) -> usize {
+    build_mesh(chunk, borders, Some(light), settings, material_of)
+}
+
+fn build_mesh<T, F>(
+    chunk : &VoxelChunk<VoxelVal<T>>,
+    borders : &ChunkBorders<VoxelVal<T>>,
+    light : Option<&[LightColor]>,
+    settings : &VoxelMeshSettings,
+    material_of : F) -> VoxelMeshData
+    where T : PartialEq + Eq + Clone, F : Fn(&T) -> usize {
+
+    let mut padded = with_occlusion(padded_chunk(chunk, borders), &chunk.size, settings.ambient_occlusion);
+    if let Some(light) = light {
+        padded = with_light(padded, light, &chunk.size);
+    }
+    let buffer = greedy_padded(&chunk.size, &padded);
+    let stride = [1, chunk.size.x as u32 + 2, (chunk.size.x as u32 + 2) * (chunk.size.y as u32 + 2)];
+    let voxel_size = settings.voxel_size;
+
+    let mut submeshes : HashMap<usize, VoxelSubMesh> = HashMap::new();
+
+    for (face_idx, (group, face)) in buffer.quads.groups.iter().zip(RIGHT_HANDED_Y_UP_CONFIG.faces.iter()).enumerate() {
+        let normal = face.signed_normal().as_vec3().to_array();
+        for quad in group {
+            let UnorientedQuad { minimum, .. } = quad;
+            let idx = minimum[0] * stride[0] + minimum[1] * stride[1] + minimum[2] * stride[2];
+            let voxel = &padded[idx as usize];
+            let material = match &voxel.val {
+                VoxelVal::Voxel(id) => material_of(id),
+                _ => continue
+            };
+            let ao = voxel.ao[face_idx];
+            let light = voxel.light[face_idx];
+
+            //padded coordinates start one voxel before chunk
+            let pos = face.quad_mesh_positions(quad, 1.0)
+                .map(|p| [(p[0] - 1.0) * voxel_size, (p[1] - 1.0) * voxel_size, (p[2] - 1.0) * voxel_size]);
+            let uv = face.tex_coords(RIGHT_HANDED_Y_UP_CONFIG.u_flip_face, true, quad);
+            let tangent = quad_tangent(&pos, &uv);
+
+            let sub = submeshes.entry(material).or_insert_with(|| VoxelSubMesh {
+                material,
+                ..Default::default()
+            });
+            let start = sub.vertices.len() as u32;
+
+            //split along brighter diagonal, otherwise occlusion looks anisotropic
+            let mut indices = face.quad_mesh_indices(start);
+            if ao[0] as u32 + ao[3] as u32 > ao[1] as u32 + ao[2] as u32 {
+                indices = if indices[1] == start + 1 {
+                    [start, start + 1, start + 3, start, start + 3, start + 2]
+                } else {
+                    [start, start + 3, start + 1, start, start + 2, start + 3]
+                };
+            }
+            sub.indices.extend_from_slice(&indices);
+
+            for i in 0..4 {
+                sub.vertices.push(GVertex {
+                    pos : pos[i],
+                    normal,
+                    tangent,
+                    uv : uv[i],
+                    ao : AO_LEVELS[ao[i] as usize],
+                    light : light[i].map(|l| l as f32 / MAX_LIGHT as f32)
+                });
+            }
+        }
+    }
+
+    let mut submeshes : Vec<VoxelSubMesh> = submeshes.into_values().collect();
+    submeshes.sort_by_key(|s| s.material);
+    VoxelMeshData {
+        submeshes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use space_core::Pos3i;
+    use super::*;
+
+    fn mesh_with(chunk : &VoxelChunk<VoxelVal<usize>>, voxel_size : f32, ambient_occlusion : bool) -> VoxelMeshData {
+        let settings = VoxelMeshSettings {
+            voxel_size,
+            ambient_occlusion
+        };
+        build_voxel_mesh(chunk, &ChunkBorders::empty(chunk.size), &settings, |id| *id)
+    }
+
+    fn mesh(chunk : &VoxelChunk<VoxelVal<usize>>) -> VoxelMeshData {
+        mesh_with(chunk, 0.5, false)
+    }
+
+    #[test]
+    fn single_cube() {
+        let mut chunk = VoxelChunk::<VoxelVal<usize>>::new(Pos3i::new(0, 0, 0), Vec3i::new(4, 4, 4));
+        *chunk.get_mut(1, 2, 3) = VoxelVal::Voxel(2);
+
+        let data = mesh(&chunk);
+        assert_eq!(data.submeshes.len(), 1);
+        let sub = &data.submeshes[0];
+        assert_eq!(sub.material, 2);
+        assert_eq!(sub.vertices.len(), 24);
+        assert_eq!(sub.indices.len(), 36);
+
+        for v in &sub.vertices {
+            for i in 0..3 {
+                let min = [0.5, 1.0, 1.5][i];
+                assert!(v.pos[i] >= min - 1e-5 && v.pos[i] <= min + 0.5 + 1e-5);
+            }
+            let normal = Vec3::from(v.normal);
+            let tangent = Vec3::from(v.tangent);
+            assert!((normal.norm() - 1.0).abs() < 1e-5);
+            assert!((tangent.norm() - 1.0).abs() < 1e-5);
+            assert!(normal.dot(&tangent).abs() < 1e-5);
+        }
+
+        //faces point outwards
+        let center = Vec3::new(0.75, 1.25, 1.75);
+        for tri in sub.indices.chunks(3) {
+            let a = Vec3::from(sub.vertices[tri[0] as usize].pos);
+            let b = Vec3::from(sub.vertices[tri[1] as usize].pos);
+            let c = Vec3::from(sub.vertices[tri[2] as usize].pos);
+            let n = (b - a).cross(&(c - a));
+            assert!(n.dot(&(a - center)) > 0.0);
+        }
+    }
+
+    #[test]
+    fn merged_quads_tile_uv() {
+        let mut chunk = VoxelChunk::<VoxelVal<usize>>::new(Pos3i::new(0, 0, 0), Vec3i::new(4, 4, 4));
+        for x in 0..4 {
+            for z in 0..3 {
+                *chunk.get_mut(x, 0, z) = VoxelVal::Voxel(0);
+            }
+        }
+
+        let data = mesh(&chunk);
+        let sub = &data.submeshes[0];
+        assert_eq!(sub.indices.len(), 36);
+
+        let top : Vec<&GVertex> = sub.vertices.iter().filter(|v| v.normal == [0.0, 1.0, 0.0]).collect();
+        assert_eq!(top.len(), 4);
+        let max_u = top.iter().map(|v| v.uv[0]).fold(0.0, f32::max);
+        let max_v = top.iter().map(|v| v.uv[1]).fold(0.0, f32::max);
+        assert_eq!((max_u.max(max_v), max_u.min(max_v)), (4.0, 3.0));
+    }
+
+    #[test]
+    fn submesh_per_material() {
+        let mut chunk = VoxelChunk::<VoxelVal<usize>>::new(Pos3i::new(0, 0, 0), Vec3i::new(4, 4, 4));
+        *chunk.get_mut(0, 0, 0) = VoxelVal::Voxel(10);
+        *chunk.get_mut(1, 0, 0) = VoxelVal::Voxel(11);
+        *chunk.get_mut(2, 0, 0) = VoxelVal::Voxel(12);
+
+        //10 and 12 share one material
+        let data = build_voxel_mesh(&chunk, &ChunkBorders::empty(chunk.size), &VoxelMeshSettings::default(), |id| id % 2);
+        assert_eq!(data.submeshes.iter().map(|s| s.material).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(data.submeshes[0].indices.len() / 6, 10);
+        assert_eq!(data.submeshes[1].indices.len() / 6, 4);
+        assert_eq!(data.triangle_count(), 28);
+    }
+
+    fn floor_with_pillar() -> VoxelChunk<VoxelVal<usize>> {
+        let mut chunk = VoxelChunk::<VoxelVal<usize>>::new(Pos3i::new(0, 0, 0), Vec3i::new(8, 4, 8));
+        for x in 0..8 {
+            for z in 0..8 {
+                *chunk.get_mut(x, 0, z) = VoxelVal::Voxel(0);
+            }
+        }
+        *chunk.get_mut(4, 1, 4) = VoxelVal::Voxel(0);
+        chunk
+    }
+
+    #[test]
+    fn pillar_darkens_floor() {
+        let chunk = floor_with_pillar();
+        let data = mesh_with(&chunk, 1.0, true);
+        let sub = &data.submeshes[0];
+
+        let floor_top : Vec<&GVertex> = sub.vertices.iter()
+            .filter(|v| v.normal == [0.0, 1.0, 0.0] && v.pos[1] == 1.0)
+            .collect();
+
+        //vertex on the pillar side, two occluding neighbours would be 0, here only the pillar
+        let near = floor_top.iter().find(|v| v.pos == [4.0, 1.0, 4.0]).unwrap();
+        assert_eq!(near.ao, AO_LEVELS[2]);
+        let far = floor_top.iter().find(|v| v.pos == [0.0, 1.0, 0.0]).unwrap();
+        assert_eq!(far.ao, 1.0);
+
+        //every quad is flat in occlusion or exactly matches corner values of its voxels
+        for quad in sub.vertices.chunks(4) {
+            if quad.iter().all(|v| v.ao == 1.0) {
+                continue;
+            }
+            let size = Vec3::from(quad[3].pos) - Vec3::from(quad[0].pos);
+            assert!(size.abs().max() <= 1.0 + 1e-5);
+        }
+    }
+
+    #[test]
+    fn occlusion_toggle() {
+        let chunk = floor_with_pillar();
+        let with_ao = mesh_with(&chunk, 1.0, true);
+        let without_ao = mesh_with(&chunk, 1.0, false);
+
+        assert!(without_ao.submeshes[0].vertices.iter().all(|v| v.ao == 1.0));
+        assert!(with_ao.triangle_count() > without_ao.triangle_count());
+    }
+
+    #[test]
+    fn corner_occlusion_in_hole() {
+        //inner corner between three walls is fully dark
+        let mut chunk = VoxelChunk::<VoxelVal<usize>>::new(Pos3i::new(0, 0, 0), Vec3i::new(4, 4, 4));
+        *chunk.get_mut(0, 0, 0) = VoxelVal::Voxel(1);
+        *chunk.get_mut(1, 0, 0) = VoxelVal::Voxel(1);
+        *chunk.get_mut(0, 0, 1) = VoxelVal::Voxel(1);
+        *chunk.get_mut(1, 1, 0) = VoxelVal::Voxel(1);
+        *chunk.get_mut(0, 1, 1) = VoxelVal::Voxel(1);
+
+        let data = mesh_with(&chunk, 1.0, true);
+        let dark = data.submeshes[0].vertices.iter()
+            .find(|v| v.normal == [0.0, 1.0, 0.0] && v.pos == [1.0, 1.0, 1.0])
+            .unwrap();
+        assert_eq!(dark.ao, AO_LEVELS[0]);
+    }
+
+    #[test]
+    fn baked_light_on_floor() {
+        let mut chunk = VoxelChunk::<VoxelVal<usize>>::new(Pos3i::new(0, 0, 0), Vec3i::new(4, 4, 4));
+        for z in 0..4 {
+            for x in 0..4 {
+                *chunk.get_mut(x, 0, z) = VoxelVal::Voxel(1);
+            }
+        }
+        //red light from x = 2, padded cells start at -1
+        let mut light = vec![[0; 3]; 6 * 6 * 6];
+        for (i, l) in light.iter_mut().enumerate() {
+            if i % 6 >= 3 {
+                *l = [MAX_LIGHT, 0, 0];
+            }
+        }
+        let settings = VoxelMeshSettings {
+            voxel_size : 1.0,
+            ambient_occlusion : false
+        };
+        let data = build_lit_voxel_mesh(&chunk, &ChunkBorders::empty(chunk.size), &light, &settings, |id| *id);
+        let top : Vec<&GVertex> = data.submeshes[0].vertices.iter()
+            .filter(|v| v.normal == [0.0, 1.0, 0.0])
+            .collect();
+        //light steps keep top face from merging into one quad
+        assert!(top.len() > 4);
+        for v in top {
+            let expected = match v.pos[0] as i32 {
+                0 | 1 => 0.0,
+                2 => 8.0 / 15.0,
+                _ => 1.0
+            };
+            assert!((v.light[0] - expected).abs() < 1e-5, "{:?}", v.pos);
+            assert_eq!(v.light[1], 0.0);
+        }
+
+        //unlit mesh has no baked light
+        let data = build_voxel_mesh(&chunk, &ChunkBorders::empty(chunk.size), &settings, |id| *id);
+        assert!(data.submeshes[0].vertices.iter().all(|v| v.light == [0.0; 3]));
+    }
+}
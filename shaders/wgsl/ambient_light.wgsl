@@ -2,7 +2,10 @@
 
 struct AmbientLightUniform {
     color : vec3<f32>,
-    cam_pos : vec3<f32>
+    cam_pos : vec3<f32>,
+    use_ssao : f32,
+    use_vertex_ao : f32,
+    baked_light : f32
 }
 
 struct VertexInput {
@@ -51,6 +54,11 @@ var t_ssao: texture_2d<f32>;
 var s_ssao: sampler;
 
 @group(0) @binding(10)
+var t_baked: texture_2d<f32>;
+@group(0) @binding(11)
+var s_baked: sampler;
+
+@group(0) @binding(12)
 var<uniform> light : AmbientLightUniform;
 
 struct FragmentOutput {
@@ -83,9 +91,11 @@ fn fs_main(in: VertexOutput) -> FragmentOutput {
     var V = normalize(light.cam_pos - pos);
 
     var tex_color = textureSample(t_diffuse, s_diffuse, screen_uv).rgb;
-    var mr = textureSample(t_mr, s_mr, screen_uv).rgb;
+    var mr_ao = textureSample(t_mr, s_mr, screen_uv);
+    var mr = mr_ao.rgb;
 
-    var ssao = textureSample(t_ssao, s_ssao, screen_uv).r;
+    var ssao = mix(1.0, textureSample(t_ssao, s_ssao, screen_uv).r, light.use_ssao);
+    ssao = ssao * mix(1.0, mr_ao.a, light.use_vertex_ao);
 
     var F0 = vec3<f32>(0.04,0.04,0.04);
     F0 = mix(F0, tex_color, mr.b);
@@ -94,7 +104,9 @@ fn fs_main(in: VertexOutput) -> FragmentOutput {
 
     let diffuse = EnvBRDFApprox(tex_color, 1.0, NdotV);
     let specular = EnvBRDFApprox(F0, mr.g, NdotV);
-    let Lo = (diffuse + specular) * light.color * ssao;
+    //baked voxel light is a cheap ambient term, it is occluded the same way
+    let baked = textureSample(t_baked, s_baked, screen_uv).rgb * light.baked_light;
+    let Lo = (diffuse + specular) * (light.color + baked) * ssao;
     out.color = vec4<f32>(Lo, 1.0);
     return out;
 }
\ No newline at end of file
@@ -20,6 +20,9 @@ struct VertexInput {
     @location(9)  normal_mat_2 : vec4<f32>,
     @location(10) normal_mat_3 : vec4<f32>,
     @location(11) normal_mat_4 : vec4<f32>,
+    @location(12) ao : f32,
+    @location(13) light : vec3<f32>,
+    @location(14) instance_light : vec3<f32>,
 }
 
 
@@ -28,7 +31,9 @@ struct VertexOutput {
     @location(0) normal: vec3<f32>,
     @location(1) pos: vec3<f32>,
     @location(2) uv : vec2<f32>,
-    @location(3) tangent : vec3<f32>
+    @location(3) tangent : vec3<f32>,
+    @location(4) ao : f32,
+    @location(5) light : vec3<f32>
 }
 
 @vertex
@@ -44,6 +49,8 @@ fn vs_main(
     out.uv = model.uv;
     out.tangent = normalize((normal_mat * vec4<f32>(model.tangent, 1.0)).rgb);
 //    out.tangent = model.tangent;
+    out.ao = model.ao;
+    out.light = model.light + model.instance_light;
     return out;
 }
 
@@ -52,6 +59,7 @@ struct FragmentOutput {
 @location(1) normal : vec4<f32>,
 @location(2) pos : vec4<f32>,
 @location(3) mr : vec4<f32>,
+@location(4) light : vec4<f32>,
 };
 
 @group(1) @binding(0)
@@ -92,7 +100,9 @@ fn fs_main(in: VertexOutput) -> FragmentOutput {
     out.normal = normal_mapping(in.normal, in.tangent, in.uv);
 //     out.normal = vec4<f32>(in.normal, 1.0);
     out.pos = vec4<f32>(in.pos, 1.0);
-    out.mr = textureSample(t_mr, s_mr, in.uv);
+    //alpha of mr keeps baked vertex ao
+    out.mr = vec4<f32>(textureSample(t_mr, s_mr, in.uv).rgb, in.ao);
+    out.light = vec4<f32>(in.light, 1.0);
 
     return out;
 }
\ No newline at end of file
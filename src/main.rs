@@ -10,6 +10,7 @@ async fn run() {
         .build_global().unwrap();
     let mut game = Game::default();
     add_game_render_plugins(&mut game);
+    game.add_schedule_plugin(space_game::plugins::CameraControllerSystem{});
     game.add_schedule_plugin(MainMenu{});
     game.add_schedule_plugin(StationBuildMenu{});
     game.update_scene_scheldue();
@@ -0,0 +1,210 @@
+use std::path::Path;
+use std::sync::Arc;
+use bevy::asset::{Assets, Handle};
+use bevy::utils::HashMap;
+use space_core::ecs::*;
+use space_core::{Camera, Pos3, Pos3i};
+use space_assets::{GMesh, Location, Material, SpaceAssetServer};
+use space_game::{RenderApi, TaskServerApi};
+use space_voxel::asteroid_field::{AsteroidField, AsteroidFieldSettings};
+use space_voxel::change_log::ChangeCursor;
+use space_voxel::remesher::{ChunkMesher, RemeshResult, RemeshScheduler};
+use space_voxel::smooth_voxel_map::{build_map_mesh, build_smooth_mesh, DensityMap, DensityVoxel};
+use space_voxel::streaming::{ChunkStreamEvent, ChunkStreamer};
+use space_voxel::voxel_mesher::VoxelMeshData;
+
+//tile textures stand in for rock and ores, materials past the list use the last one
+const MATERIAL_TEXTURES : [&str; 3] = [
+    "assets/ss13/tiles/raw_floor.png",
+    "assets/ss13/tiles/dark_floor.png",
+    "assets/ss13/tiles/reinforcment_floor.png"];
+
+#[derive(Component)]
+pub struct AsteroidDebris {
+    pub chunk : Pos3i
+}
+
+//gpu side of the field, created on first update
+pub struct AsteroidRender {
+    pub materials : Vec<Handle<Material>>,
+    //rock of unit radius, debris scales it
+    pub debris : Handle<GMesh>
+}
+
+impl AsteroidRender {
+    fn new(
+            render : &RenderApi,
+            assets : &mut SpaceAssetServer,
+            meshes : &mut Assets<GMesh>,
+            materials : &mut Assets<Material>) -> Self {
+
+        let materials = MATERIAL_TEXTURES.iter()
+            .map(|path| {
+                let mut material = assets.get_default_material();
+                material.color = assets.load_color_texture(path.to_string(), true);
+                materials.add(material)
+            })
+            .collect();
+
+        let mut rock = DensityMap::new(0.25, [8, 8, 8].into());
+        rock.add_sphere(&Pos3::origin(), 1.0, 0);
+        let rock = build_map_mesh(&rock, |m| m as usize);
+        Self {
+            materials,
+            debris : meshes.add(rock.submeshes[0].to_gmesh(&render.base))
+        }
+    }
+
+    pub fn material(&self, material : usize) -> Handle<Material> {
+        self.materials[material.min(self.materials.len() - 1)].clone()
+    }
+}
+
+pub fn asteroid_mesher(voxel_size : f32) -> ChunkMesher<DensityVoxel, VoxelMeshData> {
+    Arc::new(move |chunk, borders, _| build_smooth_mesh(chunk, borders, voxel_size, |m| m as usize))
+}
+
+#[derive(Resource)]
+pub struct AsteroidFieldState {
+    pub enabled : bool,
+    pub field : Arc<AsteroidField>,
+    pub map : DensityMap,
+    pub streamer : ChunkStreamer<DensityVoxel>,
+    pub meshes : RemeshScheduler<DensityVoxel, VoxelMeshData>,
+    //rendered submeshes of every chunk
+    pub chunks : HashMap<Pos3i, Vec<Entity>>,
+    pub render : Option<AsteroidRender>,
+    map_cursor : ChangeCursor,
+    //debris of chunks missing in map is despawned on next update
+    stale_debris : bool
+}
+
+impl Default for AsteroidFieldState {
+    fn default() -> Self {
+        AsteroidFieldState::new(AsteroidFieldSettings::default())
+    }
+}
+
+impl AsteroidFieldState {
+    pub fn new(settings : AsteroidFieldSettings) -> Self {
+        let mut map = DensityMap::new(1.0, [16, 16, 16].into());
+        let field = Arc::new(AsteroidField::new(settings));
+        //cache is per seed, mined chunks of one field must not leak into another.
+        //densities hold no entity ids, so cache outlives the session
+        let cache = format!("cache/asteroids_{}", field.settings.seed);
+        let streamer = ChunkStreamer::reopen(Path::new(&cache), 96.0, 112.0)
+            .with_generator(AsteroidField::chunk_generator(field.clone(), map.voxel_size));
+        Self {
+            enabled : false,
+            field,
+            meshes : RemeshScheduler::new(asteroid_mesher(map.voxel_size), 4),
+            chunks : HashMap::new(),
+            render : None,
+            map_cursor : map.changes.subscribe(),
+            map,
+            streamer,
+            stale_debris : false
+        }
+    }
+
+    pub fn seed(&self) -> u32 {
+        self.field.settings.seed
+    }
+
+    //drops loaded field, chunks already saved stay in their cache
+    pub fn reset(&mut self, settings : AsteroidFieldSettings) {
+        let enabled = self.enabled;
+        let chunks = std::mem::take(&mut self.chunks);
+        let render = self.render.take();
+        *self = AsteroidFieldState::new(settings);
+        self.enabled = enabled;
+        self.render = render;
+        //old chunks are missing in new map, their meshes get removed
+        for origin in chunks.keys() {
+            self.meshes.mark_dirty(*origin);
+        }
+        self.chunks = chunks;
+        self.stale_debris = true;
+    }
+}
+
+pub fn asteroid_field_system(
+    mut cmds : Commands,
+    camera : Res<Camera>,
+    tasks : Res<TaskServerApi>,
+    render : Res<RenderApi>,
+    mut assets : ResMut<SpaceAssetServer>,
+    mut meshes : ResMut<Assets<GMesh>>,
+    mut materials : ResMut<Assets<Material>>,
+    debris : Query<(Entity, &AsteroidDebris)>,
+    mut state : ResMut<AsteroidFieldState>) {
+
+    let state = state.as_mut();
+    if state.enabled {
+        state.streamer.update(&mut state.map, &camera.pos, &tasks);
+    }
+    let render_assets = state.render.get_or_insert_with(||
+        AsteroidRender::new(&render, &mut assets, &mut meshes, &mut materials));
+
+    //loaded and unloaded chunks change borders of their neighbours too
+    let changes = state.map.changes.drain(state.map_cursor);
+    for origin in state.map.touched_chunks(&changes) {
+        state.meshes.mark_dirty(origin);
+    }
+    state.meshes.update(&state.map, &camera.pos, &tasks.server);
+
+    //old mesh stays visible until the new one is ready
+    for res in state.meshes.drain_finished() {
+        let (origin, mesh) = match res {
+            RemeshResult::Meshed(origin, mesh) => (origin, Some(mesh)),
+            RemeshResult::Removed(origin) => (origin, None)
+        };
+        for e in state.chunks.remove(&origin).unwrap_or_default() {
+            cmds.entity(e).despawn();
+        }
+        let Some(mesh) = mesh else {
+            continue;
+        };
+
+        let mut entities = vec![];
+        for sub in &mesh.submeshes {
+            let mut location = Location::new(&render.device);
+            location.pos = origin.coords.cast::<f32>() * state.map.voxel_size;
+            let entity = cmds.spawn((meshes.add(sub.to_gmesh(&render.base)), render_assets.material(sub.material)))
+                .insert(location).id();
+            entities.push(entity);
+        }
+        state.chunks.insert(origin, entities);
+    }
+
+    for event in state.streamer.drain_events() {
+        match event {
+            ChunkStreamEvent::Loaded(origin) => {
+                let spawns = state.field.debris_in_chunk(&origin, &state.map.chunk_size, state.map.voxel_size);
+                for spawn in spawns {
+                    let mut location = Location::new(&render.device);
+                    location.pos = spawn.pos.coords;
+                    location.rotation = spawn.rotation;
+                    location.scale = [spawn.scale; 3].into();
+                    cmds.spawn((render_assets.debris.clone(), render_assets.material(state.field.settings.rock_material as usize)))
+                        .insert(location)
+                        .insert(AsteroidDebris {
+                            chunk : origin
+                        });
+                }
+            }
+            ChunkStreamEvent::Unloaded(_) => {
+                state.stale_debris = true;
+            }
+        }
+    }
+
+    if state.stale_debris {
+        state.stale_debris = false;
+        for (e, d) in debris.iter() {
+            if !state.map.map.contains_key(&d.chunk) {
+                cmds.entity(e).despawn();
+            }
+        }
+    }
+}
@@ -1,5 +1,8 @@
 mod station_build_scene;
 mod station_plugin;
 mod station_data;
+mod station_damage;
+mod station_structure;
+mod asteroid_field;
 
 pub use station_build_scene::*;
\ No newline at end of file
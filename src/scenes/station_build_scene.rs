@@ -1,12 +1,14 @@
 use std::marker::PhantomData;
 use std::process::id;
 use bevy::asset::AssetServer;
-use bevy::prelude::{info_span, info};
+use bevy::prelude::{info_span, info, warn};
 use egui::{Context, Key, Ui};
-use space_game::{Game, GameCommands, SchedulePlugin, GlobalStageStep, EguiContext, SceneType, RonAssetPlugin, RenderApi, InputSystem, KeyCode, ScreenSize};
+use space_game::{Game, GameCommands, SchedulePlugin, GlobalStageStep, EguiContext, SceneType, RonAssetPlugin, RenderApi, InputSystem, ScreenSize};
 use space_render::{add_game_render_plugins, AutoInstancing};
+use space_render::light::{AmbientOcclusionSettings, BakedLightSettings};
 use space_core::{ecs::*, app::App, nalgebra, SpaceResult, Pos3i, Vec3i, Vec3, Pos3};
-use space_core::{serde::*, Camera};
+use space_core::{serde::*, Camera, Projection, Ray};
+use space_core::{CameraController, FreeFlyController, OrbitController, RtsController};
 use bevy::asset::*;
 use bevy::utils::HashMap;
 use winit::event::MouseButton;
@@ -15,10 +17,25 @@ use bevy::reflect::TypeUuid;
 use std::string::String;
 use crate::scenes::station_data::*;
 use crate::scenes::station_plugin::*;
-
+use crate::scenes::station_damage::*;
+use crate::scenes::station_structure::*;
+use crate::scenes::asteroid_field::*;
+use space_voxel::streaming::ChunkStreamEvent;
+use space_voxel::grid::GridTransform;
+use space_voxel::objected_voxel_map::VoxelFilling;
+use space_voxel::solid_voxel_map::VoxelHit;
+use space_voxel::asteroid_field::AsteroidFieldSettings;
+use space_voxel::light::{LightColor, MAX_LIGHT};
+
+//dim bluish light of open space around grids
+const SPACE_LIGHT : LightColor = [4, 4, 6];
+
+//positions are local to grid
 #[derive(Component)]
 struct StationBuildActiveBlock {
-    pub voxel_pos : Pos3
+    pub grid : Option<Entity>,
+    pub voxel_pos : Pos3,
+    pub target_pos : Option<Pos3>
 }
 
 pub struct StationBuildMenu {}
@@ -36,24 +53,53 @@ impl SchedulePlugin for StationBuildMenu {
         app.add_event::<AddBlockEvent>();
         app.add_event::<InstancingUpdateEvent>();
         app.add_event::<ChunkUpdateEvent>();
+        app.add_event::<ExplosionEvent>();
+        app.add_event::<ChunkStreamEvent>();
+        app.add_event::<GridChunkChangedEvent>();
 
         app.add_system_set(SystemSet::on_enter(SceneType::StationBuilding)
             .with_system(init_station_build));
+        app.add_system_set(SystemSet::on_exit(SceneType::StationBuilding)
+            .with_system(exit_station_build));
 
         app.add_system_set(
             SystemSet::on_update(SceneType::StationBuilding)
                 .with_system(station_menu)
-                .with_system(camera_movement)
-                .with_system(place_block)
+                .with_system(camera_menu)
+                .with_system(move_grids)
+                .with_system(place_block.after(move_grids))
                 .with_system(add_block_to_station.after(station_menu))
                 .with_system(setup_blocks)
+                .with_system(sync_grid_parts.after(move_grids).after(setup_blocks))
                 .with_system(update_instancing_holders)
-                .with_system(catch_update_events));
+                .with_system(catch_update_events)
+                .with_system(meteor_shower_system)
+                .with_system(explosion_system.after(meteor_shower_system))
+                .with_system(station_streaming_system)
+                .with_system(asteroid_field_system)
+                .with_system(structure_system
+                    .after(setup_blocks)
+                    .after(explosion_system)
+                    .before(station_change_events))
+                .with_system(structure_menu)
+                .with_system(floating_overlay.after(structure_system))
+                .with_system(station_change_events
+                    .after(setup_blocks)
+                    .after(explosion_system)
+                    .after(station_streaming_system))
+                .with_system(station_light_system.after(station_change_events))
+                .with_system(object_light_system.after(station_light_system))
+                .with_system(station_remesh_system.after(station_light_system)));
         app.add_system_set(
             SystemSet::on_update(CommonBlockState::Waiting)
                 .with_system(wait_loading_common_asset));
 
         app.insert_resource(StationRender::default());
+        app.insert_resource(DamageSettings::default());
+        app.insert_resource(MeteorShower::default());
+        app.insert_resource(ActiveGrid::default());
+        app.insert_resource(AsteroidFieldState::default());
+        app.insert_resource(StructureSettings::default());
     }
 }
 
@@ -71,11 +117,15 @@ fn add_block_to_station(
             return;
         }
         if let Some(e) = panels.active_entity.as_ref() {
-            events.send(AddBlockEvent {
-                id : panels.active_id.clone(),
-                world_pos: world.get_component::<StationBuildActiveBlock>(*e).unwrap().voxel_pos.into(),
-                rot : panels.mode.clone()
-            });
+            let active = world.get_component::<StationBuildActiveBlock>(*e).unwrap();
+            if let Some(grid) = active.grid {
+                events.send(AddBlockEvent {
+                    id : panels.active_id.clone(),
+                    grid,
+                    local_pos: active.voxel_pos,
+                    rot : panels.mode.clone()
+                });
+            }
         }
     }
     
@@ -85,11 +135,15 @@ fn add_block_to_station(
             return;
         }
         if let Some(e) = panels.active_entity.as_ref() {
-            events.send(AddBlockEvent{
-                id: BuildCommand::None,
-                world_pos: world.get_component::<StationBuildActiveBlock>(*e).unwrap().voxel_pos.into(),
-                rot : panels.mode.clone()
-            });
+            let active = world.get_component::<StationBuildActiveBlock>(*e).unwrap();
+            if let Some(grid) = active.grid {
+                events.send(AddBlockEvent{
+                    id: BuildCommand::None,
+                    grid,
+                    local_pos: active.target_pos.unwrap_or(active.voxel_pos),
+                    rot : panels.mode.clone()
+                });
+            }
         }
     }
     
@@ -102,88 +156,136 @@ fn place_block(
     input : Res<InputSystem>,
     mut panels : ResMut<StationBlocks>,
     screen_size : Res<ScreenSize>,
-    chunk : Res<Station>,
+    stations : Query<(Entity, &Station)>,
+    active_grid : Res<ActiveGrid>,
     render : Res<RenderApi>,
     block_holder : Res<BlockHolder>) {
 
-    let ray = camera.screen_pos_to_ray(
+    let world_ray = camera.screen_pos_to_ray(
         input.get_mouse_pos(),
         nalgebra::Point2::<f32>::new(screen_size.size.width as f32, screen_size.size.height as f32));
 
+    //nearest grid under cursor, otherwise active grid at build level
+    let mut nearest : Option<(Entity, VoxelHit)> = None;
+    for (grid, station) in stations.iter() {
+        let ray = station.transform.ray_to_local(&world_ray);
+        //blocks are drawn from voxel corner, map cells are centered on voxel
+        let half_voxel = station.map.voxel_size / 2.0;
+        let map_ray = Ray {
+            pos : ray.pos - Vec3::new(half_voxel, half_voxel, half_voxel),
+            dir : ray.dir
+        };
+        if let Some(hit) = station.map.raycast(&map_ray, 1000.0, |v| *v != StationBlock::None) {
+            if nearest.as_ref().is_none_or(|(_, best)| hit.distance < best.distance) {
+                nearest = Some((grid, hit));
+            }
+        }
+    }
+
+    let grid = nearest.as_ref().map(|(grid, _)| *grid).or(active_grid.entity);
+    let Some((grid, chunk)) = grid.and_then(|grid| stations.get(grid).ok()) else {
+        return;
+    };
+    let hit = nearest.map(|(_, hit)| hit);
+    let ray = chunk.transform.ray_to_local(&world_ray);
+
     for  (mut loc, mut active_pos) in query.iter_mut() {
+        active_pos.grid = Some(grid);
         let ray_point = ray.interact_y(panels.build_level as f32 * chunk.map.voxel_size);
         let point = chunk.get_grid_pos(&ray_point);
-        let point = Pos3::new(
+        let mut point = Pos3::new(
             ((point.x / chunk.map.voxel_size / 2.0) as i32 * 2) as f32 * chunk.map.voxel_size,
              point.y,
             ((point.z / chunk.map.voxel_size / 2.0) as i32 * 2) as f32 * chunk.map.voxel_size,
         );
 
+        active_pos.target_pos = hit.as_ref().map(|hit| chunk.map.get_world_pos(&hit.voxel));
+
         // let point = ray.pos + 10.0 * ray.dir;
 
-        if let BuildCommand::Block(id) = &panels.active_id {
-            if let Some(desc) = block_holder.map.get(id) {
-                let mut bbox = desc.bbox.clone();
-                let rot;
-                match panels.mode {
-                    BlockAxis::Y => {
-                        rot = Vec3::new(0.0,0.0,0.0);
-                    }
-                    BlockAxis::X => {
-                        rot = Vec3::new(0.0, 0.0, 3.14 / 2.0);
-                        bbox = Vec3i::new(bbox.y, bbox.x, bbox.z);
-                    }
-                    BlockAxis::Z => {
-                        rot = Vec3::new(3.14 / 2.0, 0.0, 0.0);
-                        bbox = Vec3i::new(bbox.x, bbox.z, bbox.y);
+        let desc_bbox = match &panels.active_id {
+            BuildCommand::Block(id) => block_holder.map.get(id).map(|desc| desc.footprint.size()),
+            BuildCommand::Voxel(_) => Some(Vec3i::new(1, 1, 1)),
+            BuildCommand::None => None
+        };
+        if let Some(bbox) = desc_bbox {
+            let bbox = panels.mode.grid_rotation().rotate_size(&bbox);
+            let rot = panels.mode.euler();
+
+            let shift = Vec3::new(
+                bbox.x as f32 * chunk.map.voxel_size / 2.0,
+                bbox.y as f32 * chunk.map.voxel_size / 2.0,
+                bbox.z as f32 * chunk.map.voxel_size / 2.0,
+            );
+
+            //snap onto the clicked face, growing away from it
+            if let Some(hit) = &hit {
+                let mut vp = hit.voxel + hit.normal;
+                for i in 0..3 {
+                    if hit.normal[i] < 0 {
+                        vp[i] -= bbox[i] - 1;
                     }
                 }
+                point = chunk.map.get_world_pos(&vp);
+            }
 
-                let shift = Vec3::new(
-                    bbox.x as f32 * chunk.map.voxel_size / 2.0,
-                    bbox.y as f32 * chunk.map.voxel_size / 2.0,
-                    bbox.z as f32 * chunk.map.voxel_size / 2.0,
-                );
-
-                loc.pos.x = point.x;
-                loc.pos.y = point.y;
-                loc.pos.z = point.z;
-                loc.pos = loc.pos + shift;
+            loc.pos = chunk.transform.to_world(&(point + shift)).coords;
+            loc.rotation = chunk.transform.euler_to_world(&rot);
 
-                active_pos.voxel_pos = point;
-            }
+            active_pos.voxel_pos = point;
         }
 
     }
 }
 
-fn camera_movement(
+//O switches between perspective and top-down orthographic view
+fn camera_menu(
     mut camera : ResMut<Camera>,
-    input : Res<InputSystem>) {
-
-    let mut frw = camera.up.clone_owned();
-    frw.y = 0.0;
-    frw = frw.normalize();
-    let speed = 0.1;
-    let right = camera.get_right();
-    if input.get_key_state(KeyCode::W) {
-        camera.pos = camera.pos + speed * frw;
-    }
-    if input.get_key_state(KeyCode::S) {
-        camera.pos = camera.pos - speed * frw;
-    }
-    if input.get_key_state(KeyCode::A) {
-        camera.pos = camera.pos - speed * right;
-    }
-    if input.get_key_state(KeyCode::D) {
-        camera.pos = camera.pos + speed * right;
-    }
-    if input.get_key_state(KeyCode::LShift) {
-        camera.pos.y = camera.pos.y + speed;
-    }
-    if input.get_key_state(KeyCode::LControl) {
-        camera.pos.y = camera.pos.y - speed;
+    controller : Option<ResMut<CameraController>>,
+    ctx : Res<EguiContext>) {
+
+    let toggle = !ctx.wants_keyboard_input() && ctx.input().key_pressed(Key::O);
+    if toggle {
+        camera.toggle_projection();
     }
+    let Some(mut controller) = controller else {
+        return;
+    };
+
+    egui::Window::new("Camera").show(&ctx, |ui| {
+        egui::ComboBox::from_label("Controller")
+            .selected_text(controller.name())
+            .show_ui(ui, |ui| {
+                //new controller starts from current view
+                let options = [
+                    CameraController::Rts(RtsController::from_camera(&camera, 0.0)),
+                    CameraController::Orbit(OrbitController::from_camera(&camera, 10.0)),
+                    CameraController::FreeFly(FreeFlyController::from_camera(&camera))];
+                for option in options {
+                    let selected = option.name() == controller.name();
+                    if ui.selectable_label(selected, option.name()).clicked() && !selected {
+                        *controller = option;
+                    }
+                }
+            });
+        let mut ortho = camera.projection == Projection::Orthographic;
+        if ui.checkbox(&mut ortho, "Orthographic (O)").changed() {
+            camera.toggle_projection();
+        }
+        match controller.as_mut() {
+            CameraController::Rts(c) => {
+                ui.add(egui::Slider::new(&mut c.pan_speed, 0.1..=4.0).text("Pan speed"));
+                ui.add(egui::Slider::new(&mut c.edge_margin, 0.0..=32.0).text("Edge pan"));
+            }
+            CameraController::Orbit(c) => {
+                ui.add(egui::Slider::new(&mut c.rotate_speed, 0.001..=0.02).text("Rotate speed"));
+            }
+            CameraController::FreeFly(c) => {
+                ui.add(egui::Slider::new(&mut c.move_speed, 1.0..=50.0).text("Move speed"));
+                ui.add(egui::Slider::new(&mut c.look_speed, 0.001..=0.01).text("Look speed"));
+            }
+        }
+    });
 }
 
 #[derive(Default, Deserialize, TypeUuid, Debug, Clone)]
@@ -191,7 +293,36 @@ fn camera_movement(
 pub struct RonBlockDesc {
     pub name : String,
     pub model_path : String,
-    pub bbox : Vec<i32>
+    pub bbox : Vec<i32>,
+    //optional occupancy inside bbox: layers bottom to top, rows along z, '#' marks taken cell
+    #[serde(default)]
+    pub mask : Vec<Vec<String>>,
+    //rgb light level of fixture, up to 15
+    #[serde(default)]
+    pub light : Vec<u8>
+}
+
+impl RonBlockDesc {
+    pub fn footprint(&self) -> VoxelFilling {
+        let bbox = VoxelFilling::BBox(Vec3i::new(self.bbox[0], self.bbox[1], self.bbox[2]));
+        if self.mask.is_empty() {
+            return bbox;
+        }
+        match VoxelFilling::from_layers(&self.mask) {
+            Some(mask) if mask.size() == bbox.size() => mask,
+            _ => {
+                warn!("Mask of {} does not match its bbox, using whole bbox", self.name);
+                bbox
+            }
+        }
+    }
+
+    pub fn light_color(&self) -> Option<LightColor> {
+        match self.light.as_slice() {
+            [r, g, b] => Some([*r, *g, *b].map(|l| l.min(MAX_LIGHT))),
+            _ => None
+        }
+    }
 }
 
 #[derive(Resource, Default)]
@@ -202,7 +333,9 @@ struct StationBlocks {
     pub active_entity : Option<Entity>,
     pub build_level : i32,
 
-    pub mode : BlockAxis
+    pub mode : BlockAxis,
+    //tiles are placed as meshed voxels instead of block objects
+    pub voxel_mode : bool
 }
 
 #[derive(Clone, PartialEq, Eq, Debug, Hash)]
@@ -259,7 +392,8 @@ fn wait_loading_common_asset(
                     mesh : mesh.clone(),
                     material: mat_handle,
                     name: file.clone(),
-                    bbox : Vec3i::new(desc.bbox[0], desc.bbox[1], desc.bbox[2])
+                    footprint : desc.footprint(),
+                    light : desc.light_color()
                 };
 
                 let id = BlockId(block_holder.map.len());
@@ -287,7 +421,8 @@ fn wait_loading_common_asset(
                     mesh,
                     material: mat,
                     name: desc.name.clone(),
-                    bbox : Vec3i::new(desc.bbox[0], desc.bbox[1], desc.bbox[2])
+                    footprint : desc.footprint(),
+                    light : desc.light_color()
                 };
 
                 let id = BlockId(block_holder.map.len());
@@ -298,6 +433,8 @@ fn wait_loading_common_asset(
     }
 }
 
+type GridMenuItem = (Entity, Option<&'static mut GridVelocity>, Option<&'static mut StationStreaming>);
+
 fn station_menu(
     mut commands : Commands,
     ctx : Res<EguiContext>,
@@ -307,7 +444,14 @@ fn station_menu(
     render : Res<RenderApi>,
     mut materials : ResMut<Assets<Material>>,
     mut meshes : ResMut<Assets<GMesh>>,
-    mut blocs_holder : ResMut<BlockHolder>
+    mut blocs_holder : ResMut<BlockHolder>,
+    mut meteors : ResMut<MeteorShower>,
+    mut grids : Query<GridMenuItem, With<Station>>,
+    mut active_grid : ResMut<ActiveGrid>,
+    camera : Res<Camera>,
+    mut ao_settings : ResMut<AmbientOcclusionSettings>,
+    mut baked_light : ResMut<BakedLightSettings>,
+    mut asteroids : ResMut<AsteroidFieldState>
 ) {
 
     egui::SidePanel::left("Build panel").show(&ctx, |ui| {
@@ -326,7 +470,7 @@ fn station_menu(
                 ui.label(format!("Selected block: None"));
             }
             BuildCommand::Voxel(id) => {
-
+                ui.label(format!("Selected voxel: {}", id.0));
             }
             BuildCommand::Block(id) => {
                 ui.label(format!("Selected block: {}", id.0));
@@ -343,6 +487,8 @@ fn station_menu(
             ui.selectable_value(&mut panels.mode, BlockAxis::Z, "Z");
         });
 
+        ui.checkbox(&mut panels.voxel_mode, "Place as voxels");
+
         ui.label("Blocks:");
         let mut panel_list = panels.panels.clone();
         egui::ScrollArea::vertical().show(ui, |ui| {
@@ -354,14 +500,85 @@ fn station_menu(
     
                     let e = commands.spawn((block.mesh.clone(), block.material.clone()))
                         .insert(Location::new(&render.device))
-                        .insert(StationBuildActiveBlock{ voxel_pos : Pos3::default()}).id();
+                        .insert(StationBuildActiveBlock{ grid : None, voxel_pos : Pos3::default(), target_pos : None }).id();
                     panels.active_entity = Some(e.clone());
-                    panels.active_id = BuildCommand::Block(idx.clone());
+                    panels.active_id = if panels.voxel_mode {
+                        BuildCommand::Voxel(VoxelId(idx.0))
+                    } else {
+                        BuildCommand::Block(idx.clone())
+                    };
                 }
             }
         });
         
 
+        ui.separator();
+
+        ui.label("Disasters:");
+        ui.checkbox(&mut meteors.enabled, "Meteor shower");
+        let mut seed = meteors.seed;
+        ui.add(egui::DragValue::new(&mut seed).prefix("Seed "));
+        //button has to be drawn every frame, so it is not part of the condition
+        let restart = ui.button("Restart shower").clicked();
+        if seed != meteors.seed || restart {
+            meteors.reset(seed);
+        }
+
+        ui.separator();
+
+        for (_, _, streaming) in grids.iter_mut() {
+            if let Some(mut streaming) = streaming {
+                ui.checkbox(&mut streaming.enabled, "Stream far chunks");
+            }
+        }
+
+        ui.checkbox(&mut asteroids.enabled, "Asteroid field");
+        let mut asteroid_seed = asteroids.seed();
+        ui.add(egui::DragValue::new(&mut asteroid_seed).prefix("Field seed "));
+        if asteroid_seed != asteroids.seed() {
+            let settings = AsteroidFieldSettings {
+                seed : asteroid_seed,
+                ..asteroids.field.settings.clone()
+            };
+            asteroids.reset(settings);
+        }
+
+        ui.label("Grids:");
+        for (grid, velocity, _) in grids.iter_mut() {
+            let active = active_grid.entity == Some(grid);
+            if ui.selectable_label(active, format!("Grid {}", grid.index())).clicked() {
+                active_grid.entity = Some(grid);
+            }
+            if let (true, Some(mut velocity)) = (active, velocity) {
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut velocity.linear.x).speed(0.1).prefix("vx "));
+                    ui.add(egui::DragValue::new(&mut velocity.linear.y).speed(0.1).prefix("vy "));
+                    ui.add(egui::DragValue::new(&mut velocity.linear.z).speed(0.1).prefix("vz "));
+                });
+                ui.add(egui::DragValue::new(&mut velocity.angular.y).speed(0.01).prefix("Yaw speed "));
+            }
+        }
+        if ui.button("Spawn ship").clicked() {
+            let transform = GridTransform::new(camera.pos.coords + camera.frw * 10.0, Default::default());
+            let mut station = Station::new(transform);
+            let light = StationLight::new(&mut station.map, SPACE_LIGHT);
+            let structure = StationStructure::new(&mut station.map);
+            let ship = commands.spawn(StationMeshes::new(station.map.voxel_size, ao_settings.vertex_ao))
+                .insert(light)
+                .insert(structure)
+                .insert(station)
+                .insert(GridVelocity::default()).id();
+            active_grid.entity = Some(ship);
+        }
+
+        ui.separator();
+
+        ui.label("Ambient occlusion:");
+        ui.checkbox(&mut ao_settings.ssao, "SSAO");
+        ui.checkbox(&mut ao_settings.vertex_ao, "Vertex AO");
+        ui.checkbox(&mut baked_light.enabled, "Baked light");
+        ui.add(egui::Slider::new(&mut baked_light.intensity, 0.0..=4.0).text("Baked intensity"));
+
         ui.separator();
 
         // if ui.button("Stress test").clicked() {
@@ -406,6 +623,7 @@ fn init_station_build(
     blocks.panels.push(assets.load("ss13/walls_configs/metal_grid.wall"));
     blocks.panels.push(assets.load("ss13/walls_configs/metal_wall.wall"));
     blocks.panels.push(assets.load("ss13/walls_configs/door.wall"));
+    blocks.panels.push(assets.load("ss13/walls_configs/lamp.wall"));
 
     let common_asset : Handle<RonBlockDesc> = assets.load("ss13/walls_configs/metal_floor.wall");
 
@@ -431,7 +649,23 @@ fn init_station_build(
     camera.frw = camera.frw.normalize();
 
     camera.up =  camera.get_right().cross(&camera.frw).normalize();
-
-    commands.insert_resource(Station::default());
+    commands.insert_resource(CameraController::Rts(RtsController::from_camera(&camera, 0.0)));
+
+    let mut station = Station::default();
+    let light = StationLight::new(&mut station.map, SPACE_LIGHT);
+    let structure = StationStructure::new(&mut station.map);
+    let station = commands.spawn(StationMeshes::new(station.map.voxel_size, true))
+        .insert(light)
+        .insert(structure)
+        .insert(station)
+        .insert(StationStreaming::default()).id();
+    commands.insert_resource(ActiveGrid {
+        entity : Some(station)
+    });
 }
 
+
+//controller drives camera only inside build scene
+fn exit_station_build(mut commands : Commands) {
+    commands.remove_resource::<CameraController>();
+}
@@ -0,0 +1,398 @@
+use std::f32::consts::PI;
+use bevy::log::info;
+use bevy::time::Time;
+use bevy::utils::{HashMap, HashSet};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use space_core::ecs::*;
+use space_core::{Pos3, Pos3i, Ray, Vec3};
+use space_voxel::solid_voxel_map::VoxelMap;
+use crate::scenes::station_data::*;
+
+#[derive(Clone, Debug)]
+pub struct ExplosionEvent {
+    pub center : Pos3,
+    pub radius : f32,
+    pub power : f32
+}
+
+#[derive(Resource, Clone)]
+pub struct DamageSettings {
+    pub ray_count : usize,
+    //part of energy that passes through one solid cell
+    pub wall_absorption : f32,
+    //damage that breaks voxel blocks, they make the hull
+    pub hull_strength : f32,
+    //damage that wrecks objects, e.g. lamps or machines
+    pub object_strength : f32
+}
+
+impl Default for DamageSettings {
+    fn default() -> Self {
+        Self {
+            ray_count : 512,
+            wall_absorption : 0.35,
+            hull_strength : 1.0,
+            object_strength : 0.5
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct ExplosionDamage {
+    pub damage : HashMap<Pos3i, f32>,
+    //voxel cells opened to space
+    pub breached : Vec<Pos3i>,
+    //object cells, whole object goes with any of them
+    pub wrecked : Vec<Pos3i>
+}
+
+//evenly spread directions, the same for every explosion
+fn sphere_directions(count : usize) -> Vec<Vec3> {
+    let golden_angle = PI * (3.0 - 5.0f32.sqrt());
+    let mut res = Vec::with_capacity(count);
+    for i in 0..count {
+        let y = 1.0 - (i as f32 + 0.5) / count as f32 * 2.0;
+        let r = (1.0 - y * y).sqrt();
+        let phi = golden_angle * i as f32;
+        res.push(Vec3::new(phi.cos() * r, y, phi.sin() * r));
+    }
+    res
+}
+
+pub fn simulate_explosion(
+    map : &VoxelMap<StationBlock>,
+    explosion : &ExplosionEvent,
+    settings : &DamageSettings) -> ExplosionDamage {
+
+    let mut res = ExplosionDamage::default();
+    let step = map.voxel_size * 0.5;
+
+    for dir in sphere_directions(settings.ray_count) {
+        let mut attenuation = 1.0;
+        let mut last_cell = None;
+        let mut dist = 0.0;
+
+        while dist <= explosion.radius {
+            let pos = explosion.center + dir * dist;
+            let cell = map.get_voxel_pos(&pos);
+            dist += step;
+
+            if last_cell == Some(cell) {
+                continue;
+            }
+            last_cell = Some(cell);
+
+            let energy = explosion.power * (1.0 - (pos - explosion.center).norm() / explosion.radius) * attenuation;
+            if energy <= 0.0 {
+                break;
+            }
+
+            if map.get_cloned(&map.get_world_pos(&cell)) != StationBlock::None {
+                let damage = res.damage.entry(cell).or_insert(0.0);
+                if *damage < energy {
+                    *damage = energy;
+                }
+                attenuation *= settings.wall_absorption;
+            }
+        }
+    }
+
+    for (pos, damage) in &res.damage {
+        match map.get_voxel(pos) {
+            Some(StationBlock::Voxel(_)) if *damage >= settings.hull_strength => res.breached.push(*pos),
+            Some(StationBlock::Object(_)) if *damage >= settings.object_strength => res.wrecked.push(*pos),
+            _ => {}
+        }
+    }
+    res.breached.sort_by_key(|p| (p.x, p.y, p.z));
+    res.wrecked.sort_by_key(|p| (p.x, p.y, p.z));
+
+    res
+}
+
+//opens breached cells to space and removes wrecked objects, returns them
+pub fn apply_explosion(
+    station : &mut Station,
+    damage : &ExplosionDamage) -> Vec<Entity> {
+
+    let mut destroyed = vec![];
+    let mut destroyed_set = HashSet::new();
+
+    for cell in damage.breached.iter().chain(&damage.wrecked) {
+        let pos = station.map.get_world_pos(cell);
+        match station.map.get_cloned(&pos) {
+            StationBlock::None => {}
+            StationBlock::Voxel(_) => {
+                station.map.set(&pos, StationBlock::None);
+            }
+            StationBlock::Object(entity) => {
+                station.remove_object(entity, &pos);
+                if destroyed_set.insert(entity) {
+                    destroyed.push(entity);
+                }
+            }
+        }
+    }
+
+    destroyed
+}
+
+pub fn station_bounds(map : &VoxelMap<StationBlock>) -> Option<(Pos3i, Pos3i)> {
+    let mut res : Option<(Pos3i, Pos3i)> = None;
+    for chunk in map.map.values() {
+        for z in 0..chunk.size.z {
+            for y in 0..chunk.size.y {
+                for x in 0..chunk.size.x {
+                    if *chunk.get(x, y, z) == StationBlock::None {
+                        continue;
+                    }
+                    let p = chunk.origin + space_core::Vec3i::new(x, y, z);
+                    res = match res {
+                        None => Some((p, p)),
+                        Some((min, max)) => Some((min.inf(&p), max.sup(&p)))
+                    };
+                }
+            }
+        }
+    }
+    res
+}
+
+#[derive(Resource)]
+pub struct MeteorShower {
+    pub seed : u64,
+    pub enabled : bool,
+    pub interval : f32,
+    pub min_radius : f32,
+    pub max_radius : f32,
+    pub min_power : f32,
+    pub max_power : f32,
+    rng : StdRng,
+    timer : f32
+}
+
+impl Default for MeteorShower {
+    fn default() -> Self {
+        MeteorShower::new(0)
+    }
+}
+
+impl MeteorShower {
+    pub fn new(seed : u64) -> Self {
+        Self {
+            seed,
+            enabled : false,
+            interval : 2.0,
+            min_radius : 1.0,
+            max_radius : 3.0,
+            min_power : 1.0,
+            max_power : 4.0,
+            rng : StdRng::seed_from_u64(seed),
+            timer : 0.0
+        }
+    }
+
+    pub fn reset(&mut self, seed : u64) {
+        self.seed = seed;
+        self.rng = StdRng::seed_from_u64(seed);
+        self.timer = 0.0;
+    }
+
+    fn random_dir(&mut self) -> Vec3 {
+        let y : f32 = self.rng.gen_range(-1.0..1.0);
+        let phi : f32 = self.rng.gen_range(0.0..(2.0 * PI));
+        let r = (1.0 - y * y).sqrt();
+        Vec3::new(phi.cos() * r, y, phi.sin() * r)
+    }
+
+    //None if station is empty or meteor flew through a gap, center is grid-local
+    pub fn next_impact(&mut self, map : &VoxelMap<StationBlock>) -> Option<ExplosionEvent> {
+        let dir = self.random_dir();
+        let (min, max) = station_bounds(map)?;
+
+        let min = map.get_world_pos(&min);
+        let max = map.get_world_pos(&max);
+        let half_size = (max - min) / 2.0;
+        let center = min + half_size;
+
+        let target = center + Vec3::new(
+            self.rng.gen_range(-1.0..=1.0) * half_size.x,
+            self.rng.gen_range(-1.0..=1.0) * half_size.y,
+            self.rng.gen_range(-1.0..=1.0) * half_size.z);
+        let radius = self.rng.gen_range(self.min_radius..=self.max_radius);
+        let power = self.rng.gen_range(self.min_power..=self.max_power);
+
+        let bound_radius = half_size.norm() + map.voxel_size * 2.0;
+        let ray = Ray {
+            pos : target - dir * bound_radius,
+            dir
+        };
+        let hit = map.raycast(&ray, bound_radius * 2.0, |v| *v != StationBlock::None)?;
+
+        Some(ExplosionEvent {
+            center : map.get_world_pos(&hit.voxel),
+            radius,
+            power
+        })
+    }
+}
+
+//meteors fall on the active grid
+pub fn meteor_shower_system(
+    time : Res<Time>,
+    mut shower : ResMut<MeteorShower>,
+    active : Res<ActiveGrid>,
+    stations : Query<&Station>,
+    mut events : EventWriter<ExplosionEvent>) {
+
+    if !shower.enabled {
+        return;
+    }
+    let Some(station) = active.entity.and_then(|e| stations.get(e).ok()) else {
+        return;
+    };
+
+    shower.timer += time.delta_seconds();
+    while shower.timer >= shower.interval {
+        shower.timer -= shower.interval;
+        if let Some(mut impact) = shower.next_impact(&station.map) {
+            impact.center = station.transform.to_world(&impact.center);
+            info!("Meteor impact at {:?}", &impact.center);
+            events.send(impact);
+        }
+    }
+}
+
+//explosions are in world space and damage every grid in reach
+pub fn explosion_system(
+    mut cmds : Commands,
+    mut stations : Query<&mut Station>,
+    settings : Res<DamageSettings>,
+    mut events : EventReader<ExplosionEvent>) {
+
+    for e in events.iter() {
+        for mut station in stations.iter_mut() {
+            let local = ExplosionEvent {
+                center : station.transform.to_local(&e.center),
+                ..e.clone()
+            };
+            let damage = simulate_explosion(&station.map, &local, &settings);
+            if damage.breached.is_empty() && damage.wrecked.is_empty() {
+                continue;
+            }
+            for entity in apply_explosion(&mut station, &damage) {
+                cmds.entity(entity).despawn();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wall_station() -> Station {
+        let mut station = Station::default();
+        let s = station.map.voxel_size;
+        for z in -3..4 {
+            for y in -3..4 {
+                station.map.set(&Pos3::new(2.0 * s, y as f32 * s, z as f32 * s), StationBlock::Voxel(VoxelId(1)));
+                station.map.set(&Pos3::new(4.0 * s, y as f32 * s, z as f32 * s), StationBlock::Voxel(VoxelId(1)));
+            }
+        }
+        station
+    }
+
+    #[test]
+    fn walls_attenuate_blast() {
+        let station = wall_station();
+        let s = station.map.voxel_size;
+        let explosion = ExplosionEvent {
+            center : Pos3::new(0.0, 0.0, 0.0),
+            radius : 10.0 * s,
+            power : 10.0
+        };
+        let damage = simulate_explosion(&station.map, &explosion, &DamageSettings::default());
+
+        let front = damage.damage[&Pos3i::new(2, 0, 0)];
+        let back = damage.damage[&Pos3i::new(4, 0, 0)];
+        assert!(front > back);
+
+        let free_falloff = 10.0 * (1.0 - 4.0 / 10.0);
+        assert!(back < free_falloff * 0.5);
+    }
+
+    #[test]
+    fn breached_cells_open_to_space() {
+        let mut station = wall_station();
+        let s = station.map.voxel_size;
+        let explosion = ExplosionEvent {
+            center : Pos3::new(0.0, 0.0, 0.0),
+            radius : 6.0 * s,
+            power : 3.0
+        };
+        let damage = simulate_explosion(&station.map, &explosion, &DamageSettings::default());
+        assert!(damage.breached.contains(&Pos3i::new(2, 0, 0)));
+        assert!(!damage.breached.contains(&Pos3i::new(4, 0, 0)));
+
+        apply_explosion(&mut station, &damage);
+        assert_eq!(station.map.get_cloned(&Pos3::new(2.0 * s, 0.0, 0.0)), StationBlock::None);
+        assert_ne!(station.map.get_cloned(&Pos3::new(4.0 * s, 0.0, 0.0)), StationBlock::None);
+    }
+
+    #[test]
+    fn objects_have_own_strength() {
+        let mut station = wall_station();
+        let s = station.map.voxel_size;
+        let object = StationBlock::Object(Entity::from_raw(1));
+        station.map.set(&Pos3::new(s, 0.0, 0.0), object.clone());
+        let explosion = ExplosionEvent {
+            center : Pos3::new(0.0, 0.0, 0.0),
+            radius : 6.0 * s,
+            power : 3.0
+        };
+        let settings = DamageSettings {
+            hull_strength : 100.0,
+            ..Default::default()
+        };
+        let damage = simulate_explosion(&station.map, &explosion, &settings);
+        assert!(damage.breached.is_empty());
+        assert_eq!(damage.wrecked, vec![Pos3i::new(1, 0, 0)]);
+
+        let settings = DamageSettings {
+            object_strength : 100.0,
+            ..Default::default()
+        };
+        let damage = simulate_explosion(&station.map, &explosion, &settings);
+        assert!(damage.wrecked.is_empty());
+        assert!(!damage.breached.is_empty());
+    }
+
+    #[test]
+    fn meteor_shower_is_deterministic() {
+        let station = wall_station();
+
+        let mut first = MeteorShower::new(42);
+        let mut second = MeteorShower::new(42);
+        for _ in 0..10 {
+            let a = first.next_impact(&station.map).map(|e| (e.center, e.radius, e.power));
+            let b = second.next_impact(&station.map).map(|e| (e.center, e.radius, e.power));
+            assert_eq!(a, b);
+        }
+    }
+
+    #[test]
+    fn meteor_hits_hull() {
+        let station = wall_station();
+        let mut shower = MeteorShower::new(7);
+        let mut hits = 0;
+        for _ in 0..20 {
+            if let Some(impact) = shower.next_impact(&station.map) {
+                assert_ne!(station.map.get_cloned(&impact.center), StationBlock::None);
+                hits += 1;
+            }
+        }
+        assert!(hits > 0);
+    }
+}
@@ -5,17 +5,29 @@ use space_assets::{GMesh, LocationInstancing, Material, SubLocation};
 use space_core::ecs::*;
 use space_core::asset::*;
 use space_core::app::*;
-use space_core::{nalgebra, Pos3, Pos3i, Vec3i};
+use space_core::{nalgebra, Pos3, Pos3i, Vec3, Vec3i};
 use space_core::nalgebra::{inf, Point3};
-use space_voxel::objected_voxel_map::VoxelVal;
+use space_voxel::objected_voxel_map::{VoxelFilling, VoxelVal};
+use space_voxel::region::GridRotation;
+use space_core::serde::*;
 use space_voxel::solid_voxel_map::VoxelMap;
+use space_voxel::change_log::{ChangeCursor, ChunkChangedEvent};
+use space_voxel::grid::GridTransform;
+use space_voxel::remesher::{ChunkMesher, RemeshScheduler};
+use space_voxel::voxel_mesher::{build_lit_voxel_mesh, VoxelMeshData, VoxelMeshSettings};
+use space_voxel::light::{max_color, LightColor, LightProps, VoxelLight, MAX_LIGHT};
+use space_voxel::connectivity::Connectivity;
+use space_voxel::streaming::ChunkStreamer;
 use crate::scenes::RonBlockDesc;
 
 pub struct BlockDesc {
     pub mesh : Handle<GMesh>,
     pub material : Handle<Material>,
     pub name : String,
-    pub bbox : Vec3i
+    //cells taken by block, before BlockAxis rotation
+    pub footprint : VoxelFilling,
+    //light fixture color
+    pub light : Option<LightColor>
 }
 
 #[derive(Resource, Default)]
@@ -26,7 +38,7 @@ pub struct BlockHolder {
 #[derive(Clone, Eq, Hash, PartialEq)]
 pub struct BlockId(pub usize);
 
-#[derive(Clone, Eq, Hash, PartialEq)]
+#[derive(Clone, Eq, Hash, PartialEq, Debug, Serialize, Deserialize)]
 pub struct VoxelId(pub usize);
 
 #[derive(Clone)]
@@ -53,9 +65,159 @@ pub enum InstancingUpdateEvent {
     Update(Entity, BlockId, Point3<i32>)
 }
 
-#[derive(Resource)]
+//voxel grid entity, stations and ships alike, map is in grid-local space
+#[derive(Component)]
 pub struct Station {
-    pub map : VoxelMap<StationBlock>
+    pub map : VoxelMap<StationBlock>,
+    pub transform : GridTransform,
+    //cursor of station_change_events system
+    pub chunk_events : ChangeCursor
+}
+
+#[derive(Component, Default)]
+pub struct GridVelocity {
+    pub linear : Vec3,
+    //rotation axis scaled by radians per second
+    pub angular : Vec3
+}
+
+//grid that receives new blocks when nothing is under cursor
+#[derive(Resource, Default)]
+pub struct ActiveGrid {
+    pub entity : Option<Entity>
+}
+
+pub struct GridChunkChangedEvent {
+    pub grid : Entity,
+    pub chunk : ChunkChangedEvent
+}
+
+//voxel chunk meshes of grid, rebuilt on task server
+#[derive(Component)]
+pub struct StationMeshes {
+    pub scheduler : RemeshScheduler<StationBlock, VoxelMeshData, Vec<LightColor>>,
+    pub vertex_ao : bool,
+    //rendered submeshes of every chunk
+    pub chunks : HashMap<Pos3i, Vec<Entity>>
+}
+
+//light is padded light around chunk, see VoxelLight::padded
+pub fn station_mesher(voxel_size : f32, vertex_ao : bool) -> ChunkMesher<StationBlock, VoxelMeshData, Vec<LightColor>> {
+    let settings = VoxelMeshSettings {
+        voxel_size,
+        ambient_occlusion : vertex_ao
+    };
+    std::sync::Arc::new(move |chunk, borders, light| build_lit_voxel_mesh(chunk, borders, light, &settings, |id : &VoxelId| id.0))
+}
+
+impl StationMeshes {
+    pub fn new(voxel_size : f32, vertex_ao : bool) -> Self {
+        Self {
+            scheduler : RemeshScheduler::new(station_mesher(voxel_size, vertex_ao), 4),
+            vertex_ao,
+            chunks : HashMap::new()
+        }
+    }
+}
+
+//baked light of grid, follows its map changes
+#[derive(Component)]
+pub struct StationLight {
+    pub light : VoxelLight,
+    //light colors of placed fixture objects
+    pub fixtures : HashMap<Entity, LightColor>,
+    map_cursor : ChangeCursor,
+    light_cursor : ChangeCursor
+}
+
+impl StationLight {
+    pub fn new(map : &mut VoxelMap<StationBlock>, space_light : LightColor) -> Self {
+        let mut light = VoxelLight::new(map, space_light);
+        let light_cursor = light.cells.changes.subscribe();
+        Self {
+            light,
+            fixtures : HashMap::new(),
+            map_cursor : map.changes.subscribe(),
+            light_cursor
+        }
+    }
+
+    //updates light from map changes, returns chunks whose light changed
+    pub fn update(&mut self, map : &mut VoxelMap<StationBlock>, block_holder : &BlockHolder) -> HashSet<Pos3i> {
+        let changes = map.changes.drain(self.map_cursor);
+        if changes.is_empty() {
+            return HashSet::new();
+        }
+        let fixtures = &self.fixtures;
+        self.light.update(map, &changes, |val| block_light_props(block_holder, fixtures, val));
+
+        let light_changes = self.light.cells.changes.drain(self.light_cursor);
+        self.light.cells.touched_chunks(&light_changes)
+    }
+}
+
+//cells of map taken by placed object
+pub fn object_cells(map : &VoxelMap<StationBlock>, entity : Entity, part : &StationPart) -> Vec<Pos3i> {
+    let half = part.bbox.cast::<f32>() * map.voxel_size / 2.0;
+    //local_pos is center of bbox, its min corner sits on first voxel
+    let min = map.get_voxel_pos(&Pos3::from(part.local_pos - half));
+    let mut res = vec![];
+    for z in 0..part.bbox.z {
+        for y in 0..part.bbox.y {
+            for x in 0..part.bbox.x {
+                let pos = min + Vec3i::new(x, y, z);
+                if map.get_voxel(&pos) == Some(&StationBlock::Object(entity)) {
+                    res.push(pos);
+                }
+            }
+        }
+    }
+    res
+}
+
+//objects are opaque, they show own emission and light of open cells next to them
+pub fn object_light(map : &VoxelMap<StationBlock>, light : &VoxelLight, cells : &[Pos3i]) -> Vec3 {
+    let mut res = [0; 3];
+    for pos in cells {
+        res = max_color(res, light.get(pos));
+        for offset in Connectivity::Faces.offsets() {
+            let near = pos + offset;
+            if map.get_voxel(&near).is_none_or(|v| *v == StationBlock::None) {
+                res = max_color(res, light.get(&near));
+            }
+        }
+    }
+    Vec3::new(res[0] as f32, res[1] as f32, res[2] as f32) / MAX_LIGHT as f32
+}
+
+fn block_light_props(block_holder : &BlockHolder, fixtures : &HashMap<Entity, LightColor>, val : &StationBlock) -> LightProps {
+    match val {
+        StationBlock::None => LightProps::default(),
+        StationBlock::Voxel(id) => LightProps {
+            opaque : true,
+            emission : block_holder.map.get(&BlockId(id.0)).and_then(|d| d.light).unwrap_or_default()
+        },
+        StationBlock::Object(e) => LightProps {
+            opaque : true,
+            emission : fixtures.get(e).cloned().unwrap_or_default()
+        }
+    }
+}
+
+//only the home station streams its chunks
+#[derive(Component)]
+pub struct StationStreaming {
+    pub enabled : bool,
+    pub streamer : ChunkStreamer<StationBlock>
+}
+
+impl Default for StationStreaming {
+    fn default() -> Self {
+        Self {
+            enabled : false,
+            streamer : ChunkStreamer::new(std::path::Path::new("cache/station"), 40.0, 48.0)
+        }
+    }
 }
 
 #[derive(Resource, Default)]
@@ -65,9 +227,7 @@ pub struct StationRender {
 
 impl Default for Station {
     fn default() -> Self {
-        Self {
-            map : VoxelMap::new(0.5, [16, 16, 16].into())
-        }
+        Station::new(GridTransform::default())
     }
 }
 
@@ -77,6 +237,28 @@ pub struct ChunkUpdateEvent {
 }
 
 impl Station {
+    pub fn new(transform : GridTransform) -> Self {
+        let mut map = VoxelMap::new(0.5, [16, 16, 16].into());
+        let chunk_events = map.changes.subscribe();
+        Self {
+            map,
+            transform,
+            chunk_events
+        }
+    }
+
+    pub fn remove_object(&mut self, entity : Entity, pos : &Pos3) {
+        let val = StationBlock::Object(entity);
+        let reach = self.map.chunk_size;
+        let min = self.map.get_voxel_pos(pos) - reach;
+        let cells : Vec<Pos3i> = self.map.iter_region(&min, &(reach * 2))
+            .filter(|(_, v)| **v == val)
+            .map(|(p, _)| p)
+            .collect();
+        for p in cells {
+            self.map.set_voxel(&p, StationBlock::None);
+        }
+    }
 
     pub fn get_grid_pos(
         &self,
@@ -94,7 +276,7 @@ impl Station {
 
 
         let pos = nalgebra::Point3::from_slice(
-            event.world_pos.coords.as_slice());
+            event.local_pos.coords.as_slice());
 
         let origin = self.map.get_origin(&self.map.get_voxel_pos(
             &pos));
@@ -136,14 +318,73 @@ impl Default for BlockAxis {
     }
 }
 
+impl BlockAxis {
+    //euler angles of block mesh, as in Location
+    pub fn euler(&self) -> Vec3 {
+        match self {
+            BlockAxis::Y => Vec3::new(0.0, 0.0, 0.0),
+            BlockAxis::X => Vec3::new(0.0, 0.0, std::f32::consts::FRAC_PI_2),
+            BlockAxis::Z => Vec3::new(std::f32::consts::FRAC_PI_2, 0.0, 0.0)
+        }
+    }
+
+    //same turn as euler, for footprints
+    pub fn grid_rotation(&self) -> GridRotation {
+        match self {
+            BlockAxis::Y => GridRotation::identity(),
+            BlockAxis::X => GridRotation::around_z(1),
+            BlockAxis::Z => GridRotation::around_x(1)
+        }
+    }
+}
+
 pub struct AddBlockEvent {
     pub id : BuildCommand,
-    pub world_pos : Pos3,
+    pub grid : Entity,
+    pub local_pos : Pos3,
     pub rot : BlockAxis
 }
 
 #[derive(Component)]
 pub struct StationPart {
-    pub bbox : Vec3i
+    pub bbox : Vec3i,
+    pub grid : Entity,
+    //placement inside grid, world Location follows grid transform
+    pub local_pos : Vec3,
+    pub local_rot : Vec3
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn object_sees_light_around_it() {
+        let mut station = Station::default();
+        let entity = Entity::from_raw(7);
+        let mut light = VoxelLight::new(&station.map, [0; 3]);
+        let fixtures = HashMap::from_iter([(entity, [0, 0, 12])]);
+        let holder = BlockHolder::default();
+
+        //1x2x1 lamp with a wall block above it
+        station.map.set_voxel(&Pos3i::new(2, 0, 0), StationBlock::Object(entity));
+        station.map.set_voxel(&Pos3i::new(2, 1, 0), StationBlock::Object(entity));
+        station.map.set_voxel(&Pos3i::new(2, 2, 0), StationBlock::Voxel(VoxelId(1)));
+        light.rebuild(&station.map, |val| block_light_props(&holder, &fixtures, val));
+
+        let part = StationPart {
+            bbox : Vec3i::new(1, 2, 1),
+            grid : entity,
+            local_pos : Vec3::new(2.5, 1.0, 0.5) * station.map.voxel_size,
+            local_rot : Vec3::zeros()
+        };
+        let cells = object_cells(&station.map, entity, &part);
+        assert_eq!(cells, vec![Pos3i::new(2, 0, 0), Pos3i::new(2, 1, 0)]);
+        assert_eq!(object_light(&station.map, &light, &cells), Vec3::new(0.0, 0.0, 12.0 / 15.0));
+
+        //wall next to lamp gets light of open cells around it, one step dimmer
+        let wall = object_light(&station.map, &light, &[Pos3i::new(2, 2, 0)]);
+        assert_eq!(wall, Vec3::new(0.0, 0.0, 10.0 / 15.0));
+    }
+}
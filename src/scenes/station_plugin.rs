@@ -1,21 +1,33 @@
 use std::f32::consts::PI;
+use bevy::utils::HashSet;
 use space_assets::{GMesh, Location, LocationInstancing, Material, SubLocation};
+use space_core::asset::Assets;
 use space_core::ecs::*;
-use space_core::{nalgebra, Pos3, Vec3, Vec3i};
-use space_game::RenderApi;
+use bevy::time::Time;
+use space_core::{nalgebra, Pos3, Pos3i, Vec3, Vec3i};
+use space_core::Camera;
+use space_game::{RenderApi, TaskServerApi};
+use space_render::light::AmbientOcclusionSettings;
 use space_voxel::objected_voxel_map::VoxelVal;
 use space_voxel::solid_voxel_map::VoxelChunk;
+use space_voxel::streaming::ChunkStreamEvent;
+use space_voxel::remesher::RemeshResult;
+use space_voxel::change_log::ChunkChangedEvent;
 use crate::scenes::station_data::*;
 
 
 pub fn setup_blocks(
     mut cmds : Commands,
     block_holder : Res<BlockHolder>,
-    mut station : ResMut<Station>,
+    mut stations : Query<(&mut Station, Option<&mut StationLight>)>,
     mut events : EventReader<AddBlockEvent>,
     render : Res<RenderApi>) {
 
     for e in events.iter() {
+        let Ok((mut station, mut light)) = stations.get_mut(e.grid) else {
+            continue;
+        };
+
         // station.add_block_event(
         //     &mut cmds,
         //     e,
@@ -24,29 +36,15 @@ pub fn setup_blocks(
 
         match &e.id {
             BuildCommand::None => {
-                let val = station.map.get_cloned(&e.world_pos);
+                let val = station.map.get_cloned(&e.local_pos);
                 match &val {
                     StationBlock::None => {}
                     StationBlock::Voxel(_) => {}
                     StationBlock::Object(entity) => {
-                        for dz in -1..2 {
-                            for dy in -1..2 {
-                                for dx in -1..2 {
-                                    let test_pos =
-                                        e.world_pos
-                                            + Vec3::new(dx as f32, dy as f32, dz as f32) * 16.0 * station.map.voxel_size;
-
-                                    if let Some(chunk) = station.map.get_chunk_mut(&test_pos) {
-                                        for idx in 0..chunk.data.len() {
-                                            if chunk.data[idx] == val {
-                                                chunk.data[idx] = StationBlock::None;
-                                            }
-                                        }
-                                    }
-                                }
-                            }
+                        if let Some(light) = light.as_mut() {
+                            light.fixtures.remove(entity);
                         }
-
+                        station.remove_object(*entity, &e.local_pos);
                         cmds.entity(*entity).despawn();
                     }
                 }
@@ -55,22 +53,9 @@ pub fn setup_blocks(
             BuildCommand::Block(id) => {
                 let bundle = block_holder.map.get(&id).unwrap();
 
-                let mut bbox = bundle.bbox.clone();
-
-                let rot;
-                match e.rot {
-                    BlockAxis::Y => {
-                        rot = Vec3::new(0.0,0.0,0.0);
-                    }
-                    BlockAxis::X => {
-                        rot = Vec3::new(0.0, 0.0, 3.14 / 2.0);
-                        bbox = Vec3i::new(bbox.y, bbox.x, bbox.z);
-                    }
-                    BlockAxis::Z => {
-                        rot = Vec3::new(3.14 / 2.0, 0.0, 0.0);
-                        bbox = Vec3i::new(bbox.x, bbox.z, bbox.y);
-                    }
-                }
+                let footprint = bundle.footprint.rotated(&e.rot.grid_rotation());
+                let bbox = footprint.size();
+                let rot = e.rot.euler();
 
                 let shift = Vec3::new(
                   bbox.x as f32 * station.map.voxel_size / 2.0,
@@ -78,58 +63,204 @@ pub fn setup_blocks(
                   bbox.z as f32 * station.map.voxel_size / 2.0,
                 );
 
-                let vp = station.map.get_voxel_pos(&(e.world_pos));
-
-                //test bbox
-                let mut is_free = true;
-                for z in 0..bbox.z {
-                    for y in 0..bbox.y {
-                        for x in 0..bbox.x {
-                            let pos_i = (vp + Vec3i::new(x, y, z));
-                            let pos = Pos3::new(
-                                pos_i.x as f32 * station.map.voxel_size,
-                                pos_i.y as f32 * station.map.voxel_size,
-                                pos_i.z as f32 * station.map.voxel_size,
-                            );
-                            if station.map.get_cloned(&pos) != StationBlock::None {
-                                is_free = false;
-                            }
-                        }
-                    }
-                }
+                let vp = station.map.get_voxel_pos(&(e.local_pos));
 
-                if is_free {
+                if station.map.is_filling_empty(&vp, &footprint) {
+                    let local_pos = e.local_pos.coords + shift;
                     let mut loc = Location::new(&render.device);
-                    loc.rotation = rot;
-                    loc.pos = e.world_pos.coords + shift;
+                    loc.rotation = station.transform.euler_to_world(&rot);
+                    loc.pos = station.transform.to_world(&Pos3::from(local_pos)).coords;
                     let entity = cmds.spawn((bundle.material.clone(), bundle.mesh.clone()))
-                        .insert(StationPart { bbox: Default::default() })
+                        .insert(StationPart {
+                            bbox,
+                            grid : e.grid,
+                            local_pos,
+                            local_rot : rot
+                        })
                         .insert(loc).id();
 
-                    for z in 0..bbox.z {
-                        for y in 0..bbox.y {
-                            for x in 0..bbox.x {
-                                let pos_i = (vp + Vec3i::new(x, y, z));
-                                let pos = Pos3::new(
-                                    pos_i.x as f32 * station.map.voxel_size,
-                                    pos_i.y as f32 * station.map.voxel_size,
-                                    pos_i.z as f32 * station.map.voxel_size,
-                                );
-                                station.map.set(&pos, VoxelVal::Object(entity.clone()));
-                                if station.map.get_cloned(&pos) != StationBlock::None {
-                                    is_free = false;
-                                }
-                            }
-                        }
+                    //fixture must be known before its cells reach the light system
+                    if let (Some(color), Some(light)) = (bundle.light, light.as_mut()) {
+                        light.fixtures.insert(entity, color);
                     }
+                    station.map.fill_filling(&vp, &footprint, &VoxelVal::Object(entity));
                 }
             }
             BuildCommand::Voxel(id) => {
+                let vp = station.map.get_voxel_pos(&e.local_pos);
+                if station.map.get_voxel(&vp).is_none_or(|v| *v == StationBlock::None) {
+                    station.map.set_voxel(&vp, StationBlock::Voxel(id.clone()));
+                }
+            }
+        }
+
+
+    }
+}
+
+pub fn station_streaming_system(
+    camera : Res<Camera>,
+    tasks : Res<TaskServerApi>,
+    mut stations : Query<(&mut Station, &mut StationStreaming)>,
+    mut events : EventWriter<ChunkStreamEvent>) {
+
+    for (mut station, mut streaming) in stations.iter_mut() {
+        if !streaming.enabled {
+            continue;
+        }
+
+        let camera_pos = station.transform.to_local(&camera.pos);
+        streaming.streamer.update(&mut station.map, &camera_pos, &tasks);
+        events.send_batch(streaming.streamer.drain_events());
+    }
+}
+
+pub fn station_change_events(
+    mut stations : Query<(Entity, &mut Station)>,
+    mut events : EventWriter<GridChunkChangedEvent>) {
+
+    for (grid, mut station) in stations.iter_mut() {
+        let station = station.as_mut();
+        let changes = station.map.changes.drain(station.chunk_events);
+        if !changes.is_empty() {
+            events.send_batch(station.map.chunk_events(&changes).into_iter()
+                .map(|chunk| GridChunkChangedEvent { grid, chunk }));
+        }
+    }
+}
 
+//light changes remesh chunks like block changes do
+pub fn station_light_system(
+    block_holder : Res<BlockHolder>,
+    mut grids : Query<(Entity, &mut Station, &mut StationLight)>,
+    mut events : EventWriter<GridChunkChangedEvent>) {
+
+    for (grid, mut station, mut light) in grids.iter_mut() {
+        let mut origins : Vec<Pos3i> = light.update(&mut station.map, &block_holder).into_iter().collect();
+        origins.sort_by_key(|p| (p.x, p.y, p.z));
+        events.send_batch(origins.into_iter()
+            .map(|origin| GridChunkChangedEvent { grid, chunk : ChunkChangedEvent { origin } }));
+    }
+}
+
+//chunk meshes bake light into vertices, placed objects take it per instance
+pub fn object_light_system(
+    mut events : EventReader<GridChunkChangedEvent>,
+    grids : Query<(&Station, &StationLight)>,
+    mut parts : Query<(Entity, &StationPart, &mut Location, ChangeTrackers<StationPart>)>) {
+
+    let changed : HashSet<(Entity, Pos3i)> = events.iter().map(|e| (e.grid, e.chunk.origin)).collect();
+    for (entity, part, mut loc, tracker) in parts.iter_mut() {
+        //chunk meshes have no footprint
+        if part.bbox == Vec3i::zeros() || (changed.is_empty() && !tracker.is_changed()) {
+            continue;
+        }
+        let Ok((station, light)) = grids.get(part.grid) else {
+            continue;
+        };
+        let cells = object_cells(&station.map, entity, part);
+        let touched = tracker.is_changed() || cells.iter().any(|c| changed.contains(&(part.grid, station.map.get_origin(c))));
+        if !touched {
+            continue;
+        }
+        let color = object_light(&station.map, &light.light, &cells);
+        if loc.light != color {
+            loc.light = color;
+        }
+    }
+}
+
+pub fn station_remesh_system(
+    mut cmds : Commands,
+    camera : Res<Camera>,
+    tasks : Res<TaskServerApi>,
+    render : Res<RenderApi>,
+    ao_settings : Res<AmbientOcclusionSettings>,
+    block_holder : Res<BlockHolder>,
+    mut meshes : ResMut<Assets<GMesh>>,
+    mut events : EventReader<GridChunkChangedEvent>,
+    mut grids : Query<(Entity, &Station, &mut StationMeshes, &StationLight)>) {
+
+    let events : Vec<&GridChunkChangedEvent> = events.iter().collect();
+    for (grid, station, mut station_meshes, light) in grids.iter_mut() {
+        let station_meshes = station_meshes.as_mut();
+        if station_meshes.vertex_ao != ao_settings.vertex_ao {
+            station_meshes.vertex_ao = ao_settings.vertex_ao;
+            station_meshes.scheduler.mesher = station_mesher(station.map.voxel_size, ao_settings.vertex_ao);
+            station_meshes.scheduler.mark_all(&station.map);
+        }
+        for e in events.iter().filter(|e| e.grid == grid) {
+            station_meshes.scheduler.mark_dirty(e.chunk.origin);
+        }
+
+        let focus = station.transform.to_local(&camera.pos);
+        station_meshes.scheduler.update_with(&station.map, &focus, &tasks.server, |origin| light.light.padded(origin));
+
+        //old mesh stays visible until the new one is ready
+        for res in station_meshes.scheduler.drain_finished() {
+            let (origin, mesh) = match res {
+                RemeshResult::Meshed(origin, mesh) => (origin, Some(mesh)),
+                RemeshResult::Removed(origin) => (origin, None)
+            };
+            for e in station_meshes.chunks.remove(&origin).unwrap_or_default() {
+                cmds.entity(e).despawn();
             }
+            let Some(mesh) = mesh else {
+                continue;
+            };
+
+            let local_pos = origin.coords.cast::<f32>() * station.map.voxel_size;
+            let mut entities = vec![];
+            for sub in &mesh.submeshes {
+                let Some(desc) = block_holder.map.get(&BlockId(sub.material)) else {
+                    continue;
+                };
+                let mut loc = Location::new(&render.device);
+                loc.pos = station.transform.to_world(&Pos3::from(local_pos)).coords;
+                loc.rotation = station.transform.euler_to_world(&Vec3::zeros());
+                let entity = cmds.spawn((meshes.add(sub.to_gmesh(&render.base)), desc.material.clone()))
+                    .insert(StationPart {
+                        bbox : Vec3i::zeros(),
+                        grid,
+                        local_pos,
+                        local_rot : Vec3::zeros()
+                    })
+                    .insert(loc).id();
+                entities.push(entity);
+            }
+            station_meshes.chunks.insert(origin, entities);
+        }
+    }
+}
+
+pub fn move_grids(
+    time : Res<Time>,
+    mut grids : Query<(&mut Station, &GridVelocity)>) {
+
+    for (mut station, velocity) in grids.iter_mut() {
+        if velocity.linear.norm_squared() == 0.0 && velocity.angular.norm_squared() == 0.0 {
+            continue;
         }
+        station.transform.integrate(&velocity.linear, &velocity.angular, time.delta_seconds());
+    }
+}
 
+//keeps blocks attached to their grids
+pub fn sync_grid_parts(
+    stations : Query<&Station>,
+    mut parts : Query<(&StationPart, &mut Location)>) {
 
+    for (part, mut loc) in parts.iter_mut() {
+        let Ok(station) = stations.get(part.grid) else {
+            continue;
+        };
+        let pos = station.transform.to_world(&Pos3::from(part.local_pos)).coords;
+        let rotation = station.transform.euler_to_world(&part.local_rot);
+        //touching Location re-uploads its buffer
+        if loc.pos != pos || loc.rotation != rotation {
+            loc.pos = pos;
+            loc.rotation = rotation;
+        }
     }
 }
 
@@ -198,7 +329,7 @@ pub fn catch_update_events(
 
 pub fn update_instancing_holders(
     mut query : Query<&mut LocationInstancing>,
-    station : Res<Station>,
+    stations : Query<&Station>,
     mut events : EventReader<InstancingUpdateEvent>
 ) {
     for event in events.iter() {
@@ -206,7 +337,7 @@ pub fn update_instancing_holders(
             InstancingUpdateEvent::Update(e, id, key) => {
                 match query.get_component_mut::<LocationInstancing>(*e) {
                     Ok(mut loc) => {
-                        if let Some(chunk) = station.map.get_chunk_by_voxel(&key) {
+                        if let Some(chunk) = stations.iter().find_map(|station| station.map.get_chunk_by_voxel(&key)) {
                             // loc.locs = collect_sub_locs(chunk, *id, station.map.voxel_size);
                         }
                     },
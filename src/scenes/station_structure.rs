@@ -0,0 +1,360 @@
+use bevy::log::info;
+use bevy::utils::HashSet;
+use space_core::ecs::*;
+use space_core::{Camera, Pos3, Pos3i, Vec3};
+use space_game::{EguiContext, ScreenSize};
+use space_voxel::change_log::{ChangeCursor, MapChange};
+use space_voxel::connectivity::{Connectivity, VoxelComponents};
+use space_voxel::solid_voxel_map::VoxelMap;
+use crate::scenes::station_data::*;
+
+//speed a cut off piece gets away from the station
+const DETACH_SPEED : f32 = 0.5;
+
+//highlighting thousands of cells only hides the station
+const MAX_HIGHLIGHTED : usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FloatingAction {
+    //cut off pieces become separate drifting grids
+    Detach,
+    //pieces stay in place and are highlighted until builder reconnects or detaches them
+    Warn
+}
+
+#[derive(Resource)]
+pub struct StructureSettings {
+    pub action : FloatingAction,
+    //detach every highlighted piece on next update
+    pub detach_now : bool
+}
+
+impl Default for StructureSettings {
+    fn default() -> Self {
+        Self {
+            action : FloatingAction::Detach,
+            detach_now : false
+        }
+    }
+}
+
+fn is_solid(val : &StationBlock) -> bool {
+    *val != StationBlock::None
+}
+
+//face connected pieces of grid, core starts as the largest one
+//and then stays the largest piece of itself, other structures never take it over
+#[derive(Component)]
+pub struct StationStructure {
+    pub components : VoxelComponents,
+    //pieces cut off from core that still belong to this grid
+    pub floating : Vec<u32>,
+    core : Option<u32>,
+    cursor : ChangeCursor
+}
+
+impl StationStructure {
+    pub fn new(map : &mut VoxelMap<StationBlock>) -> Self {
+        let components = VoxelComponents::build(map, Connectivity::Faces, is_solid, |_, _| true);
+        Self {
+            core : Self::largest(&components, components.labels()),
+            components,
+            floating : vec![],
+            cursor : map.changes.subscribe()
+        }
+    }
+
+    fn largest(components : &VoxelComponents, labels : impl Iterator<Item = u32>) -> Option<u32> {
+        labels.max_by_key(|label| (components.size(*label), std::cmp::Reverse(*label)))
+    }
+
+    pub fn core(&self) -> Option<u32> {
+        self.core
+    }
+
+    //applies map changes, returns pieces that removals cut off from core
+    //separate structures were never attached, removing their blocks cuts nothing
+    pub fn update(&mut self, map : &mut VoxelMap<StationBlock>) -> Vec<u32> {
+        let changes = map.changes.drain(self.cursor);
+        if changes.is_empty() {
+            return vec![];
+        }
+
+        //labels are only valid before update
+        let old_core = self.core;
+        let core_removals : HashSet<Pos3i> = changes.iter()
+            .filter_map(|change| match change {
+                MapChange::Voxel { pos, old, new : StationBlock::None } if is_solid(old) => Some(*pos),
+                _ => None
+            })
+            .filter(|pos| old_core.is_some() && self.components.label(pos) == old_core)
+            .collect();
+        //core cell that stays, finds core again when labels are merged or rebuilt
+        let anchor = old_core
+            .and_then(|core| self.components.cells(core))
+            .and_then(|cells| cells.iter().find(|pos| !core_removals.contains(*pos)).cloned());
+        self.components.update_changes(map, &changes, is_solid, |_, _| true);
+
+        //every piece that came out of old core touches a removed cell or is the anchor one
+        let mut pieces = vec![];
+        for pos in anchor.into_iter().chain(core_removals.iter().flat_map(|pos|
+                Connectivity::Faces.offsets().into_iter().map(move |offset| pos + offset))) {
+            match self.components.label(&pos) {
+                Some(label) if !pieces.contains(&label) => pieces.push(label),
+                _ => {}
+            }
+        }
+        let core = if old_core.is_some() && !pieces.is_empty() {
+            Self::largest(&self.components, pieces.iter().cloned())
+        } else {
+            Self::largest(&self.components, self.components.labels())
+        };
+        self.core = core;
+        let cut : Vec<u32> = pieces.into_iter().filter(|label| Some(*label) != core).collect();
+
+        //reconnected pieces were merged into core
+        let components = &self.components;
+        self.floating.retain(|label| components.size(*label) > 0 && Some(*label) != core);
+        cut
+    }
+
+    pub fn floating_cells(&self) -> impl Iterator<Item = &Pos3i> + '_ {
+        self.floating.iter()
+            .filter_map(|label| self.components.cells(*label))
+            .flatten()
+    }
+}
+
+//moves cells into another grid at the same local place, objects keep their entities
+//piece consumers subscribed before the move see the cells as placed blocks
+pub fn move_cells(station : &mut Station, piece : &mut Station, cells : &[Pos3i]) {
+    for pos in cells {
+        let val = station.map.get_voxel(pos).cloned().unwrap_or_default();
+        piece.map.set_voxel(pos, val);
+        station.map.set_voxel(pos, StationBlock::None);
+    }
+}
+
+fn cells_center(map : &VoxelMap<StationBlock>, cells : &[Pos3i]) -> Pos3 {
+    let sum = cells.iter().fold(Vec3::zeros(), |sum, p| sum + map.get_world_pos(p).coords);
+    Pos3::from(sum / cells.len().max(1) as f32)
+}
+
+type StructureGrid = (
+    Entity,
+    &'static mut Station,
+    &'static mut StationStructure,
+    Option<&'static GridVelocity>,
+    Option<&'static mut StationLight>,
+    Option<&'static StationStreaming>);
+
+pub fn structure_system(
+    mut cmds : Commands,
+    mut settings : ResMut<StructureSettings>,
+    mut grids : Query<StructureGrid>,
+    mut parts : Query<&mut StationPart>) {
+
+    let detach_now = std::mem::replace(&mut settings.detach_now, false);
+    for (grid, mut station, mut structure, velocity, mut light, streaming) in grids.iter_mut() {
+        //far chunks are unloaded, pieces may only look separate
+        //changes stay queued and are checked once streaming is off
+        if streaming.is_some_and(|s| s.enabled) {
+            continue;
+        }
+        let cut = structure.update(&mut station.map);
+
+        let detached = match settings.action {
+            FloatingAction::Detach => cut,
+            FloatingAction::Warn => {
+                structure.floating.extend(cut);
+                if detach_now {
+                    std::mem::take(&mut structure.floating)
+                } else {
+                    vec![]
+                }
+            }
+        };
+
+        let core_cells : Vec<Pos3i> = structure.core()
+            .and_then(|core| structure.components.cells(core))
+            .map(|cells| cells.iter().cloned().collect())
+            .unwrap_or_default();
+        let core_center = cells_center(&station.map, &core_cells);
+
+        for label in detached {
+            let Some(cells) = structure.components.cells(label) else {
+                continue;
+            };
+            let cells : Vec<Pos3i> = cells.iter().cloned().collect();
+            let away = cells_center(&station.map, &cells) - core_center;
+            let mut piece = Station::new(station.transform);
+            let mut piece_light = StationLight::new(&mut piece.map, light.as_ref().map_or([0; 3], |l| l.light.space_light));
+            move_cells(&mut station, &mut piece, &cells);
+
+            let entity = cmds.spawn_empty().id();
+            let objects : HashSet<Entity> = cells.iter()
+                .filter_map(|pos| match piece.map.get_voxel(pos) {
+                    Some(StationBlock::Object(e)) => Some(*e),
+                    _ => None
+                })
+                .collect();
+            for object in objects {
+                if let Ok(mut part) = parts.get_mut(object) {
+                    part.grid = entity;
+                }
+                if let Some(color) = light.as_mut().and_then(|l| l.fixtures.remove(&object)) {
+                    piece_light.fixtures.insert(object, color);
+                }
+            }
+
+            let drift = station.transform.dir_to_world(&away.try_normalize(1e-6).unwrap_or(Vec3::y())) * DETACH_SPEED;
+            info!("Grid {} lost {} cells, they drift away as grid {}", grid.index(), cells.len(), entity.index());
+            cmds.entity(entity)
+                .insert(StationMeshes::new(piece.map.voxel_size, true))
+                .insert(StationStructure::new(&mut piece.map))
+                .insert(piece_light)
+                .insert(piece)
+                .insert(GridVelocity {
+                    linear : velocity.map_or(Vec3::zeros(), |v| v.linear) + drift,
+                    angular : velocity.map_or(Vec3::zeros(), |v| v.angular)
+                });
+        }
+    }
+}
+
+pub fn structure_menu(
+    ctx : Res<EguiContext>,
+    mut settings : ResMut<StructureSettings>,
+    grids : Query<&StationStructure>) {
+
+    egui::Window::new("Structure").show(&ctx, |ui| {
+        egui::ComboBox::from_label("Cut off pieces")
+            .selected_text(format!("{:?}", settings.action))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut settings.action, FloatingAction::Detach, "Detach");
+                ui.selectable_value(&mut settings.action, FloatingAction::Warn, "Warn");
+            });
+        let floating : usize = grids.iter().map(|s| s.floating_cells().count()).sum();
+        ui.label(format!("Floating blocks: {}", floating));
+        if ui.add_enabled(floating > 0, egui::Button::new("Detach floating")).clicked() {
+            settings.detach_now = true;
+        }
+    });
+}
+
+pub fn floating_overlay(
+    ctx : Res<EguiContext>,
+    camera : Res<Camera>,
+    screen_size : Res<ScreenSize>,
+    grids : Query<(&Station, &StationStructure)>) {
+
+    let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Foreground, egui::Id::new("Floating blocks")));
+    let size = space_core::nalgebra::Point2::new(screen_size.size.width as f32, screen_size.size.height as f32);
+    let pixels_per_point = ctx.pixels_per_point();
+
+    for (station, structure) in grids.iter() {
+        let half_voxel = Vec3::repeat(station.map.voxel_size / 2.0);
+        for pos in structure.floating_cells().take(MAX_HIGHLIGHTED) {
+            //blocks are drawn from voxel corner
+            let world = station.transform.to_world(&(station.map.get_world_pos(pos) + half_voxel));
+            let Some(screen) = camera.world_to_screen(&world, size) else {
+                continue;
+            };
+            painter.circle_stroke(
+                egui::pos2(screen.x / pixels_per_point, screen.y / pixels_per_point),
+                4.0,
+                egui::Stroke::new(2.0, egui::Color32::RED));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //two towers joined by a bridge at y = 4
+    fn towers() -> Station {
+        let mut station = Station::default();
+        for y in 0..5 {
+            station.map.set_voxel(&Pos3i::new(0, y, 0), StationBlock::Voxel(VoxelId(1)));
+            station.map.set_voxel(&Pos3i::new(4, y, 0), StationBlock::Voxel(VoxelId(1)));
+        }
+        station.map.set_voxel(&Pos3i::new(0, 5, 0), StationBlock::Voxel(VoxelId(1)));
+        for x in 1..4 {
+            station.map.set_voxel(&Pos3i::new(x, 4, 0), StationBlock::Voxel(VoxelId(1)));
+        }
+        station
+    }
+
+    #[test]
+    fn cut_bridge_leaves_floating_tower() {
+        let mut station = towers();
+        let mut structure = StationStructure::new(&mut station.map);
+        assert_eq!(structure.components.component_count(), 1);
+
+        //separate new block is not cut off
+        station.map.set_voxel(&Pos3i::new(10, 0, 0), StationBlock::Voxel(VoxelId(1)));
+        assert!(structure.update(&mut station.map).is_empty());
+
+        station.map.set_voxel(&Pos3i::new(3, 4, 0), StationBlock::None);
+        let cut = structure.update(&mut station.map);
+        assert_eq!(cut.len(), 1);
+        let cells = structure.components.cells(cut[0]).unwrap();
+        assert_eq!(cells.len(), 5);
+        assert!(cells.contains(&Pos3i::new(4, 0, 0)));
+
+        //removing a block of separate structure does not cut it off
+        station.map.set_voxel(&Pos3i::new(11, 0, 0), StationBlock::Voxel(VoxelId(1)));
+        station.map.set_voxel(&Pos3i::new(12, 0, 0), StationBlock::Voxel(VoxelId(1)));
+        assert!(structure.update(&mut station.map).is_empty());
+        station.map.set_voxel(&Pos3i::new(11, 0, 0), StationBlock::None);
+        assert!(structure.update(&mut station.map).is_empty());
+
+        //building the bridge back reconnects the tower
+        structure.floating = cut;
+        station.map.set_voxel(&Pos3i::new(3, 4, 0), StationBlock::Voxel(VoxelId(1)));
+        assert!(structure.update(&mut station.map).is_empty());
+        assert!(structure.floating.is_empty());
+    }
+
+    #[test]
+    fn separate_larger_structure_does_not_take_core() {
+        let mut station = towers();
+        let mut structure = StationStructure::new(&mut station.map);
+        let core = structure.core();
+
+        //never attached slab, bigger than the station
+        for x in 20..40 {
+            station.map.set_voxel(&Pos3i::new(x, 0, 0), StationBlock::Voxel(VoxelId(1)));
+        }
+        assert!(structure.update(&mut station.map).is_empty());
+        assert_eq!(structure.core(), core);
+
+        //cutting the bridge only detaches the smaller tower
+        station.map.set_voxel(&Pos3i::new(2, 4, 0), StationBlock::None);
+        let cut = structure.update(&mut station.map);
+        assert_eq!(cut.len(), 1);
+        let cells = structure.components.cells(cut[0]).unwrap();
+        assert!(cells.contains(&Pos3i::new(4, 0, 0)));
+        assert!(structure.components.cells(structure.core().unwrap()).unwrap().contains(&Pos3i::new(0, 0, 0)));
+    }
+
+    #[test]
+    fn detached_piece_keeps_cells() {
+        let mut station = towers();
+        let mut structure = StationStructure::new(&mut station.map);
+        station.map.set_voxel(&Pos3i::new(3, 4, 0), StationBlock::None);
+        let cut = structure.update(&mut station.map);
+
+        let cells : Vec<Pos3i> = structure.components.cells(cut[0]).unwrap().iter().cloned().collect();
+        let mut piece = Station::new(station.transform);
+        move_cells(&mut station, &mut piece, &cells);
+        assert_eq!(piece.map.get_voxel(&Pos3i::new(4, 2, 0)), Some(&StationBlock::Voxel(VoxelId(1))));
+        assert_eq!(station.map.get_voxel(&Pos3i::new(4, 2, 0)), Some(&StationBlock::None));
+
+        //removing the piece does not cut anything else
+        assert!(structure.update(&mut station.map).is_empty());
+        assert_eq!(structure.components.component_count(), 1);
+        assert_eq!(StationStructure::new(&mut piece.map).components.component_count(), 1);
+    }
+}